@@ -0,0 +1,42 @@
+// Release notes, one "## <version>" section per release, newest first
+pub const CHANGELOG: &str = include_str!("../CHANGELOG.md");
+
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .trim()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Returns the sections for the releases newer than `last_seen_version`,
+/// or only the latest one when we don't know what was seen before.
+pub fn get_release_notes(changelog: &str, last_seen_version: Option<&str>) -> String {
+    let last_seen_version = last_seen_version.map(parse_version);
+    let mut notes = Vec::new();
+    for section in changelog.split("\n## ").skip(1) {
+        let version = section.lines().next().unwrap_or_default();
+        match &last_seen_version {
+            Some(last_seen_version) if parse_version(version) <= *last_seen_version => break,
+            None if !notes.is_empty() => break,
+            _ => notes.push(format!("## {}", section.trim_end())),
+        }
+    }
+    notes.join("\n\n")
+}
+
+#[test]
+fn test_release_notes_since_last_seen_version() {
+    let changelog =
+        "# Có gì mới\n\n## 0.3.0\n\n- Mới\n\n## 0.2.10\n\n- Sửa lỗi\n\n## 0.2.9\n\n- Cũ\n";
+    assert_eq!(
+        get_release_notes(changelog, Some("0.2.9")),
+        "## 0.3.0\n\n- Mới\n\n## 0.2.10\n\n- Sửa lỗi"
+    );
+    assert_eq!(
+        get_release_notes(changelog, Some("0.2.10")),
+        "## 0.3.0\n\n- Mới"
+    );
+    assert_eq!(get_release_notes(changelog, Some("0.3.0")), "");
+    assert_eq!(get_release_notes(changelog, None), "## 0.3.0\n\n- Mới");
+}
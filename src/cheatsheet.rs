@@ -0,0 +1,123 @@
+//! The key rules shown in the "Hướng dẫn gõ" window. They are found by typing
+//! each key after a few letters with the engine, so the window shows what
+//! vi-rs actually does for the chosen method.
+
+use crate::input::TypingMethod;
+
+/// A single row of the cheat-sheet: the keys to press and what they do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleDescription {
+    pub keys: String,
+    pub description: String,
+}
+
+const PROBED_KEYS: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+// The sample is what the key does after "a", tutorial.rs looks for it
+const TONES: [(&str, &str); 5] = [
+    ("á", "dấu sắc (á)"),
+    ("à", "dấu huyền (à)"),
+    ("ả", "dấu hỏi (ả)"),
+    ("ã", "dấu ngã (ã)"),
+    ("ạ", "dấu nặng (ạ)"),
+];
+const LETTER_MODIFICATIONS: [(&str, &str); 7] = [
+    ("a", "â"),
+    ("e", "ê"),
+    ("o", "ô"),
+    ("u", "ư"),
+    ("o", "ơ"),
+    ("a", "ă"),
+    ("d", "đ"),
+];
+
+fn transform(method: TypingMethod, text: &str) -> String {
+    let transform_buffer = match method {
+        TypingMethod::VNI => vi::vni::transform_buffer,
+        TypingMethod::Telex => vi::telex::transform_buffer,
+    };
+    let mut output = String::new();
+    transform_buffer(text.chars(), &mut output);
+    output
+}
+
+// What the key does, and where its row goes: tones first, then removing the
+// tone, then the letter modifications
+fn describe_key(method: TypingMethod, key: char, toned: &str) -> Option<(usize, String)> {
+    let typed = |text: &str| transform(method, &format!("{}{}", text, key));
+    if let Some(index) = TONES.iter().position(|(sample, _)| typed("a") == *sample) {
+        return Some((index, TONES[index].1.to_string()));
+    }
+    if !toned.is_empty() && typed(toned) == "a" {
+        return Some((TONES.len(), "xoá dấu thanh".to_string()));
+    }
+    let modifications = LETTER_MODIFICATIONS
+        .iter()
+        .enumerate()
+        .filter(|(_, (from, to))| typed(from) == *to)
+        .collect::<Vec<_>>();
+    let (first, _) = modifications.first()?;
+    let mut descriptions = modifications
+        .iter()
+        .map(|(_, (from, to))| format!("{} → {}", from, to))
+        .collect::<Vec<_>>();
+    if typed("") == "ư" {
+        descriptions.push("gõ ư".to_string());
+    }
+    Some((TONES.len() + 1 + first, descriptions.join(", ")))
+}
+
+/// The rules of the typing method, one row per key.
+pub fn describe_method(method: TypingMethod) -> Vec<RuleDescription> {
+    // A word with a tone, to find the key that removes it
+    let toned = PROBED_KEYS
+        .chars()
+        .map(|key| format!("a{}", key))
+        .find(|text| transform(method, text) == TONES[0].0)
+        .unwrap_or_default();
+    let mut rules = PROBED_KEYS
+        .chars()
+        .filter_map(|key| {
+            describe_key(method, key, &toned).map(|(order, description)| {
+                (
+                    order,
+                    RuleDescription {
+                        keys: key.to_string(),
+                        description,
+                    },
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    rules.sort_by_key(|(order, _)| *order);
+    rules.into_iter().map(|(_, rule)| rule).collect()
+}
+
+#[test]
+fn test_describe_method() {
+    let telex = describe_method(TypingMethod::Telex);
+    let keys = telex
+        .iter()
+        .map(|rule| rule.keys.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        ["s", "f", "r", "x", "j", "z", "a", "e", "o", "w", "d"]
+    );
+    assert_eq!(
+        telex[0],
+        RuleDescription {
+            keys: "s".to_string(),
+            description: "dấu sắc (á)".to_string(),
+        }
+    );
+    assert_eq!(telex[5].description, "xoá dấu thanh");
+    assert_eq!(telex[9].description, "u → ư, o → ơ, a → ă, gõ ư");
+
+    let vni = describe_method(TypingMethod::VNI);
+    let keys = vni
+        .iter()
+        .map(|rule| rule.keys.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["1", "2", "3", "4", "5", "0", "6", "7", "8", "9"]);
+    assert_eq!(vni[6].description, "a → â, e → ê, o → ô");
+}
@@ -0,0 +1,274 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::platform::{FocusedField, SelectionSource};
+
+// Text fields that should never be transformed, as (role, subrole, content type).
+// An empty subrole or content type matches anything.
+const PASS_THROUGH_FIELDS: &[(&str, &str, &str)] = &[
+    ("AXTextField", "", "URL"),
+    ("AXTextField", "", "emailAddress"),
+    ("AXTextField", "", "telephoneNumber"),
+    ("AXTextField", "", "creditCardNumber"),
+    ("AXTextField", "", "oneTimeCode"),
+    ("AXTextField", "AXSecureTextField", ""),
+];
+
+/// Returns true if the keys typed into this field should be passed
+/// through as is, like URL, email or number inputs.
+pub fn should_pass_through(field: &FocusedField) -> bool {
+    PASS_THROUGH_FIELDS
+        .iter()
+        .any(|&(role, subrole, content_type)| {
+            field.role == role
+                && (subrole.is_empty() || field.subrole == subrole)
+                && (content_type.is_empty() || field.content_type == content_type)
+        })
+}
+
+// Editors that insert the closing character when an opening one is typed.
+// VS Code and its forks are not here, see get_auto_inserted_suffix
+const AUTO_PAIR_APPS: &[&str] = &[
+    "Zed.app",
+    "Sublime Text.app",
+    "IntelliJ IDEA",
+    "PyCharm",
+    "WebStorm",
+];
+const AUTO_PAIR_CLOSERS: &[char] = &[')', ']', '}', '"', '\'', '`'];
+
+pub fn is_auto_pair_app(app: &str) -> bool {
+    AUTO_PAIR_APPS.iter().any(|name| app.contains(name))
+}
+
+/// Returns the closing characters an editor inserted between the word we
+/// typed and the caret. These have to be deleted and typed again along with
+/// the word, or the backspaces would stop short of the word.
+///
+/// VS Code keeps the caret between the pair, so there is nothing to do there
+/// and it is not in AUTO_PAIR_APPS.
+pub fn get_auto_inserted_suffix<'a>(text_before_caret: &'a str, word: &str) -> &'a str {
+    if word.is_empty() {
+        return "";
+    }
+    let trimmed = text_before_caret.trim_end_matches(AUTO_PAIR_CLOSERS);
+    if !trimmed.ends_with(word) {
+        return "";
+    }
+    &text_before_caret[trimmed.len()..]
+}
+
+// Characters before the caret looked at to tell the script of the document
+pub const SCRIPT_CONTEXT_LENGTH: usize = 16;
+
+// Chinese, Japanese, Korean, Hebrew and Arabic, with their punctuation
+const FOREIGN_SCRIPT_RANGES: &[(char, char)] = &[
+    ('\u{0590}', '\u{08FF}'),
+    ('\u{1100}', '\u{11FF}'),
+    ('\u{3000}', '\u{30FF}'),
+    ('\u{3130}', '\u{318F}'),
+    ('\u{3400}', '\u{4DBF}'),
+    ('\u{4E00}', '\u{9FFF}'),
+    ('\u{AC00}', '\u{D7AF}'),
+    ('\u{F900}', '\u{FAFF}'),
+    ('\u{FB1D}', '\u{FEFF}'),
+    ('\u{FF00}', '\u{FFEF}'),
+];
+
+fn is_foreign_script(c: char) -> bool {
+    FOREIGN_SCRIPT_RANGES
+        .iter()
+        .any(|&(start, end)| (start..=end).contains(&c))
+}
+
+/// Returns true when the text before the caret is written in a CJK or RTL
+/// script only. Any Latin letter means the languages are mixed, and the
+/// word may well be Vietnamese.
+pub fn is_foreign_script_context(text_before_caret: &str) -> bool {
+    let mut has_foreign_script = false;
+    for c in text_before_caret.chars() {
+        if is_foreign_script(c) {
+            has_foreign_script = true;
+        } else if c.is_alphabetic() {
+            return false;
+        }
+    }
+    has_foreign_script
+}
+
+// Firefox doesn't expose the URL bar selection to the Accessibility API,
+// it gets dismissed by typing a space and deleting it instead
+pub fn needs_selection_dismissal(app: &str) -> bool {
+    app.contains("Firefox")
+}
+
+// Apps that keep autocompleted text selected without exposing it to the
+// Accessibility API at all
+const SELECTION_ASSUMED_APPS: &[&str] = &["Spotlight"];
+
+/// The last step of the text selection check, when the Accessibility API
+/// had no answer.
+pub fn assume_text_selection(app: &str) -> bool {
+    SELECTION_ASSUMED_APPS.iter().any(|name| app.contains(name))
+}
+
+/// Whether any of the workarounds above applies to the app.
+pub fn has_workarounds(app: &str) -> bool {
+    is_auto_pair_app(app) || needs_selection_dismissal(app) || assume_text_selection(app)
+}
+
+/// Counts which step of the text selection check answered in each app, so
+/// apps where the Accessibility API falls short show up in diagnostics.
+pub struct SelectionMetrics {
+    counts: BTreeMap<String, [u32; 3]>,
+}
+
+impl SelectionMetrics {
+    pub const fn new() -> Self {
+        Self {
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, app: &str, source: SelectionSource) {
+        let counts = self.counts.entry(app.to_string()).or_default();
+        counts[source as usize] += 1;
+    }
+
+    /// Counts per app for the selected text, selected range and heuristic steps.
+    pub fn get_counts(&self) -> &BTreeMap<String, [u32; 3]> {
+        &self.counts
+    }
+}
+
+// Average time to process a key above which typing starts to feel laggy
+const LATENCY_WARNING_THRESHOLD: Duration = Duration::from_millis(25);
+const LATENCY_SAMPLE_SIZE: u32 = 30;
+
+/// Measures how long each key takes to process in the active app, mostly
+/// spent in the Accessibility API checks, which are slow in some apps.
+pub struct LatencyTracker {
+    total: Duration,
+    samples: u32,
+    warned_apps: Vec<String>,
+}
+
+impl LatencyTracker {
+    pub const fn new() -> Self {
+        Self {
+            total: Duration::ZERO,
+            samples: 0,
+            warned_apps: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.total = Duration::ZERO;
+        self.samples = 0;
+    }
+
+    /// Returns true only the first time the average latency in this app
+    /// goes over the budget, so the user is not asked again and again.
+    pub fn record(&mut self, app: &str, elapsed: Duration) -> bool {
+        self.total += elapsed;
+        self.samples += 1;
+        if self.samples < LATENCY_SAMPLE_SIZE {
+            return false;
+        }
+        let average = self.total / self.samples;
+        self.reset();
+        if average < LATENCY_WARNING_THRESHOLD || self.warned_apps.iter().any(|a| a == app) {
+            return false;
+        }
+        self.warned_apps.push(app.to_string());
+        true
+    }
+}
+
+#[test]
+fn test_pass_through_special_fields() {
+    let field = |role: &str, subrole: &str, content_type: &str| FocusedField {
+        role: role.to_string(),
+        subrole: subrole.to_string(),
+        content_type: content_type.to_string(),
+    };
+    assert!(should_pass_through(&field("AXTextField", "", "URL")));
+    assert!(should_pass_through(&field(
+        "AXTextField",
+        "",
+        "emailAddress"
+    )));
+    assert!(should_pass_through(&field(
+        "AXTextField",
+        "AXSecureTextField",
+        ""
+    )));
+    assert!(!should_pass_through(&field("AXTextField", "", "")));
+    assert!(!should_pass_through(&field("AXTextArea", "", "URL")));
+    assert!(!should_pass_through(&FocusedField::default()));
+}
+
+#[test]
+fn test_latency_warning_only_once_per_app() {
+    let mut tracker = LatencyTracker::new();
+    for _ in 0..LATENCY_SAMPLE_SIZE - 1 {
+        assert!(!tracker.record("Slow.app", Duration::from_millis(40)));
+    }
+    assert!(tracker.record("Slow.app", Duration::from_millis(40)));
+    for _ in 0..LATENCY_SAMPLE_SIZE {
+        assert!(!tracker.record("Slow.app", Duration::from_millis(40)));
+    }
+}
+
+#[test]
+fn test_no_latency_warning_when_fast() {
+    let mut tracker = LatencyTracker::new();
+    for _ in 0..LATENCY_SAMPLE_SIZE * 2 {
+        assert!(!tracker.record("Fast.app", Duration::from_millis(2)));
+    }
+}
+
+#[test]
+fn test_auto_inserted_suffix() {
+    // VS Code: the caret stays between the brackets
+    assert_eq!(get_auto_inserted_suffix("foo(vie", "vie"), "");
+    // The caret was moved after the closing characters
+    assert_eq!(get_auto_inserted_suffix("(vie)", "vie"), ")");
+    assert_eq!(get_auto_inserted_suffix("[\"vie\"]", "vie"), "\"]");
+    // Text we know nothing about is left alone
+    assert_eq!(get_auto_inserted_suffix("(other)", "vie"), "");
+    assert_eq!(get_auto_inserted_suffix("()", ""), "");
+}
+
+#[test]
+fn test_auto_pair_apps() {
+    assert!(is_auto_pair_app("/Applications/Zed.app"));
+    assert!(!is_auto_pair_app("/Applications/Visual Studio Code.app"));
+    assert!(!is_auto_pair_app("/Applications/Safari.app"));
+}
+
+#[test]
+fn test_selection_metrics_per_app() {
+    let mut metrics = SelectionMetrics::new();
+    metrics.record("Safari.app", SelectionSource::SelectedText);
+    metrics.record("Sandboxed.app", SelectionSource::SelectedRange);
+    metrics.record("Sandboxed.app", SelectionSource::Heuristic);
+    metrics.record("Sandboxed.app", SelectionSource::Heuristic);
+    assert_eq!(metrics.get_counts()["Safari.app"], [1, 0, 0]);
+    assert_eq!(metrics.get_counts()["Sandboxed.app"], [0, 1, 2]);
+    assert!(assume_text_selection(
+        "/System/Library/CoreServices/Spotlight.app"
+    ));
+    assert!(!assume_text_selection("/Applications/Safari.app"));
+}
+
+#[test]
+fn test_foreign_script_context() {
+    assert!(is_foreign_script_context("你好，"));
+    assert!(is_foreign_script_context("こんにちは "));
+    assert!(is_foreign_script_context("안녕하세요 "));
+    assert!(is_foreign_script_context("مرحبا "));
+    assert!(!is_foreign_script_context("你好 xin chào "));
+    assert!(!is_foreign_script_context("Xin chào "));
+    assert!(!is_foreign_script_context("123 "));
+    assert!(!is_foreign_script_context(""));
+}
@@ -4,26 +4,83 @@ use std::{
     fs::File,
     io,
     io::{Result, Write},
-    path::PathBuf,
-    sync::Mutex,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
 };
 
+use log::warn;
 use once_cell::sync::Lazy;
 
-use crate::platform::get_home_dir;
+use crate::platform::{get_home_dir, get_managed_config, set_owner_only_permissions};
 
 pub static CONFIG_MANAGER: Lazy<Mutex<ConfigStore>> = Lazy::new(|| Mutex::new(ConfigStore::new()));
 
+// The tests never read or write the user's config
+static IS_IN_MEMORY: AtomicBool = AtomicBool::new(cfg!(test));
+
+// Bumped when an existing key changes meaning, files without a version are
+// from before it was written. See DEVELOPMENT.md for the compatibility rules.
+const CONFIG_SCHEMA_VERSION: u32 = 1;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AppPolicy {
+    Vietnamese,
+    English,
+    Default,
+    Ignored,
+}
+
 pub struct ConfigStore {
     hotkey: String,
+    globe_key_behavior: String,
+    macro_conflict_behavior: String,
+    macro_expand_hotkey: String,
+    transform_word_hotkey: String,
+    transform_selection_hotkey: String,
     method: String,
+    locale: String,
     vn_apps: Vec<String>,
     en_apps: Vec<String>,
+    ignored_apps: Vec<String>,
+    fast_mode_apps: Vec<String>,
+    alt_passthrough_apps: Vec<String>,
+    // Apps added to the lists by switching the input mode in them
+    learned_apps: Vec<String>,
     is_macro_enabled: bool,
     macro_table: BTreeMap<String, String>,
+    // Triggers of the macros whose placeholders are expanded, see template.rs
+    template_macros: Vec<String>,
     is_auto_toggle_enabled: bool,
+    unknown_app_mode: String,
+    is_auto_learn_enabled: bool,
     is_gox_mode_enabled: bool,
+    is_low_power_enabled: bool,
+    is_tray_enabled: bool,
+    is_debug_indicator_enabled: bool,
     allowed_words: Vec<String>,
+    spelling_rules: Vec<String>,
+    // None until the user changes a feature, so new defaults still apply
+    engine_features: Option<Vec<String>>,
+    last_seen_version: Option<String>,
+    is_unsigned_content_allowed: bool,
+    is_settings_server_enabled: bool,
+    settings_server_port: Option<u16>,
+    settings_server_token: String,
+    trace_stages: Vec<String>,
+    trace_app: Option<String>,
+    locked_keys: Vec<String>,
+    // The user's own lines for the managed keys
+    overridden_lines: Vec<String>,
+    is_first_run: bool,
+    // When the file was last read or written by us, to notice edits by hand
+    modified_time: Option<SystemTime>,
+    is_in_memory: bool,
+    // Lines this version doesn't understand, written back as they were
+    unknown_lines: Vec<String>,
 }
 
 fn parse_vec_string(line: String) -> Vec<String> {
@@ -42,6 +99,32 @@ fn parse_kv_string(line: &str) -> Option<(String, String)> {
     return None;
 }
 
+fn read_config_lines(path: &Path) -> Vec<String> {
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    io::BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .collect()
+}
+
+fn read_config_entries(path: &Path) -> Vec<(String, String)> {
+    read_config_lines(path)
+        .iter()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" = ")?;
+            Some((left.to_string(), right.to_string()))
+        })
+        .collect()
+}
+
+fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
 fn build_kv_string(k: &str, v: &str) -> String {
     format!(
         "\"{}\"=\"{}\"",
@@ -50,8 +133,14 @@ fn build_kv_string(k: &str, v: &str) -> String {
     )
 }
 
+/// Keeps the defaults and never touches ~/.goxkey, for replaying traces the
+/// same way on every machine. Must be called before the config is first used.
+pub fn use_in_memory_config() {
+    IS_IN_MEMORY.store(true, Ordering::Relaxed);
+}
+
 impl ConfigStore {
-    fn get_config_path() -> PathBuf {
+    pub fn get_config_path() -> PathBuf {
         get_home_dir()
             .expect("Cannot read home directory!")
             .join(".goxkey")
@@ -59,98 +148,426 @@ impl ConfigStore {
 
     fn write_config_data(&mut self) -> Result<()> {
         let mut file = File::create(ConfigStore::get_config_path())?;
+        // The file holds the settings server token
+        set_owner_only_permissions(&file)?;
+        self.write_config(&mut file)?;
+        self.modified_time = file.metadata()?.modified().ok();
+        Ok(())
+    }
+
+    fn write_config(&self, file: &mut impl Write) -> Result<()> {
+        let mut data = Vec::new();
 
-        writeln!(file, "{} = {}", HOTKEY_CONFIG_KEY, self.hotkey)?;
-        writeln!(file, "{} = {}", TYPING_METHOD_CONFIG_KEY, self.method)?;
-        writeln!(file, "{} = {}", VN_APPS_CONFIG_KEY, self.vn_apps.join(","))?;
-        writeln!(file, "{} = {}", EN_APPS_CONFIG_KEY, self.en_apps.join(","))?;
         writeln!(
-            file,
+            data,
+            "{} = {}",
+            SCHEMA_VERSION_CONFIG_KEY, CONFIG_SCHEMA_VERSION
+        )?;
+        writeln!(data, "{} = {}", HOTKEY_CONFIG_KEY, self.hotkey)?;
+        writeln!(
+            data,
+            "{} = {}",
+            GLOBE_KEY_BEHAVIOR_CONFIG_KEY, self.globe_key_behavior
+        )?;
+        writeln!(data, "{} = {}", TYPING_METHOD_CONFIG_KEY, self.method)?;
+        writeln!(data, "{} = {}", LOCALE_CONFIG_KEY, self.locale)?;
+        writeln!(data, "{} = {}", VN_APPS_CONFIG_KEY, self.vn_apps.join(","))?;
+        writeln!(data, "{} = {}", EN_APPS_CONFIG_KEY, self.en_apps.join(","))?;
+        writeln!(
+            data,
+            "{} = {}",
+            IGNORED_APPS_CONFIG_KEY,
+            self.ignored_apps.join(",")
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            FAST_MODE_APPS_CONFIG_KEY,
+            self.fast_mode_apps.join(",")
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            ALT_PASSTHROUGH_APPS_CONFIG_KEY,
+            self.alt_passthrough_apps.join(",")
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            LEARNED_APPS_CONFIG_KEY,
+            self.learned_apps.join(",")
+        )?;
+        writeln!(
+            data,
             "{} = {}",
             ALLOWED_WORDS_CONFIG_KEY,
             self.allowed_words.join(",")
         )?;
         writeln!(
-            file,
+            data,
+            "{} = {}",
+            SPELLING_RULES_CONFIG_KEY,
+            self.spelling_rules.join(",")
+        )?;
+        writeln!(
+            data,
             "{} = {}",
             AUTOS_TOGGLE_ENABLED_CONFIG_KEY, self.is_auto_toggle_enabled
         )?;
         writeln!(
-            file,
+            data,
+            "{} = {}",
+            UNKNOWN_APP_MODE_CONFIG_KEY, self.unknown_app_mode
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            AUTO_LEARN_APPS_CONFIG_KEY, self.is_auto_learn_enabled
+        )?;
+        writeln!(
+            data,
             "{} = {}",
             MACRO_ENABLED_CONFIG_KEY, self.is_macro_enabled
         )?;
+        writeln!(
+            data,
+            "{} = {}",
+            MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY, self.macro_conflict_behavior
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            MACRO_EXPAND_HOTKEY_CONFIG_KEY, self.macro_expand_hotkey
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            TRANSFORM_WORD_HOTKEY_CONFIG_KEY, self.transform_word_hotkey
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            TRANSFORM_SELECTION_HOTKEY_CONFIG_KEY, self.transform_selection_hotkey
+        )?;
         for (k, v) in self.macro_table.iter() {
-            writeln!(file, "{} = {}", MACROS_CONFIG_KEY, build_kv_string(k, &v))?;
+            writeln!(data, "{} = {}", MACROS_CONFIG_KEY, build_kv_string(k, &v))?;
         }
         writeln!(
-            file,
+            data,
+            "{} = {}",
+            TEMPLATE_MACROS_CONFIG_KEY,
+            self.template_macros.join(",")
+        )?;
+        writeln!(
+            data,
             "{} = {}",
             GOX_MODE_CONFIG_KEY, self.is_gox_mode_enabled
         )?;
+        writeln!(
+            data,
+            "{} = {}",
+            LOW_POWER_ENABLED_CONFIG_KEY, self.is_low_power_enabled
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            TRAY_ENABLED_CONFIG_KEY, self.is_tray_enabled
+        )?;
+        writeln!(
+            data,
+            "{} = {}",
+            DEBUG_INDICATOR_CONFIG_KEY, self.is_debug_indicator_enabled
+        )?;
+        if let Some(engine_features) = &self.engine_features {
+            writeln!(
+                data,
+                "{} = {}",
+                ENGINE_FEATURES_CONFIG_KEY,
+                engine_features.join(",")
+            )?;
+        }
+        // Only written when set, it has to be turned on by hand
+        if self.is_unsigned_content_allowed {
+            writeln!(data, "{} = true", ALLOW_UNSIGNED_CONTENT_CONFIG_KEY)?;
+        }
+        if self.is_settings_server_enabled {
+            writeln!(data, "{} = true", SETTINGS_SERVER_CONFIG_KEY)?;
+        }
+        if let Some(port) = self.settings_server_port {
+            writeln!(data, "{} = {}", SETTINGS_SERVER_PORT_CONFIG_KEY, port)?;
+        }
+        if !self.settings_server_token.is_empty() {
+            writeln!(
+                data,
+                "{} = {}",
+                SETTINGS_SERVER_TOKEN_CONFIG_KEY, self.settings_server_token
+            )?;
+        }
+        if !self.trace_stages.is_empty() {
+            writeln!(
+                data,
+                "{} = {}",
+                TRACE_STAGES_CONFIG_KEY,
+                self.trace_stages.join(",")
+            )?;
+        }
+        if let Some(app) = &self.trace_app {
+            writeln!(data, "{} = {}", TRACE_APP_CONFIG_KEY, app)?;
+        }
+        if let Some(version) = &self.last_seen_version {
+            writeln!(data, "{} = {}", LAST_SEEN_VERSION_CONFIG_KEY, version)?;
+        }
+        for line in &self.unknown_lines {
+            writeln!(data, "{}", line)?;
+        }
+
+        // Managed values stay out of the file, the user's own lines for those
+        // keys are written back instead for when the setting is unlocked
+        for line in String::from_utf8_lossy(&data).lines() {
+            let is_managed = line
+                .split_once(" = ")
+                .is_some_and(|(key, _)| self.is_locked(key));
+            if !is_managed {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        for line in &self.overridden_lines {
+            writeln!(file, "{}", line)?;
+        }
         Ok(())
     }
 
+    // Returns false for the keys this version doesn't know
+    fn apply_config_value(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            SCHEMA_VERSION_CONFIG_KEY => {
+                let version = value.trim().parse().unwrap_or(CONFIG_SCHEMA_VERSION);
+                if version > CONFIG_SCHEMA_VERSION {
+                    warn!(
+                        "The config file is from a newer version (schema {}), unknown settings are kept as is",
+                        version
+                    );
+                }
+            }
+            HOTKEY_CONFIG_KEY => self.hotkey = value.to_string(),
+            GLOBE_KEY_BEHAVIOR_CONFIG_KEY => self.globe_key_behavior = value.to_string(),
+            TYPING_METHOD_CONFIG_KEY => self.method = value.to_string(),
+            LOCALE_CONFIG_KEY => self.locale = value.to_string(),
+            VN_APPS_CONFIG_KEY => self.vn_apps = parse_vec_string(value.to_string()),
+            EN_APPS_CONFIG_KEY => self.en_apps = parse_vec_string(value.to_string()),
+            IGNORED_APPS_CONFIG_KEY => self.ignored_apps = parse_vec_string(value.to_string()),
+            FAST_MODE_APPS_CONFIG_KEY => self.fast_mode_apps = parse_vec_string(value.to_string()),
+            ALT_PASSTHROUGH_APPS_CONFIG_KEY => {
+                self.alt_passthrough_apps = parse_vec_string(value.to_string())
+            }
+            LEARNED_APPS_CONFIG_KEY => self.learned_apps = parse_vec_string(value.to_string()),
+            ALLOWED_WORDS_CONFIG_KEY => self.allowed_words = parse_vec_string(value.to_string()),
+            SPELLING_RULES_CONFIG_KEY => self.spelling_rules = parse_vec_string(value.to_string()),
+            AUTOS_TOGGLE_ENABLED_CONFIG_KEY => {
+                self.is_auto_toggle_enabled = matches!(value.trim(), "true")
+            }
+            UNKNOWN_APP_MODE_CONFIG_KEY => self.unknown_app_mode = value.trim().to_string(),
+            AUTO_LEARN_APPS_CONFIG_KEY => {
+                self.is_auto_learn_enabled = !matches!(value.trim(), "false")
+            }
+            MACRO_ENABLED_CONFIG_KEY => self.is_macro_enabled = matches!(value.trim(), "true"),
+            MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY => {
+                self.macro_conflict_behavior = value.trim().to_string()
+            }
+            MACRO_EXPAND_HOTKEY_CONFIG_KEY => self.macro_expand_hotkey = value.trim().to_string(),
+            TRANSFORM_WORD_HOTKEY_CONFIG_KEY => {
+                self.transform_word_hotkey = value.trim().to_string()
+            }
+            TRANSFORM_SELECTION_HOTKEY_CONFIG_KEY => {
+                self.transform_selection_hotkey = value.trim().to_string()
+            }
+            MACROS_CONFIG_KEY => {
+                if let Some((k, v)) = parse_kv_string(value) {
+                    self.macro_table.insert(k, v);
+                }
+            }
+            TEMPLATE_MACROS_CONFIG_KEY => {
+                self.template_macros = parse_vec_string(value.to_string())
+            }
+            GOX_MODE_CONFIG_KEY => self.is_gox_mode_enabled = matches!(value.trim(), "true"),
+            LOW_POWER_ENABLED_CONFIG_KEY => {
+                self.is_low_power_enabled = matches!(value.trim(), "true")
+            }
+            TRAY_ENABLED_CONFIG_KEY => self.is_tray_enabled = !matches!(value.trim(), "false"),
+            DEBUG_INDICATOR_CONFIG_KEY => {
+                self.is_debug_indicator_enabled = matches!(value.trim(), "true")
+            }
+            ENGINE_FEATURES_CONFIG_KEY => {
+                self.engine_features = Some(parse_vec_string(value.to_string()))
+            }
+            ALLOW_UNSIGNED_CONTENT_CONFIG_KEY => {
+                self.is_unsigned_content_allowed = matches!(value.trim(), "true")
+            }
+            SETTINGS_SERVER_CONFIG_KEY => {
+                self.is_settings_server_enabled = matches!(value.trim(), "true")
+            }
+            SETTINGS_SERVER_PORT_CONFIG_KEY => {
+                self.settings_server_port = value.trim().parse().ok()
+            }
+            SETTINGS_SERVER_TOKEN_CONFIG_KEY => {
+                self.settings_server_token = value.trim().to_string()
+            }
+            TRACE_STAGES_CONFIG_KEY => self.trace_stages = parse_vec_string(value.to_string()),
+            TRACE_APP_CONFIG_KEY => self.trace_app = Some(value.trim().to_string()),
+            LAST_SEEN_VERSION_CONFIG_KEY => self.last_seen_version = Some(value.trim().to_string()),
+            _ => return false,
+        }
+        true
+    }
+
     pub fn new() -> Self {
+        if IS_IN_MEMORY.load(Ordering::Relaxed) {
+            return ConfigStore::from_lines(Vec::new(), Vec::new());
+        }
+        let config_path = ConfigStore::get_config_path();
+        let managed_entries = get_managed_config()
+            .into_iter()
+            .chain(read_config_entries(Path::new(MANAGED_CONFIG_PATH)))
+            .collect();
+        let mut config = ConfigStore::from_lines(read_config_lines(&config_path), managed_entries);
+        config.is_first_run = !config_path.exists();
+        config.modified_time = get_modified_time(&config_path);
+        config
+    }
+
+    fn from_lines(lines: Vec<String>, managed_entries: Vec<(String, String)>) -> Self {
         let mut config = Self {
             hotkey: "ctrl+space".to_string(),
+            globe_key_behavior: "toggle".to_string(),
+            macro_conflict_behavior: "expand".to_string(),
+            macro_expand_hotkey: String::new(),
+            transform_word_hotkey: String::new(),
+            transform_selection_hotkey: String::new(),
             method: "telex".to_string(),
+            locale: "vi_VN".to_string(),
             vn_apps: Vec::new(),
             en_apps: Vec::new(),
+            ignored_apps: Vec::new(),
+            fast_mode_apps: Vec::new(),
+            alt_passthrough_apps: Vec::new(),
+            learned_apps: Vec::new(),
             is_macro_enabled: false,
             macro_table: BTreeMap::new(),
+            template_macros: Vec::new(),
             is_auto_toggle_enabled: false,
+            unknown_app_mode: "keep-current".to_string(),
+            is_auto_learn_enabled: true,
             is_gox_mode_enabled: false,
+            is_low_power_enabled: false,
+            is_debug_indicator_enabled: false,
+            is_tray_enabled: true,
             allowed_words: vec!["đc".to_string()],
+            spelling_rules: Vec::new(),
+            engine_features: None,
+            last_seen_version: None,
+            is_unsigned_content_allowed: false,
+            is_settings_server_enabled: false,
+            settings_server_port: None,
+            settings_server_token: String::new(),
+            trace_stages: Vec::new(),
+            trace_app: None,
+            locked_keys: Vec::new(),
+            overridden_lines: Vec::new(),
+            is_first_run: false,
+            modified_time: None,
+            is_in_memory: IS_IN_MEMORY.load(Ordering::Relaxed),
+            unknown_lines: Vec::new(),
         };
 
-        let config_path = ConfigStore::get_config_path();
+        for line in &lines {
+            let is_known = match line.split_once(" = ") {
+                Some((key, value)) => config.apply_config_value(key, value),
+                None => line.trim().is_empty(),
+            };
+            if !is_known {
+                config.unknown_lines.push(line.clone());
+            }
+        }
 
-        if let Ok(file) = File::open(config_path) {
-            let reader = io::BufReader::new(file);
-            for line in reader.lines() {
-                if let Some((left, right)) = line.unwrap_or_default().split_once(" = ") {
-                    match left {
-                        HOTKEY_CONFIG_KEY => config.hotkey = right.to_string(),
-                        TYPING_METHOD_CONFIG_KEY => config.method = right.to_string(),
-                        VN_APPS_CONFIG_KEY => config.vn_apps = parse_vec_string(right.to_string()),
-                        EN_APPS_CONFIG_KEY => config.en_apps = parse_vec_string(right.to_string()),
-                        ALLOWED_WORDS_CONFIG_KEY => {
-                            config.allowed_words = parse_vec_string(right.to_string())
-                        }
-                        AUTOS_TOGGLE_ENABLED_CONFIG_KEY => {
-                            config.is_auto_toggle_enabled = matches!(right.trim(), "true")
-                        }
-                        MACRO_ENABLED_CONFIG_KEY => {
-                            config.is_macro_enabled = matches!(right.trim(), "true")
-                        }
-                        MACROS_CONFIG_KEY => {
-                            if let Some((k, v)) = parse_kv_string(right) {
-                                config.macro_table.insert(k, v);
-                            }
-                        }
-                        GOX_MODE_CONFIG_KEY => {
-                            config.is_gox_mode_enabled = matches!(right.trim(), "true")
-                        }
-                        _ => {}
-                    }
-                }
+        // Managed settings are applied last so they win over the user's own,
+        // and they can't be changed from the app afterwards
+        for (key, value) in managed_entries {
+            config.apply_config_value(&key, &value);
+            if !config.locked_keys.contains(&key) {
+                config.locked_keys.push(key);
             }
         }
+        config.overridden_lines = lines
+            .into_iter()
+            .filter(|line| {
+                line.split_once(" = ")
+                    .is_some_and(|(key, _)| config.is_locked(key))
+            })
+            .collect();
 
         config
     }
 
+    // Settings set by an administrator, see MANAGED_CONFIG_PATH
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.locked_keys.iter().any(|locked_key| locked_key == key)
+    }
+
+    pub fn get_locked_keys(&self) -> &[String] {
+        &self.locked_keys
+    }
+
+    // The per-app policies are spread over several lists, locking any of
+    // them locks the policies
+    pub fn is_app_policy_locked(&self) -> bool {
+        [
+            VN_APPS_CONFIG_KEY,
+            EN_APPS_CONFIG_KEY,
+            IGNORED_APPS_CONFIG_KEY,
+            LEARNED_APPS_CONFIG_KEY,
+        ]
+        .iter()
+        .any(|key| self.is_locked(key))
+    }
+
+    // Only the defaults, nothing is read from or written to the home folder
+    pub fn is_in_memory(&self) -> bool {
+        self.is_in_memory
+    }
+
+    // Edited outside of the app since it was last read or written
+    pub fn is_changed_on_disk(&self) -> bool {
+        !self.is_in_memory
+            && get_modified_time(&ConfigStore::get_config_path()) != self.modified_time
+    }
+
+    // No config file yet, GõKey has never been launched before
+    pub fn is_first_run(&self) -> bool {
+        self.is_first_run
+    }
+
     // Hotkey
     pub fn get_hotkey(&self) -> &str {
         &self.hotkey
     }
 
     pub fn set_hotkey(&mut self, hotkey: &str) {
+        if self.is_locked(HOTKEY_CONFIG_KEY) {
+            return;
+        }
         self.hotkey = hotkey.to_string();
         self.save();
     }
 
+    pub fn get_globe_key_behavior(&self) -> &str {
+        &self.globe_key_behavior
+    }
+
+    pub fn set_globe_key_behavior(&mut self, behavior: &str) {
+        self.globe_key_behavior = behavior.to_string();
+        self.save();
+    }
+
     // Method
     pub fn get_method(&self) -> &str {
         &self.method
@@ -161,6 +578,16 @@ impl ConfigStore {
         self.save();
     }
 
+    // Locale
+    pub fn get_locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn set_locale(&mut self, locale: &str) {
+        self.locale = locale.to_string();
+        self.save();
+    }
+
     pub fn is_vietnamese_app(&self, app_name: &str) -> bool {
         self.vn_apps.contains(&app_name.to_string())
     }
@@ -169,26 +596,127 @@ impl ConfigStore {
         self.en_apps.contains(&app_name.to_string())
     }
 
-    pub fn add_vietnamese_app(&mut self, app_name: &str) {
-        if self.is_english_app(app_name) {
-            // Remove from english apps
-            self.en_apps.retain(|x| x != app_name);
+    pub fn is_ignored_app(&self, app_name: &str) -> bool {
+        self.ignored_apps.contains(&app_name.to_string())
+    }
+
+    // Apps where the Accessibility API checks are skipped to keep typing fast
+    pub fn is_fast_mode_app(&self, app_name: &str) -> bool {
+        self.fast_mode_apps.contains(&app_name.to_string())
+    }
+
+    pub fn add_fast_mode_app(&mut self, app_name: &str) {
+        if !self.is_fast_mode_app(app_name) && !self.is_locked(FAST_MODE_APPS_CONFIG_KEY) {
+            self.fast_mode_apps.push(app_name.to_string());
+            self.save();
+        }
+    }
+
+    pub fn remove_fast_mode_app(&mut self, app_name: &str) {
+        if self.is_fast_mode_app(app_name) && !self.is_locked(FAST_MODE_APPS_CONFIG_KEY) {
+            self.fast_mode_apps.retain(|app| app != app_name);
+            self.save();
+        }
+    }
+
+    // Apps where typing with Option held doesn't end the word, for the
+    // accents of other languages composed with Option dead keys
+    pub fn is_alt_passthrough_app(&self, app_name: &str) -> bool {
+        self.alt_passthrough_apps.contains(&app_name.to_string())
+    }
+
+    pub fn set_alt_passthrough_app(&mut self, app_name: &str, is_passthrough: bool) {
+        if self.is_locked(ALT_PASSTHROUGH_APPS_CONFIG_KEY) {
+            return;
+        }
+        self.alt_passthrough_apps.retain(|app| app != app_name);
+        if is_passthrough {
+            self.alt_passthrough_apps.push(app_name.to_string());
         }
-        self.vn_apps.push(app_name.to_string());
         self.save();
     }
 
+    pub fn add_vietnamese_app(&mut self, app_name: &str) {
+        self.learn_app_policy(app_name, AppPolicy::Vietnamese);
+    }
+
     pub fn add_english_app(&mut self, app_name: &str) {
-        if self.is_vietnamese_app(app_name) {
-            // Remove from vietnamese apps
-            self.vn_apps.retain(|x| x != app_name);
+        self.learn_app_policy(app_name, AppPolicy::English);
+    }
+
+    pub fn get_app_policy(&self, app_name: &str) -> AppPolicy {
+        if self.is_ignored_app(app_name) {
+            AppPolicy::Ignored
+        } else if self.is_english_app(app_name) {
+            AppPolicy::English
+        } else if self.is_vietnamese_app(app_name) {
+            AppPolicy::Vietnamese
+        } else {
+            AppPolicy::Default
+        }
+    }
+
+    pub fn set_app_policy(&mut self, app_name: &str, policy: AppPolicy) {
+        if self.is_app_policy_locked() {
+            return;
+        }
+        self.learned_apps.retain(|x| x != app_name);
+        self.update_app_lists(app_name, policy);
+        self.save();
+    }
+
+    // Apps the user added by hand stay that way when the mode is switched in them
+    fn learn_app_policy(&mut self, app_name: &str, policy: AppPolicy) {
+        if self.is_app_policy_locked() {
+            return;
         }
-        self.en_apps.push(app_name.to_string());
+        if self.get_app_policy(app_name) == AppPolicy::Default {
+            self.learned_apps.push(app_name.to_string());
+        }
+        self.update_app_lists(app_name, policy);
         self.save();
     }
 
-    pub fn is_allowed_word(&self, word: &str) -> bool {
-        self.allowed_words.contains(&word.to_string())
+    fn update_app_lists(&mut self, app_name: &str, policy: AppPolicy) {
+        // An app can only be in one of the lists at a time
+        self.vn_apps.retain(|x| x != app_name);
+        self.en_apps.retain(|x| x != app_name);
+        self.ignored_apps.retain(|x| x != app_name);
+        match policy {
+            AppPolicy::Vietnamese => self.vn_apps.push(app_name.to_string()),
+            AppPolicy::English => self.en_apps.push(app_name.to_string()),
+            AppPolicy::Ignored => self.ignored_apps.push(app_name.to_string()),
+            AppPolicy::Default => {}
+        }
+    }
+
+    pub fn is_learned_app(&self, app_name: &str) -> bool {
+        self.learned_apps.iter().any(|x| x == app_name)
+    }
+
+    pub fn get_vietnamese_apps(&self) -> &Vec<String> {
+        &self.vn_apps
+    }
+
+    pub fn get_english_apps(&self) -> &Vec<String> {
+        &self.en_apps
+    }
+
+    pub fn get_ignored_apps(&self) -> &Vec<String> {
+        &self.ignored_apps
+    }
+
+    pub fn remove_app(&mut self, app_name: &str) {
+        self.set_app_policy(app_name, AppPolicy::Default);
+    }
+
+    pub fn get_allowed_words(&self) -> &[String] {
+        &self.allowed_words
+    }
+
+    // Extra spelling corrections, written as from:to
+    pub fn get_spelling_rules(&self) -> &[String] {
+        &self.spelling_rules
     }
 
     pub fn is_auto_toggle_enabled(&self) -> bool {
@@ -200,15 +728,61 @@ impl ConfigStore {
         self.save();
     }
 
+    // The input mode for apps that are in none of the lists
+    pub fn get_unknown_app_mode(&self) -> &str {
+        &self.unknown_app_mode
+    }
+
+    pub fn set_unknown_app_mode(&mut self, mode: &str) {
+        self.unknown_app_mode = mode.to_string();
+        self.save();
+    }
+
+    // Whether switching the input mode adds the app to the lists
+    pub fn is_auto_learn_enabled(&self) -> bool {
+        self.is_auto_learn_enabled
+    }
+
+    pub fn set_auto_learn_enabled(&mut self, flag: bool) {
+        self.is_auto_learn_enabled = flag;
+        self.save();
+    }
+
     pub fn is_gox_mode_enabled(&self) -> bool {
         self.is_gox_mode_enabled
     }
 
     pub fn set_gox_mode_enabled(&mut self, flag: bool) {
+        if self.is_locked(GOX_MODE_CONFIG_KEY) {
+            return;
+        }
         self.is_gox_mode_enabled = flag;
         self.save();
     }
 
+    pub fn is_low_power_enabled(&self) -> bool {
+        self.is_low_power_enabled
+    }
+
+    pub fn set_low_power_enabled(&mut self, flag: bool) {
+        self.is_low_power_enabled = flag;
+        self.save();
+    }
+
+    pub fn is_debug_indicator_enabled(&self) -> bool {
+        self.is_debug_indicator_enabled
+    }
+
+    pub fn set_debug_indicator_enabled(&mut self, flag: bool) {
+        self.is_debug_indicator_enabled = flag;
+        self.save();
+    }
+
+    // Some managed Macs do not allow status bar items
+    pub fn is_tray_enabled(&self) -> bool {
+        self.is_tray_enabled
+    }
+
     pub fn is_macro_enabled(&self) -> bool {
         self.is_macro_enabled
     }
@@ -218,32 +792,256 @@ impl ConfigStore {
         self.save();
     }
 
+    pub fn get_engine_features(&self) -> Option<&[String]> {
+        self.engine_features.as_deref()
+    }
+
+    pub fn set_engine_features(&mut self, features: Vec<String>) {
+        self.engine_features = Some(features);
+        self.save();
+    }
+
+    // Set by hand in the config file, see security.rs
+    pub fn is_unsigned_content_allowed(&self) -> bool {
+        self.is_unsigned_content_allowed
+    }
+
+    // Set by hand in the config file, see settings_server.rs
+    pub fn is_settings_server_enabled(&self) -> bool {
+        self.is_settings_server_enabled
+    }
+
+    pub fn get_settings_server_port(&self) -> Option<u16> {
+        self.settings_server_port
+    }
+
+    // Empty until the settings server is first started
+    pub fn get_settings_server_token(&self) -> &str {
+        &self.settings_server_token
+    }
+
+    pub fn set_settings_server_token(&mut self, token: &str) {
+        self.settings_server_token = token.to_string();
+        self.save();
+    }
+
+    // Set by hand in the config file, see trace.rs
+    pub fn get_trace_stages(&self) -> &[String] {
+        &self.trace_stages
+    }
+
+    pub fn get_trace_app(&self) -> Option<&str> {
+        self.trace_app.as_deref()
+    }
+
+    // The version whose release notes were last shown
+    pub fn get_last_seen_version(&self) -> Option<&str> {
+        self.last_seen_version.as_deref()
+    }
+
+    pub fn set_last_seen_version(&mut self, version: &str) {
+        self.last_seen_version = Some(version.to_string());
+        self.save();
+    }
+
+    pub fn get_macro_conflict_behavior(&self) -> &str {
+        &self.macro_conflict_behavior
+    }
+
+    pub fn set_macro_conflict_behavior(&mut self, behavior: &str) {
+        self.macro_conflict_behavior = behavior.to_string();
+        self.save();
+    }
+
+    // Empty when no hotkey is set
+    pub fn get_macro_expand_hotkey(&self) -> &str {
+        &self.macro_expand_hotkey
+    }
+
+    pub fn set_macro_expand_hotkey(&mut self, hotkey: &str) {
+        self.macro_expand_hotkey = hotkey.to_string();
+        self.save();
+    }
+
+    // Empty when no hotkey is set
+    pub fn get_transform_word_hotkey(&self) -> &str {
+        &self.transform_word_hotkey
+    }
+
+    pub fn set_transform_word_hotkey(&mut self, hotkey: &str) {
+        self.transform_word_hotkey = hotkey.to_string();
+        self.save();
+    }
+
+    // Empty when no hotkey is set
+    pub fn get_transform_selection_hotkey(&self) -> &str {
+        &self.transform_selection_hotkey
+    }
+
+    pub fn set_transform_selection_hotkey(&mut self, hotkey: &str) {
+        self.transform_selection_hotkey = hotkey.to_string();
+        self.save();
+    }
+
     pub fn get_macro_table(&self) -> &BTreeMap<String, String> {
         &self.macro_table
     }
 
     pub fn add_macro(&mut self, from: String, to: String) {
+        if self.is_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
         self.macro_table.insert(from, to);
         self.save();
     }
 
     pub fn delete_macro(&mut self, from: &String) {
+        if self.is_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
         self.macro_table.remove(from);
+        self.template_macros.retain(|trigger| trigger != from);
+        self.save();
+    }
+
+    pub fn get_template_macros(&self) -> &Vec<String> {
+        &self.template_macros
+    }
+
+    pub fn set_template_macro(&mut self, from: &str, is_template: bool) {
+        if self.is_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
+        self.template_macros.retain(|trigger| trigger != from);
+        if is_template {
+            self.template_macros.push(from.to_string());
+        }
+        self.save();
+    }
+
+    // Make sure everything is written to disk, used before quitting
+    pub fn flush(&mut self) {
         self.save();
     }
 
     // Save config to file
     fn save(&mut self) {
+        if self.is_in_memory {
+            return;
+        }
         self.write_config_data().expect("Failed to write config");
     }
 }
 
-const HOTKEY_CONFIG_KEY: &str = "hotkey";
-const TYPING_METHOD_CONFIG_KEY: &str = "method";
+pub const HOTKEY_CONFIG_KEY: &str = "hotkey";
+pub const GLOBE_KEY_BEHAVIOR_CONFIG_KEY: &str = "globe_key_behavior";
+pub const TYPING_METHOD_CONFIG_KEY: &str = "method";
+pub const LOCALE_CONFIG_KEY: &str = "locale";
 const VN_APPS_CONFIG_KEY: &str = "vn-apps";
 const EN_APPS_CONFIG_KEY: &str = "en-apps";
-const MACRO_ENABLED_CONFIG_KEY: &str = "is_macro_enabled";
-const AUTOS_TOGGLE_ENABLED_CONFIG_KEY: &str = "is_auto_toggle_enabled";
-const MACROS_CONFIG_KEY: &str = "macros";
+const IGNORED_APPS_CONFIG_KEY: &str = "ignored-apps";
+const FAST_MODE_APPS_CONFIG_KEY: &str = "fast-mode-apps";
+const ALT_PASSTHROUGH_APPS_CONFIG_KEY: &str = "alt-passthrough-apps";
+const LEARNED_APPS_CONFIG_KEY: &str = "learned-apps";
+pub const MACRO_ENABLED_CONFIG_KEY: &str = "is_macro_enabled";
+pub const AUTOS_TOGGLE_ENABLED_CONFIG_KEY: &str = "is_auto_toggle_enabled";
+pub const UNKNOWN_APP_MODE_CONFIG_KEY: &str = "unknown-app-mode";
+pub const AUTO_LEARN_APPS_CONFIG_KEY: &str = "auto-learn-apps";
+pub const MACROS_CONFIG_KEY: &str = "macros";
+const TEMPLATE_MACROS_CONFIG_KEY: &str = "templates";
+pub const MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY: &str = "macro-conflict-behavior";
+pub const MACRO_EXPAND_HOTKEY_CONFIG_KEY: &str = "macro-expand-hotkey";
+pub const TRANSFORM_WORD_HOTKEY_CONFIG_KEY: &str = "transform-word-hotkey";
+pub const TRANSFORM_SELECTION_HOTKEY_CONFIG_KEY: &str = "transform-selection-hotkey";
 const GOX_MODE_CONFIG_KEY: &str = "is_gox_mode_enabled";
 const ALLOWED_WORDS_CONFIG_KEY: &str = "allowed_words";
+const SPELLING_RULES_CONFIG_KEY: &str = "spelling-rules";
+pub const LOW_POWER_ENABLED_CONFIG_KEY: &str = "is_low_power_enabled";
+const TRAY_ENABLED_CONFIG_KEY: &str = "is_tray_enabled";
+pub const DEBUG_INDICATOR_CONFIG_KEY: &str = "debug-indicator";
+pub const ENGINE_FEATURES_CONFIG_KEY: &str = "engine-features";
+const LAST_SEEN_VERSION_CONFIG_KEY: &str = "last-seen-version";
+const ALLOW_UNSIGNED_CONTENT_CONFIG_KEY: &str = "allow-unsigned-content";
+const SETTINGS_SERVER_CONFIG_KEY: &str = "settings-server";
+const SETTINGS_SERVER_PORT_CONFIG_KEY: &str = "settings-server-port";
+const SETTINGS_SERVER_TOKEN_CONFIG_KEY: &str = "settings-server-token";
+const TRACE_STAGES_CONFIG_KEY: &str = "trace-stages";
+const TRACE_APP_CONFIG_KEY: &str = "trace-app";
+const SCHEMA_VERSION_CONFIG_KEY: &str = "schema-version";
+
+// Same format as ~/.goxkey, for deployments that don't use configuration profiles
+const MANAGED_CONFIG_PATH: &str = "/etc/goxkey.conf";
+
+#[test]
+fn test_managed_config_not_written() {
+    let lines = ["hotkey = ctrl+space", "method = vni"]
+        .map(String::from)
+        .to_vec();
+    let managed_entries = vec![
+        ("hotkey".to_string(), "super+space".to_string()),
+        ("macros".to_string(), "\"ko\"=\"không\"".to_string()),
+        ("is_gox_mode_enabled".to_string(), "true".to_string()),
+    ];
+    let mut config = ConfigStore::from_lines(lines, managed_entries);
+    assert_eq!(config.get_hotkey(), "super+space");
+    config.set_gox_mode_enabled(false);
+    assert!(config.is_gox_mode_enabled());
+    config.set_hotkey("alt+space");
+    config.add_macro("dc".to_string(), "được".to_string());
+    assert_eq!(config.get_hotkey(), "super+space");
+    assert_eq!(config.get_macro_table().len(), 1);
+
+    let mut data = Vec::new();
+    config.write_config(&mut data).unwrap();
+    let data = String::from_utf8(data).unwrap();
+    assert!(data.contains("hotkey = ctrl+space\n"));
+    assert!(data.contains("method = vni\n"));
+    assert!(!data.contains("super+space"));
+    assert!(!data.contains("macros"));
+}
+
+#[test]
+fn test_config_round_trip() {
+    let lines = [
+        "schema-version = 2",
+        "# written by hand",
+        "",
+        "method = vni",
+        "future-setting = on",
+        "vn-apps = /Applications/Notes.app",
+        "hotkey = ctrl+space",
+    ]
+    .map(String::from)
+    .to_vec();
+    let managed_entries = vec![("hotkey".to_string(), "super+space".to_string())];
+    let config = ConfigStore::from_lines(lines, managed_entries);
+    assert_eq!(config.get_method(), "vni");
+
+    let mut data = Vec::new();
+    config.write_config(&mut data).unwrap();
+    let data = String::from_utf8(data).unwrap();
+    let written = data.lines().collect::<Vec<_>>();
+    // The version of the writer, not the one of the file
+    assert_eq!(written[0], "schema-version = 1");
+    assert!(written.contains(&"method = vni"));
+    assert!(written.contains(&"vn-apps = /Applications/Notes.app"));
+    assert!(!written.contains(&""));
+    // Unknown lines and the user's lines for the managed keys go last
+    assert_eq!(
+        written[written.len() - 3..],
+        [
+            "# written by hand",
+            "future-setting = on",
+            "hotkey = ctrl+space"
+        ]
+    );
+
+    // Reading it again gives the same file
+    let config = ConfigStore::from_lines(
+        written.iter().map(|line| line.to_string()).collect(),
+        vec![("hotkey".to_string(), "super+space".to_string())],
+    );
+    let mut rewritten = Vec::new();
+    config.write_config(&mut rewritten).unwrap();
+    assert_eq!(String::from_utf8(rewritten).unwrap(), data);
+}
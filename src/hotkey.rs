@@ -1,38 +1,133 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use crate::platform::{
-    KeyModifier, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB, SYMBOL_ALT, SYMBOL_CTRL,
-    SYMBOL_SHIFT, SYMBOL_SUPER,
+    KeyModifier, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_F1, KEY_SPACE, KEY_TAB, SYMBOL_ALT,
+    SYMBOL_CTRL, SYMBOL_SHIFT, SYMBOL_SUPER,
 };
 
+const FUNCTION_KEY_COUNT: u32 = 12;
+
+pub fn function_key(number: u32) -> Option<char> {
+    if !(1..=FUNCTION_KEY_COUNT).contains(&number) {
+        return None;
+    }
+    char::from_u32(KEY_F1 as u32 + number - 1)
+}
+
+pub fn function_key_number(c: char) -> Option<u32> {
+    let number = (c as u32).checked_sub(KEY_F1 as u32)? + 1;
+    (number <= FUNCTION_KEY_COUNT).then_some(number)
+}
+
+/// The name of a key as written in the config, e.g. "a", "space" or "f5".
+pub fn format_key_name(c: char) -> String {
+    match c {
+        KEY_SPACE => String::from("space"),
+        c => match function_key_number(c) {
+            Some(number) => format!("f{}", number),
+            None => c.to_string(),
+        },
+    }
+}
+
 pub struct Hotkey {
     modifiers: KeyModifier,
     keycode: Option<char>,
 }
 
-impl Hotkey {
-    pub fn from_str(input: &str) -> Self {
+#[derive(Debug, PartialEq, Eq)]
+pub enum HotkeyParseError {
+    Empty,
+    EmptyKey,
+    UnknownKey(String),
+    DuplicateModifier(String),
+    MultipleKeys,
+    MissingModifier,
+    // Set by an administrator, see ConfigStore::is_locked
+    Locked,
+}
+
+impl Display for HotkeyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Chưa chọn phím tắt"),
+            Self::EmptyKey => write!(f, "Thừa dấu +"),
+            Self::UnknownKey(key) => write!(f, "Phím lạ: {}", key),
+            Self::DuplicateModifier(key) => write!(f, "Lặp phím: {}", key),
+            Self::MultipleKeys => write!(f, "Chỉ được một phím"),
+            Self::MissingModifier => write!(f, "Thiếu phím bổ trợ"),
+            Self::Locked => write!(f, "Phím tắt do quản trị viên đặt"),
+        }
+    }
+}
+
+impl Default for Hotkey {
+    fn default() -> Self {
+        Self {
+            modifiers: KeyModifier::MODIFIER_CONTROL,
+            keycode: Some(KEY_SPACE),
+        }
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = HotkeyParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        // Older versions saved modifier-only hotkeys with a trailing +
+        let input = input.trim();
+        let input = input.strip_suffix('+').unwrap_or(input);
+        if input.is_empty() {
+            return Err(HotkeyParseError::Empty);
+        }
         let mut modifiers = KeyModifier::new();
         let mut keycode: Option<char> = None;
-        input
-            .split('+')
-            .for_each(|token| match token.trim().to_uppercase().as_str() {
-                "SHIFT" => modifiers.add_shift(),
-                "ALT" => modifiers.add_alt(),
-                "SUPER" => modifiers.add_super(),
-                "CTRL" => modifiers.add_control(),
-                "ENTER" => keycode = Some(KEY_ENTER),
-                "SPACE" => keycode = Some(KEY_SPACE),
-                "TAB" => keycode = Some(KEY_TAB),
-                "DELETE" => keycode = Some(KEY_DELETE),
-                "ESC" => keycode = Some(KEY_ESCAPE),
-                c => {
-                    keycode = c.chars().last();
+        for token in input.split('+') {
+            let token = token.trim().to_uppercase();
+            let modifier = match token.as_str() {
+                "" => return Err(HotkeyParseError::EmptyKey),
+                "SHIFT" => KeyModifier::MODIFIER_SHIFT,
+                "ALT" => KeyModifier::MODIFIER_ALT,
+                "SUPER" => KeyModifier::MODIFIER_SUPER,
+                "CTRL" => KeyModifier::MODIFIER_CONTROL,
+                key => {
+                    let key = match key {
+                        "ENTER" => KEY_ENTER,
+                        "SPACE" => KEY_SPACE,
+                        "TAB" => KEY_TAB,
+                        "DELETE" => KEY_DELETE,
+                        "ESC" => KEY_ESCAPE,
+                        c if c.chars().count() == 1 => c.chars().last().unwrap(),
+                        f if f.starts_with('F') => f[1..]
+                            .parse()
+                            .ok()
+                            .and_then(function_key)
+                            .ok_or_else(|| HotkeyParseError::UnknownKey(token.to_lowercase()))?,
+                        _ => return Err(HotkeyParseError::UnknownKey(token.to_lowercase())),
+                    };
+                    if keycode.replace(key).is_some() {
+                        return Err(HotkeyParseError::MultipleKeys);
+                    }
+                    continue;
                 }
-            });
-        Self { modifiers, keycode }
+            };
+            if modifiers.contains(modifier) {
+                return Err(HotkeyParseError::DuplicateModifier(token.to_lowercase()));
+            }
+            modifiers.insert(modifier);
+        }
+        if modifiers.is_empty() {
+            return Err(HotkeyParseError::MissingModifier);
+        }
+        Ok(Self { modifiers, keycode })
     }
+}
 
+impl Hotkey {
     pub fn is_match(&self, mut modifiers: KeyModifier, keycode: Option<char>) -> bool {
         // Caps Lock should not interfere with any hotkey
         modifiers.remove(KeyModifier::MODIFIER_CAPSLOCK);
@@ -46,6 +141,10 @@ impl Hotkey {
     pub fn inner(&self) -> (KeyModifier, Option<char>) {
         (self.modifiers, self.keycode)
     }
+
+    pub fn is_function_key(&self) -> bool {
+        self.keycode.and_then(function_key_number).is_some()
+    }
 }
 
 impl Display for Hotkey {
@@ -68,15 +167,165 @@ impl Display for Hotkey {
             Some(KEY_TAB) => write!(f, "Tab"),
             Some(KEY_DELETE) => write!(f, "Del"),
             Some(KEY_ESCAPE) => write!(f, "Esc"),
-            Some(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            Some(c) => match function_key_number(c) {
+                Some(number) => write!(f, "F{}", number),
+                None => write!(f, "{}", c.to_ascii_uppercase()),
+            },
             _ => write!(f, ""),
         }
     }
 }
 
+/// Actions with their own hotkey, besides the one switching the input mode.
+/// They fire when the key is pressed, and the key is not sent to the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    // Expands the current word even when macros are turned off
+    ExpandMacro,
+    // Transforms the word before the caret, typed earlier without gõkey
+    TransformWord,
+    // "Chuyển vùng chọn sang tiếng Việt", for pasted text without accents
+    TransformSelection,
+}
+
+#[derive(Default)]
+pub struct HotkeyMap {
+    hotkeys: Vec<(HotkeyAction, Hotkey)>,
+}
+
+impl HotkeyMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the action to the hotkey, or unbinds it with None.
+    pub fn set(&mut self, action: HotkeyAction, hotkey: Option<Hotkey>) {
+        self.hotkeys.retain(|(a, _)| *a != action);
+        if let Some(hotkey) = hotkey {
+            self.hotkeys.push((action, hotkey));
+        }
+    }
+
+    pub fn get_action(
+        &self,
+        modifiers: KeyModifier,
+        keycode: Option<char>,
+    ) -> Option<HotkeyAction> {
+        // Modifiers on their own are for the input mode hotkey
+        keycode?;
+        self.hotkeys
+            .iter()
+            .find(|(_, hotkey)| hotkey.is_match(modifiers, keycode))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn uses_modifier(&self, modifier: KeyModifier) -> bool {
+        self.hotkeys
+            .iter()
+            .any(|(_, hotkey)| hotkey.modifiers.contains(modifier))
+    }
+}
+
+/// Follows the modifiers held since they were all released last time, to tell
+/// whether the hotkey was pressed on its own and should fire on release.
+pub struct HotkeyMatcher {
+    modifiers: KeyModifier,
+    is_matching: bool,
+    is_cancelled: bool,
+}
+
+impl HotkeyMatcher {
+    pub const fn new() -> Self {
+        Self {
+            modifiers: KeyModifier::MODIFIER_NONE,
+            is_matching: false,
+            is_cancelled: false,
+        }
+    }
+
+    /// Returns true if all the modifiers were just released
+    /// and the hotkey should fire.
+    pub fn on_modifiers_changed(&mut self, modifiers: KeyModifier) -> bool {
+        if !modifiers.is_empty() {
+            self.modifiers.set(modifiers, true);
+            return false;
+        }
+        let should_fire = self.is_matching && !self.is_cancelled;
+        *self = Self::new();
+        should_fire
+    }
+
+    /// Holding the modifiers while clicking or dragging is not a hotkey,
+    /// e.g. Ctrl+Shift+drag to select in some apps.
+    pub fn on_mouse_event(&mut self) {
+        if !self.modifiers.is_empty() {
+            self.is_cancelled = true;
+        }
+    }
+
+    pub fn match_hotkey(&mut self, hotkey: &Hotkey, keycode: Option<char>) {
+        let is_matched = hotkey.is_match(self.modifiers, keycode);
+        if self.is_matching && !is_matched {
+            self.is_cancelled = true;
+        }
+        self.is_matching = is_matched;
+    }
+}
+
+// Holding the Globe key longer than this is considered a long press
+const GLOBE_KEY_LONG_PRESS_DURATION: Duration = Duration::from_millis(500);
+// Some external keyboards send the Globe key multiple times in a row,
+// ignore any toggle that happens too soon after the previous one.
+const GLOBE_KEY_DEBOUNCE_DURATION: Duration = Duration::from_millis(300);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GlobeKeyAction {
+    None,
+    Toggle,
+    LongPress,
+}
+
+pub struct GlobeKeyTracker {
+    pressed_at: Option<Instant>,
+    last_toggled_at: Option<Instant>,
+}
+
+impl GlobeKeyTracker {
+    pub const fn new() -> Self {
+        Self {
+            pressed_at: None,
+            last_toggled_at: None,
+        }
+    }
+
+    pub fn on_key_down(&mut self, now: Instant) {
+        // Repeated key down events while holding the key are ignored
+        if self.pressed_at.is_none() {
+            self.pressed_at = Some(now);
+        }
+    }
+
+    pub fn on_key_up(&mut self, now: Instant) -> GlobeKeyAction {
+        let Some(pressed_at) = self.pressed_at.take() else {
+            return GlobeKeyAction::None;
+        };
+        if now.duration_since(pressed_at) >= GLOBE_KEY_LONG_PRESS_DURATION {
+            return GlobeKeyAction::LongPress;
+        }
+        if self
+            .last_toggled_at
+            .is_some_and(|t| now.duration_since(t) < GLOBE_KEY_DEBOUNCE_DURATION)
+        {
+            return GlobeKeyAction::None;
+        }
+        self.last_toggled_at = Some(now);
+        GlobeKeyAction::Toggle
+    }
+}
+
 #[test]
 fn test_parse() {
-    let hotkey = Hotkey::from_str("super+shift+z");
+    let hotkey = Hotkey::from_str("super+shift+z").unwrap();
     let mut actual_modifier = KeyModifier::new();
     actual_modifier.add_shift();
     actual_modifier.add_super();
@@ -87,7 +336,7 @@ fn test_parse() {
 
 #[test]
 fn test_parse_long_input() {
-    let hotkey = Hotkey::from_str("super+shift+ctrl+alt+w");
+    let hotkey = Hotkey::from_str("super+shift+ctrl+alt+w").unwrap();
     let mut actual_modifier = KeyModifier::new();
     actual_modifier.add_shift();
     actual_modifier.add_super();
@@ -100,7 +349,7 @@ fn test_parse_long_input() {
 
 #[test]
 fn test_parse_with_named_keycode() {
-    let hotkey = Hotkey::from_str("super+ctrl+space");
+    let hotkey = Hotkey::from_str("super+ctrl+space").unwrap();
     let mut actual_modifier = KeyModifier::new();
     actual_modifier.add_super();
     actual_modifier.add_control();
@@ -111,7 +360,7 @@ fn test_parse_with_named_keycode() {
 
 #[test]
 fn test_can_match_with_or_without_capslock() {
-    let hotkey = Hotkey::from_str("super+ctrl+space");
+    let hotkey = Hotkey::from_str("super+ctrl+space").unwrap();
     let mut actual_modifier = KeyModifier::new();
     actual_modifier.add_super();
     actual_modifier.add_control();
@@ -123,7 +372,7 @@ fn test_can_match_with_or_without_capslock() {
 
 #[test]
 fn test_parse_with_just_modifiers() {
-    let hotkey = Hotkey::from_str("ctrl+shift");
+    let hotkey = Hotkey::from_str("ctrl+shift").unwrap();
     let mut actual_modifier = KeyModifier::new();
     actual_modifier.add_control();
     actual_modifier.add_shift();
@@ -132,20 +381,192 @@ fn test_parse_with_just_modifiers() {
     assert!(hotkey.is_match(actual_modifier, None));
 }
 
+#[test]
+fn test_parse_modifiers_with_trailing_plus() {
+    let hotkey = Hotkey::from_str("ctrl+shift+").unwrap();
+    assert_eq!(
+        hotkey.modifiers,
+        KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT
+    );
+    assert_eq!(hotkey.keycode, None);
+}
+
+#[test]
+fn test_parse_function_keys() {
+    let hotkey = Hotkey::from_str("ctrl+f5").unwrap();
+    assert_eq!(hotkey.keycode, function_key(5));
+    assert!(hotkey.is_function_key());
+    assert!(hotkey.is_match(KeyModifier::MODIFIER_CONTROL, function_key(5)));
+    assert!(!hotkey.is_match(KeyModifier::MODIFIER_CONTROL, function_key(6)));
+    assert_eq!(hotkey.to_string(), format!("{} F5", SYMBOL_CTRL));
+    assert_eq!(format_key_name(function_key(12).unwrap()), "f12");
+    assert!(!Hotkey::from_str("ctrl+f").unwrap().is_function_key());
+    assert_eq!(
+        Hotkey::from_str("ctrl+f13").err(),
+        Some(HotkeyParseError::UnknownKey("f13".to_string()))
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(Hotkey::from_str("").err(), Some(HotkeyParseError::Empty));
+    assert_eq!(
+        Hotkey::from_str("ctrl+++").err(),
+        Some(HotkeyParseError::EmptyKey)
+    );
+    assert_eq!(
+        Hotkey::from_str("ctrl+foo").err(),
+        Some(HotkeyParseError::UnknownKey("foo".to_string()))
+    );
+    assert_eq!(
+        Hotkey::from_str("ctrl+shift+ctrl").err(),
+        Some(HotkeyParseError::DuplicateModifier("ctrl".to_string()))
+    );
+    assert_eq!(
+        Hotkey::from_str("ctrl+a+b").err(),
+        Some(HotkeyParseError::MultipleKeys)
+    );
+    assert_eq!(
+        Hotkey::from_str("space").err(),
+        Some(HotkeyParseError::MissingModifier)
+    );
+}
+
 #[test]
 fn test_display() {
     assert_eq!(
-        format!("{}", Hotkey::from_str("super+ctrl+space")),
+        format!("{}", Hotkey::from_str("super+ctrl+space").unwrap()),
         format!("{} {} Space", SYMBOL_CTRL, SYMBOL_SUPER)
     );
 
     assert_eq!(
-        format!("{}", Hotkey::from_str("super+alt+z")),
+        format!("{}", Hotkey::from_str("super+alt+z").unwrap()),
         format!("{} {} Z", SYMBOL_ALT, SYMBOL_SUPER)
     );
 
     assert_eq!(
-        format!("{}", Hotkey::from_str("ctrl+shift+o")),
+        format!("{}", Hotkey::from_str("ctrl+shift+o").unwrap()),
         format!("{} {} O", SYMBOL_CTRL, SYMBOL_SHIFT)
     );
 }
+
+#[test]
+fn test_modifier_only_hotkey_fires_on_release() {
+    let hotkey = Hotkey::from_str("ctrl+shift").unwrap();
+    let mut matcher = HotkeyMatcher::new();
+    assert!(!matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL));
+    matcher.match_hotkey(&hotkey, None);
+    assert!(
+        !matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT)
+    );
+    matcher.match_hotkey(&hotkey, None);
+    assert!(matcher.on_modifiers_changed(KeyModifier::MODIFIER_NONE));
+}
+
+#[test]
+fn test_modifier_only_hotkey_cancelled_by_mouse() {
+    let hotkey = Hotkey::from_str("ctrl+shift").unwrap();
+    let mut matcher = HotkeyMatcher::new();
+    matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT);
+    matcher.match_hotkey(&hotkey, None);
+    matcher.on_mouse_event();
+    matcher.match_hotkey(&hotkey, None);
+    assert!(!matcher.on_modifiers_changed(KeyModifier::MODIFIER_NONE));
+
+    // The next chord is not affected
+    matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT);
+    matcher.match_hotkey(&hotkey, None);
+    assert!(matcher.on_modifiers_changed(KeyModifier::MODIFIER_NONE));
+}
+
+#[test]
+fn test_mouse_without_modifiers_does_not_cancel() {
+    let hotkey = Hotkey::from_str("ctrl+shift").unwrap();
+    let mut matcher = HotkeyMatcher::new();
+    matcher.on_mouse_event();
+    matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT);
+    matcher.match_hotkey(&hotkey, None);
+    assert!(matcher.on_modifiers_changed(KeyModifier::MODIFIER_NONE));
+}
+
+#[test]
+fn test_hotkey_cancelled_by_other_key() {
+    let hotkey = Hotkey::from_str("ctrl+shift").unwrap();
+    let mut matcher = HotkeyMatcher::new();
+    matcher.on_modifiers_changed(KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT);
+    matcher.match_hotkey(&hotkey, None);
+    matcher.match_hotkey(&hotkey, Some('a'));
+    assert!(!matcher.on_modifiers_changed(KeyModifier::MODIFIER_NONE));
+}
+
+#[test]
+fn test_globe_key_short_press_toggles() {
+    let mut tracker = GlobeKeyTracker::new();
+    let now = Instant::now();
+    tracker.on_key_down(now);
+    assert_eq!(
+        tracker.on_key_up(now + Duration::from_millis(80)),
+        GlobeKeyAction::Toggle
+    );
+}
+
+#[test]
+fn test_globe_key_repeated_key_down_is_a_long_press() {
+    let mut tracker = GlobeKeyTracker::new();
+    let now = Instant::now();
+    tracker.on_key_down(now);
+    tracker.on_key_down(now + Duration::from_millis(300));
+    tracker.on_key_down(now + Duration::from_millis(600));
+    assert_eq!(
+        tracker.on_key_up(now + Duration::from_millis(700)),
+        GlobeKeyAction::LongPress
+    );
+}
+
+#[test]
+fn test_globe_key_debounce_rapid_toggles() {
+    let mut tracker = GlobeKeyTracker::new();
+    let now = Instant::now();
+    tracker.on_key_down(now);
+    assert_eq!(
+        tracker.on_key_up(now + Duration::from_millis(50)),
+        GlobeKeyAction::Toggle
+    );
+    tracker.on_key_down(now + Duration::from_millis(100));
+    assert_eq!(
+        tracker.on_key_up(now + Duration::from_millis(150)),
+        GlobeKeyAction::None
+    );
+    tracker.on_key_down(now + Duration::from_millis(500));
+    assert_eq!(
+        tracker.on_key_up(now + Duration::from_millis(550)),
+        GlobeKeyAction::Toggle
+    );
+}
+
+#[test]
+fn test_globe_key_up_without_key_down() {
+    let mut tracker = GlobeKeyTracker::new();
+    assert_eq!(tracker.on_key_up(Instant::now()), GlobeKeyAction::None);
+}
+
+#[test]
+fn test_hotkey_map() {
+    let mut map = HotkeyMap::new();
+    let control = KeyModifier::MODIFIER_CONTROL;
+    assert_eq!(map.get_action(control, Some('e')), None);
+    map.set(
+        HotkeyAction::ExpandMacro,
+        Some(Hotkey::from_str("ctrl+e").unwrap()),
+    );
+    assert_eq!(
+        map.get_action(control, Some('e')),
+        Some(HotkeyAction::ExpandMacro)
+    );
+    assert_eq!(map.get_action(control, Some('a')), None);
+    assert_eq!(map.get_action(control, None), None);
+    assert!(map.uses_modifier(control));
+    assert!(!map.uses_modifier(KeyModifier::MODIFIER_ALT));
+    map.set(HotkeyAction::ExpandMacro, None);
+    assert_eq!(map.get_action(control, Some('e')), None);
+}
@@ -0,0 +1,141 @@
+//! Limits on what gets typed into the focused app. A broken macro or custom
+//! script could otherwise send thousands of characters or backspaces in one
+//! go, and the system stays unresponsive until they are all processed.
+
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+// Longer than any sensible macro
+pub const MAX_CHARS_PER_OPERATION: usize = 2000;
+// Multi-word macro triggers delete a few words, not whole paragraphs
+pub const MAX_BACKSPACES_PER_OPERATION: usize = 200;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+// Characters and backspaces together, fast typing stays well below this
+const MAX_KEYS_PER_WINDOW: usize = 4000;
+// The same error is reported once in a while, not on every blocked key
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+pub static mut INJECTION_LIMITER: InjectionLimiter = InjectionLimiter::new();
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InjectionError {
+    TooManyChars(usize),
+    TooManyBackspaces(usize),
+    RateLimited,
+}
+
+impl Display for InjectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManyChars(count) => write!(
+                f,
+                "Đã chặn một lần gõ {} ký tự, quá giới hạn {}. Hãy kiểm tra lại bảng gõ tắt.",
+                count, MAX_CHARS_PER_OPERATION
+            ),
+            Self::TooManyBackspaces(count) => write!(
+                f,
+                "Đã chặn một lần xoá {} ký tự, quá giới hạn {}.",
+                count, MAX_BACKSPACES_PER_OPERATION
+            ),
+            Self::RateLimited => {
+                write!(f, "Bộ gõ đang gửi quá nhiều phím, tạm dừng trong giây lát.")
+            }
+        }
+    }
+}
+
+pub struct InjectionLimiter {
+    window_started_at: Option<Instant>,
+    keys_in_window: usize,
+    last_reported_at: Option<Instant>,
+}
+
+impl InjectionLimiter {
+    pub const fn new() -> Self {
+        Self {
+            window_started_at: None,
+            keys_in_window: 0,
+            last_reported_at: None,
+        }
+    }
+
+    /// Checks an edit before it's sent, and counts it when it's allowed.
+    pub fn check(
+        &mut self,
+        backspace_count: usize,
+        char_count: usize,
+        now: Instant,
+    ) -> Result<(), InjectionError> {
+        if char_count > MAX_CHARS_PER_OPERATION {
+            return Err(InjectionError::TooManyChars(char_count));
+        }
+        if backspace_count > MAX_BACKSPACES_PER_OPERATION {
+            return Err(InjectionError::TooManyBackspaces(backspace_count));
+        }
+        let is_new_window = match self.window_started_at {
+            Some(started_at) => now.duration_since(started_at) >= RATE_LIMIT_WINDOW,
+            None => true,
+        };
+        if is_new_window {
+            self.window_started_at = Some(now);
+            self.keys_in_window = 0;
+        }
+        let key_count = backspace_count + char_count;
+        if self.keys_in_window + key_count > MAX_KEYS_PER_WINDOW {
+            return Err(InjectionError::RateLimited);
+        }
+        self.keys_in_window += key_count;
+        Ok(())
+    }
+
+    pub fn should_report(&mut self, now: Instant) -> bool {
+        let should_report = match self.last_reported_at {
+            Some(reported_at) => now.duration_since(reported_at) >= ERROR_REPORT_INTERVAL,
+            None => true,
+        };
+        if should_report {
+            self.last_reported_at = Some(now);
+        }
+        should_report
+    }
+}
+
+#[test]
+fn test_injection_caps() {
+    let mut limiter = InjectionLimiter::new();
+    let now = Instant::now();
+    assert_eq!(limiter.check(3, 5, now), Ok(()));
+    assert_eq!(
+        limiter.check(0, MAX_CHARS_PER_OPERATION + 1, now),
+        Err(InjectionError::TooManyChars(MAX_CHARS_PER_OPERATION + 1))
+    );
+    assert_eq!(
+        limiter.check(MAX_BACKSPACES_PER_OPERATION + 1, 1, now),
+        Err(InjectionError::TooManyBackspaces(
+            MAX_BACKSPACES_PER_OPERATION + 1
+        ))
+    );
+    assert_eq!(limiter.check(0, MAX_CHARS_PER_OPERATION, now), Ok(()));
+}
+
+#[test]
+fn test_injection_rate_limit() {
+    let mut limiter = InjectionLimiter::new();
+    let now = Instant::now();
+    assert_eq!(limiter.check(0, MAX_CHARS_PER_OPERATION, now), Ok(()));
+    assert_eq!(limiter.check(0, MAX_CHARS_PER_OPERATION, now), Ok(()));
+    assert_eq!(limiter.check(1, 0, now), Err(InjectionError::RateLimited));
+    // Blocked edits are not counted, and the budget comes back after a while
+    assert_eq!(limiter.check(1, 0, now + RATE_LIMIT_WINDOW), Ok(()));
+}
+
+#[test]
+fn test_injection_error_report_interval() {
+    let mut limiter = InjectionLimiter::new();
+    let now = Instant::now();
+    assert!(limiter.should_report(now));
+    assert!(!limiter.should_report(now + Duration::from_secs(1)));
+    assert!(limiter.should_report(now + ERROR_REPORT_INTERVAL));
+}
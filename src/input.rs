@@ -1,15 +1,34 @@
 use std::collections::BTreeMap;
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display, mem, str::FromStr, time::Duration};
 
+use bitflags::bitflags;
 use druid::{Data, Target};
-use log::debug;
+use log::{debug, error, LevelFilter};
 use once_cell::sync::{Lazy, OnceCell};
-use rdev::{Keyboard, KeyboardState};
 use vi::TransformResult;
 
-use crate::platform::{get_active_app_name, KeyModifier};
+use crate::platform::{
+    get_active_app_name, get_keyboard_layout_map, is_on_battery_power, KeyModifier, KEY_ENTER,
+    KEY_SPACE, KEY_TAB,
+};
 use crate::{
-    config::CONFIG_MANAGER, hotkey::Hotkey, platform::is_in_text_selection, ui::UPDATE_UI,
+    compat::{self, LatencyTracker, SelectionMetrics},
+    config::{
+        AppPolicy, AUTOS_TOGGLE_ENABLED_CONFIG_KEY, AUTO_LEARN_APPS_CONFIG_KEY, CONFIG_MANAGER,
+        DEBUG_INDICATOR_CONFIG_KEY, ENGINE_FEATURES_CONFIG_KEY, GLOBE_KEY_BEHAVIOR_CONFIG_KEY,
+        HOTKEY_CONFIG_KEY, LOCALE_CONFIG_KEY, LOW_POWER_ENABLED_CONFIG_KEY, MACROS_CONFIG_KEY,
+        MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY, MACRO_ENABLED_CONFIG_KEY,
+        MACRO_EXPAND_HOTKEY_CONFIG_KEY, TRANSFORM_SELECTION_HOTKEY_CONFIG_KEY,
+        TRANSFORM_WORD_HOTKEY_CONFIG_KEY, TYPING_METHOD_CONFIG_KEY, UNKNOWN_APP_MODE_CONFIG_KEY,
+    },
+    hotkey::{GlobeKeyTracker, Hotkey, HotkeyAction, HotkeyMap, HotkeyMatcher, HotkeyParseError},
+    migration::ImportedSettings,
+    platform::SelectionSource,
+    spelling::{get_spelling_rules, normalize_spelling, to_old_tone_style},
+    template::{expand_template, LocalDateTime, Locale},
+    trace,
+    ui::UPDATE_UI,
+    validation::{self, EnglishDictionary, VietnameseValidator, WordList, WordValidator},
     UI_EVENT_SINK,
 };
 
@@ -19,15 +38,21 @@ use crate::{
 // be around 10 to 12.
 const MAX_POSSIBLE_WORD_LENGTH: usize = 10;
 const MAX_DUPLICATE_LENGTH: usize = 4;
+// Editors insert one closing character per opening one
+const MAX_AUTO_INSERTED_LENGTH: usize = 4;
+// The longest macro trigger, in words
+const MAX_MACRO_WORDS: usize = 4;
 const TONE_DUPLICATE_PATTERNS: [&str; 17] = [
     "ss", "ff", "jj", "rr", "xx", "ww", "kk", "tt", "nn", "mm", "yy", "hh", "ii", "aaa", "eee",
     "ooo", "ddd",
 ];
 
 pub static mut INPUT_STATE: Lazy<InputState> = Lazy::new(InputState::new);
-pub static mut HOTKEY_MODIFIERS: KeyModifier = KeyModifier::MODIFIER_NONE;
-pub static mut HOTKEY_MATCHING: bool = false;
-pub static mut HOTKEY_MATCHING_CIRCUIT_BREAK: bool = false;
+// The log level configured at startup (via RUST_LOG), restored when
+// leaving low power mode.
+static DEFAULT_LOG_LEVEL: Lazy<LevelFilter> = Lazy::new(log::max_level);
+pub static mut HOTKEY_MATCHER: HotkeyMatcher = HotkeyMatcher::new();
+pub static mut GLOBE_KEY_TRACKER: GlobeKeyTracker = GlobeKeyTracker::new();
 
 pub const PREDEFINED_CHARS: [char; 47] = [
     'a', '`', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '-', '=', 'q', 'w', 'e', 'r', 't',
@@ -37,72 +62,31 @@ pub const PREDEFINED_CHARS: [char; 47] = [
 
 pub const STOP_TRACKING_WORDS: [&str; 4] = [";", "'", "?", "/"];
 
-pub fn get_key_from_char(c: char) -> rdev::Key {
-    use rdev::Key::*;
-    match &c {
-        'a' => KeyA,
-        '`' => BackQuote,
-        '1' => Num1,
-        '2' => Num2,
-        '3' => Num3,
-        '4' => Num4,
-        '5' => Num5,
-        '6' => Num6,
-        '7' => Num7,
-        '8' => Num8,
-        '9' => Num9,
-        '0' => Num0,
-        '-' => Minus,
-        '=' => Equal,
-        'q' => KeyQ,
-        'w' => KeyW,
-        'e' => KeyE,
-        'r' => KeyR,
-        't' => KeyT,
-        'y' => KeyY,
-        'u' => KeyU,
-        'i' => KeyI,
-        'o' => KeyO,
-        'p' => KeyP,
-        '[' => LeftBracket,
-        ']' => RightBracket,
-        's' => KeyS,
-        'd' => KeyD,
-        'f' => KeyF,
-        'g' => KeyG,
-        'h' => KeyH,
-        'j' => KeyJ,
-        'k' => KeyK,
-        'l' => KeyL,
-        ';' => SemiColon,
-        '\'' => Quote,
-        '\\' => BackSlash,
-        'z' => KeyZ,
-        'x' => KeyX,
-        'c' => KeyC,
-        'v' => KeyV,
-        'b' => KeyB,
-        'n' => KeyN,
-        'm' => KeyM,
-        ',' => Comma,
-        '.' => Dot,
-        '/' => Slash,
-        _ => Unknown(0),
-    }
+pub static mut KEYBOARD_LAYOUT_CHARACTER_MAP: OnceCell<HashMap<char, char>> = OnceCell::new();
+
+/// The character a key types in the current layout. Keys missing from the
+/// map, like dead keys, fall back to their QWERTY character.
+pub fn get_layout_char(map: &HashMap<char, char>, c: char) -> char {
+    map.get(&c).copied().unwrap_or(c)
 }
 
-pub static mut KEYBOARD_LAYOUT_CHARACTER_MAP: OnceCell<HashMap<char, char>> = OnceCell::new();
+// Backspaces needed to delete the word on screen. The key being processed is
+// not on screen yet, unless it's a delete, and a text selection takes one more.
+pub fn compute_backspace_count(
+    word_length: usize,
+    is_delete: bool,
+    is_in_selection: bool,
+) -> usize {
+    let backspace_count = if is_delete {
+        word_length
+    } else {
+        word_length.saturating_sub(1)
+    };
+    backspace_count + usize::from(is_in_selection)
+}
 
 fn build_keyboard_layout_map(map: &mut HashMap<char, char>) {
-    map.clear();
-    let mut kb = Keyboard::new().unwrap();
-    for c in PREDEFINED_CHARS {
-        let key = rdev::EventType::KeyPress(get_key_from_char(c));
-        if let Some(s) = kb.add(&key) {
-            let ch = s.chars().last().unwrap();
-            map.insert(c, ch);
-        }
-    }
+    *map = get_keyboard_layout_map(&PREDEFINED_CHARS);
 }
 
 pub fn rebuild_keyboard_layout_map() {
@@ -121,8 +105,13 @@ pub fn rebuild_keyboard_layout_map() {
     }
 }
 
+// Managed settings can't be changed by the user
+fn is_setting_locked(key: &str) -> bool {
+    CONFIG_MANAGER.lock().unwrap().is_locked(key)
+}
+
 #[allow(clippy::upper_case_acronyms)]
-#[derive(PartialEq, Eq, Data, Clone, Copy)]
+#[derive(PartialEq, Eq, Data, Clone, Copy, Debug)]
 pub enum TypingMethod {
     VNI,
     Telex,
@@ -152,21 +141,339 @@ impl Display for TypingMethod {
     }
 }
 
+bitflags! {
+    /// Parts of the engine that can be turned off at runtime, to find out
+    /// which one is causing trouble without rebuilding the app.
+    pub struct EngineFeatures: u32 {
+        // Restore the typed keys when the word is not valid Vietnamese
+        const AUTO_RESTORE           = 0b00000001;
+        // Send an extra backspace when the app pre-selects text, e.g. URL bars
+        const SELECTION_COMPENSATION = 0b00000010;
+        // Dismiss the URL bar selection in Firefox by typing a space first
+        const FIREFOX_WORKAROUND     = 0b00000100;
+        // Capitalize the first word of a sentence
+        const AUTO_CAPITALIZE        = 0b00001000;
+        const MACROS                 = 0b00010000;
+        // hòa, thúy instead of hoà, thuý
+        const OLD_TONE_STYLE         = 0b00100000;
+        // Leave words typed next to Chinese, Japanese, Korean, Arabic or Hebrew text alone
+        const FOREIGN_SCRIPT_PASSTHROUGH = 0b01000000;
+        // Restore words whose keys spell an English word
+        const ENGLISH_DICTIONARY     = 0b10000000;
+        // Fix regional spelling slips like "nghành" when the word is done
+        const SPELLING_NORMALIZATION = 0b100000000;
+        // For learners: invalid words are always restored, with a beep
+        const STRICT_VIETNAMESE      = 0b1000000000;
+    }
+}
+
+// Macros are saved under their own config key
+const ENGINE_FEATURE_NAMES: [(EngineFeatures, &str); 9] = [
+    (EngineFeatures::AUTO_RESTORE, "auto-restore"),
+    (
+        EngineFeatures::SELECTION_COMPENSATION,
+        "selection-compensation",
+    ),
+    (EngineFeatures::FIREFOX_WORKAROUND, "firefox-workaround"),
+    (EngineFeatures::AUTO_CAPITALIZE, "auto-capitalize"),
+    (EngineFeatures::OLD_TONE_STYLE, "old-tone-style"),
+    (
+        EngineFeatures::FOREIGN_SCRIPT_PASSTHROUGH,
+        "foreign-script-passthrough",
+    ),
+    (EngineFeatures::ENGLISH_DICTIONARY, "english-dictionary"),
+    (
+        EngineFeatures::SPELLING_NORMALIZATION,
+        "spelling-normalization",
+    ),
+    (EngineFeatures::STRICT_VIETNAMESE, "strict-vietnamese"),
+];
+
+impl Default for EngineFeatures {
+    fn default() -> Self {
+        Self::AUTO_RESTORE | Self::SELECTION_COMPENSATION | Self::FIREFOX_WORKAROUND
+    }
+}
+
+impl EngineFeatures {
+    pub fn from_names(names: &[String]) -> Self {
+        ENGINE_FEATURE_NAMES
+            .iter()
+            .filter(|(_, name)| names.iter().any(|n| n == name))
+            .fold(Self::empty(), |features, (feature, _)| features | *feature)
+    }
+
+    pub fn to_names(self) -> Vec<String> {
+        ENGINE_FEATURE_NAMES
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
+}
+
+fn is_sentence_end(c: char, modifiers: KeyModifier) -> bool {
+    matches!(c, '.' | '!' | '?') || (modifiers.is_shift() && matches!(c, '1' | '/'))
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum SentencePosition {
+    Middle,
+    // Right after a '.', '!' or '?', which could still be part of a word
+    // like a file name
+    AfterEnd,
+    Start,
+}
+
+// A new sentence starts with a space or Enter after the end of the last one
+fn get_sentence_position(
+    position: SentencePosition,
+    c: char,
+    modifiers: KeyModifier,
+) -> SentencePosition {
+    use SentencePosition::*;
+    match c {
+        KEY_ENTER => Start,
+        KEY_SPACE if position != Middle => Start,
+        '"' | '\'' | ')' if position == AfterEnd => AfterEnd,
+        c if is_sentence_end(c, modifiers) => AfterEnd,
+        _ => Middle,
+    }
+}
+
+// The word the caret is in or next to, split at the caret, if it can be
+// typed with the Telex or VNI keys. Nothing is returned for a longer run of
+// letters, which could be part of something that isn't a word.
+pub fn get_caret_word<'a>(before: &'a str, after: &'a str) -> Option<(&'a str, &'a str)> {
+    // Only ASCII is taken, so the counts are byte lengths
+    let start = before.len()
+        - before
+            .chars()
+            .rev()
+            .take_while(char::is_ascii_alphanumeric)
+            .count();
+    let end = after
+        .chars()
+        .take_while(char::is_ascii_alphanumeric)
+        .count();
+    // Part of a word that already has Vietnamese letters
+    if before[..start]
+        .chars()
+        .last()
+        .is_some_and(char::is_alphanumeric)
+        || after[end..]
+            .chars()
+            .next()
+            .is_some_and(char::is_alphanumeric)
+    {
+        return None;
+    }
+    let length = before.len() - start + end;
+    (length > 0 && length <= MAX_POSSIBLE_WORD_LENGTH).then(|| (&before[start..], &after[..end]))
+}
+
+// Words starting with a letter and with VNI keys in them were typed with
+// VNI. Names like "mp3" match too, they are left out by the validators.
+pub fn detect_typing_method(word: &str) -> TypingMethod {
+    if word.starts_with(|c: char| c.is_ascii_alphabetic())
+        && word.chars().any(|c| matches!(c, '1'..='9'))
+    {
+        TypingMethod::VNI
+    } else {
+        TypingMethod::Telex
+    }
+}
+
+// Runs every word of the text through transform. Everything else, and the
+// words that already have Vietnamese letters or are too long to be typed
+// as one, is kept as is.
+pub fn transform_words(text: &str, mut transform: impl FnMut(&str) -> String) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let is_word = rest.starts_with(char::is_alphanumeric);
+        let end = rest
+            .find(|c: char| c.is_alphanumeric() != is_word)
+            .unwrap_or(rest.len());
+        let (token, remaining) = rest.split_at(end);
+        if is_word && token.is_ascii() && token.len() <= MAX_POSSIBLE_WORD_LENGTH {
+            output.push_str(&transform(token));
+        } else {
+            output.push_str(token);
+        }
+        rest = remaining;
+    }
+    output
+}
+
+// Đ only starts a word and comes from its first d, so it follows the case of
+// that key: "Dd" and "DD" give Đ, "dD" and "dd" give đ, whatever the case of
+// the key that completed it.
+pub fn resolve_d_stroke_case(buffer: &str, output: String) -> String {
+    let resolved = match (buffer.chars().next(), output.chars().next()) {
+        (Some('d'), Some('Đ')) => 'đ',
+        (Some('D'), Some('đ')) => 'Đ',
+        _ => return output,
+    };
+    let mut result = String::with_capacity(output.len());
+    result.push(resolved);
+    result.push_str(&output['đ'.len_utf8()..]);
+    result
+}
+
+#[derive(PartialEq, Eq, Data, Clone, Copy)]
+pub enum GlobeKeyBehavior {
+    // Short press toggles Vietnamese input, long press does nothing
+    Toggle,
+    // Short press toggles Vietnamese input, long press opens the settings window
+    ToggleOrSettings,
+    // Let the system handle the Globe key
+    PassThrough,
+}
+
+impl FromStr for GlobeKeyBehavior {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "toggle-or-settings" => GlobeKeyBehavior::ToggleOrSettings,
+            "pass-through" => GlobeKeyBehavior::PassThrough,
+            _ => GlobeKeyBehavior::Toggle,
+        })
+    }
+}
+
+impl Display for GlobeKeyBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Toggle => "toggle",
+                Self::ToggleOrSettings => "toggle-or-settings",
+                Self::PassThrough => "pass-through",
+            }
+        )
+    }
+}
+
+// The input mode to switch to in apps that are in none of the lists
+#[derive(PartialEq, Eq, Data, Clone, Copy)]
+pub enum UnknownAppMode {
+    KeepCurrent,
+    Vietnamese,
+    English,
+}
+
+impl FromStr for UnknownAppMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "vietnamese" => UnknownAppMode::Vietnamese,
+            "english" => UnknownAppMode::English,
+            _ => UnknownAppMode::KeepCurrent,
+        })
+    }
+}
+
+impl Display for UnknownAppMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::KeepCurrent => "keep-current",
+                Self::Vietnamese => "vietnamese",
+                Self::English => "english",
+            }
+        )
+    }
+}
+
+/// Whether Vietnamese input should be on in an app with this policy, None
+/// keeps the current mode.
+pub fn get_app_input_mode(policy: AppPolicy, unknown_app_mode: UnknownAppMode) -> Option<bool> {
+    match (policy, unknown_app_mode) {
+        (AppPolicy::Vietnamese, _) | (AppPolicy::Default, UnknownAppMode::Vietnamese) => Some(true),
+        (AppPolicy::English, _) | (AppPolicy::Default, UnknownAppMode::English) => Some(false),
+        _ => None,
+    }
+}
+
+// What to do when a macro trigger is also a Vietnamese word, like "ko"
+#[derive(PartialEq, Eq, Data, Clone, Copy)]
+pub enum MacroConflictBehavior {
+    // Expand on Space and Tab, Esc after the trigger keeps it as typed
+    Expand,
+    // Keep the word on Space, Tab still expands it
+    Literal,
+}
+
+impl FromStr for MacroConflictBehavior {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "literal" => MacroConflictBehavior::Literal,
+            _ => MacroConflictBehavior::Expand,
+        })
+    }
+}
+
+impl Display for MacroConflictBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Expand => "expand",
+                Self::Literal => "literal",
+            }
+        )
+    }
+}
+
 pub struct InputState {
     buffer: String,
     display_buffer: String,
     method: TypingMethod,
+    locale: Locale,
     hotkey: Hotkey,
+    action_hotkeys: HotkeyMap,
+    globe_key_behavior: GlobeKeyBehavior,
     enabled: bool,
     should_track: bool,
     previous_word: String,
     active_app: String,
-    is_macro_enabled: bool,
+    is_ignored_app: bool,
+    is_fast_mode_app: bool,
+    is_alt_passthrough_app: bool,
+    latency_tracker: LatencyTracker,
+    selection_metrics: SelectionMetrics,
+    features: EngineFeatures,
+    sentence_position: SentencePosition,
+    committed_words: Vec<String>,
     macro_table: BTreeMap<String, String>,
+    template_macros: Vec<String>,
+    macro_conflict_behavior: MacroConflictBehavior,
+    allowed_words: WordList,
+    user_dictionary: WordList,
+    spelling_rules: Vec<(String, String)>,
     temporary_disabled: bool,
     previous_modifiers: KeyModifier,
+    // Ctrl was pressed with a word typed, waiting for the next key to tell
+    // whether it's a hotkey action or the word should be restored
+    is_restore_pending: bool,
     is_auto_toggle_enabled: bool,
+    unknown_app_mode: UnknownAppMode,
+    is_auto_learn_enabled: bool,
     is_gox_mode_enabled: bool,
+    is_low_power_enabled: bool,
+    is_on_battery: bool,
+    is_safe_mode: bool,
+    is_debug_indicator_enabled: bool,
+    reported_debug_state: Option<(bool, usize)>,
 }
 
 impl InputState {
@@ -176,36 +483,147 @@ impl InputState {
             buffer: String::new(),
             display_buffer: String::new(),
             method: TypingMethod::from_str(config.get_method()).unwrap(),
-            hotkey: Hotkey::from_str(config.get_hotkey()),
+            locale: Locale::from_str(config.get_locale()).unwrap(),
+            hotkey: Hotkey::from_str(config.get_hotkey()).unwrap_or_else(|err| {
+                error!("Invalid hotkey in config, using the default one: {}", err);
+                Hotkey::default()
+            }),
+            action_hotkeys: {
+                let mut action_hotkeys = HotkeyMap::new();
+                for (action, hotkey) in [
+                    (HotkeyAction::ExpandMacro, config.get_macro_expand_hotkey()),
+                    (
+                        HotkeyAction::TransformWord,
+                        config.get_transform_word_hotkey(),
+                    ),
+                    (
+                        HotkeyAction::TransformSelection,
+                        config.get_transform_selection_hotkey(),
+                    ),
+                ] {
+                    if hotkey.is_empty() {
+                        continue;
+                    }
+                    match Hotkey::from_str(hotkey) {
+                        Ok(hotkey) => action_hotkeys.set(action, Some(hotkey)),
+                        Err(err) => error!("Invalid {:?} hotkey in config: {}", action, err),
+                    }
+                }
+                action_hotkeys
+            },
+            globe_key_behavior: GlobeKeyBehavior::from_str(config.get_globe_key_behavior())
+                .unwrap(),
             enabled: true,
             should_track: true,
             previous_word: String::new(),
             active_app: String::new(),
-            is_macro_enabled: config.is_macro_enabled(),
+            is_ignored_app: false,
+            is_fast_mode_app: false,
+            is_alt_passthrough_app: false,
+            latency_tracker: LatencyTracker::new(),
+            selection_metrics: SelectionMetrics::new(),
+            features: {
+                let mut features = config
+                    .get_engine_features()
+                    .map(EngineFeatures::from_names)
+                    .unwrap_or_default();
+                features.set(EngineFeatures::MACROS, config.is_macro_enabled());
+                features
+            },
+            sentence_position: SentencePosition::Middle,
+            committed_words: Vec::new(),
             macro_table: config.get_macro_table().clone(),
+            template_macros: config.get_template_macros().clone(),
+            macro_conflict_behavior: MacroConflictBehavior::from_str(
+                config.get_macro_conflict_behavior(),
+            )
+            .unwrap(),
+            allowed_words: WordList::new(config.get_allowed_words().iter().cloned()),
+            user_dictionary: if config.is_in_memory() {
+                WordList::default()
+            } else {
+                WordList::load_user_dictionary()
+            },
+            spelling_rules: get_spelling_rules(config.get_spelling_rules()),
             temporary_disabled: false,
             previous_modifiers: KeyModifier::empty(),
+            is_restore_pending: false,
             is_auto_toggle_enabled: config.is_auto_toggle_enabled(),
+            unknown_app_mode: UnknownAppMode::from_str(config.get_unknown_app_mode()).unwrap(),
+            is_auto_learn_enabled: config.is_auto_learn_enabled(),
             is_gox_mode_enabled: config.is_gox_mode_enabled(),
+            is_low_power_enabled: config.is_low_power_enabled(),
+            is_on_battery: false,
+            is_safe_mode: false,
+            is_debug_indicator_enabled: config.is_debug_indicator_enabled(),
+            reported_debug_state: None,
         }
     }
 
     pub fn update_active_app(&mut self) -> Option<()> {
-        let current_active_app = get_active_app_name();
+        self.set_active_app(get_active_app_name())
+    }
+
+    pub fn set_active_app(&mut self, app: String) -> Option<()> {
         // Only check if switch app
-        if current_active_app == self.active_app {
+        if app == self.active_app {
             return None;
         }
-        self.active_app = current_active_app;
+        self.active_app = app;
+        trace::on_app_changed(&self.active_app);
+        self.new_word();
+        self.latency_tracker.reset();
+        self.apply_active_app_policy(self.is_auto_toggle_enabled);
+        Some(())
+    }
+
+    fn apply_active_app_policy(&mut self, should_switch_mode: bool) {
         let config = CONFIG_MANAGER.lock().unwrap();
-        // Only switch the input mode if we found the app in the config
-        if config.is_vietnamese_app(&self.active_app) {
-            self.enabled = true;
+        let policy = config.get_app_policy(&self.active_app);
+        self.is_ignored_app = policy == AppPolicy::Ignored;
+        self.is_fast_mode_app = config.is_fast_mode_app(&self.active_app);
+        self.is_alt_passthrough_app = config.is_alt_passthrough_app(&self.active_app);
+        if should_switch_mode {
+            if let Some(enabled) = get_app_input_mode(policy, self.unknown_app_mode) {
+                self.enabled = enabled;
+            }
         }
-        if config.is_english_app(&self.active_app) {
-            self.enabled = false;
+    }
+
+    pub fn get_active_app(&self) -> &str {
+        &self.active_app
+    }
+
+    pub fn get_active_app_policy(&self) -> AppPolicy {
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .get_app_policy(&self.active_app)
+    }
+
+    pub fn set_active_app_policy(&mut self, policy: AppPolicy) {
+        if CONFIG_MANAGER.lock().unwrap().is_app_policy_locked() {
+            return;
         }
-        Some(())
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_app_policy(&self.active_app, policy);
+        // The policy takes effect right away on the current app
+        self.new_word();
+        self.apply_active_app_policy(true);
+    }
+
+    pub fn set_app_policy(&mut self, app: &str, policy: AppPolicy) {
+        if app == self.active_app {
+            self.set_active_app_policy(policy);
+        } else {
+            CONFIG_MANAGER.lock().unwrap().set_app_policy(app, policy);
+        }
+    }
+
+    pub fn is_ignored_app(&self) -> bool {
+        self.is_ignored_app
     }
 
     pub fn set_temporary_disabled(&mut self) {
@@ -236,13 +654,84 @@ impl InputState {
             self.temporary_disabled = false;
         }
         self.should_track = true;
+        self.committed_words.clear();
+    }
+
+    // Same as new_word, but the word is kept for multi-word macros. Only
+    // words separated by a single space are kept.
+    pub fn commit_word(&mut self) {
+        let word = self.display_buffer.clone();
+        let mut committed_words = std::mem::take(&mut self.committed_words);
+        self.new_word();
+        if word.is_empty() {
+            return;
+        }
+        committed_words.push(word);
+        if committed_words.len() >= MAX_MACRO_WORDS {
+            committed_words.remove(0);
+        }
+        self.committed_words = committed_words;
     }
 
-    pub fn get_macro_target(&self) -> Option<&String> {
-        if !self.is_macro_enabled {
+    /// Returns the macro target and the number of characters it replaces
+    /// before the current word. Longer phrases win over shorter ones, and
+    /// the placeholders of template macros are expanded.
+    pub fn get_macro_target(&self) -> Option<(String, usize)> {
+        if !self.is_feature_enabled(EngineFeatures::MACROS) {
             return None;
         }
-        self.macro_table.get(&self.display_buffer)
+        self.find_macro_target()
+    }
+
+    // For the macro expand hotkey, which works even with macros turned off
+    pub fn get_forced_macro_target(&self) -> Option<(String, usize)> {
+        self.find_macro_target()
+    }
+
+    fn find_macro_target(&self) -> Option<(String, usize)> {
+        let word_count = self.committed_words.len();
+        (0..=word_count).rev().find_map(|count| {
+            let previous_words = &self.committed_words[word_count - count..];
+            let phrase = previous_words
+                .iter()
+                .map(String::as_str)
+                .chain([self.display_buffer.as_str()])
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let target = self.macro_table.get(&phrase)?;
+            let target = match self.template_macros.contains(&phrase) {
+                true => expand_template(target, self.locale, &LocalDateTime::now()),
+                false => target.clone(),
+            };
+            // Each previous word is followed by a space
+            let previous_length = previous_words
+                .iter()
+                .map(|word| word.chars().count() + 1)
+                .sum();
+            Some((target, previous_length))
+        })
+    }
+
+    // Only the last word of a trigger is checked against the dictionary
+    pub fn should_expand_macro(&self, keycode: char) -> bool {
+        keycode == KEY_TAB
+            || self.macro_conflict_behavior == MacroConflictBehavior::Expand
+            || !self.is_valid_word()
+    }
+
+    pub fn get_macro_conflict_behavior(&self) -> MacroConflictBehavior {
+        self.macro_conflict_behavior
+    }
+
+    pub fn set_macro_conflict_behavior(&mut self, behavior: MacroConflictBehavior) {
+        if is_setting_locked(MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY) {
+            return;
+        }
+        self.macro_conflict_behavior = behavior;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_macro_conflict_behavior(&behavior.to_string());
     }
 
     pub fn get_typing_buffer(&self) -> &str {
@@ -256,21 +745,30 @@ impl InputState {
     pub fn stop_tracking(&mut self) {
         self.clear();
         self.should_track = false;
+        // The rest of the word is not known, so the words before it can't
+        // be part of a trigger anymore
+        self.committed_words.clear();
     }
 
     pub fn toggle_vietnamese(&mut self) {
         self.enabled = !self.enabled;
         self.temporary_disabled = false;
-        let mut config = CONFIG_MANAGER.lock().unwrap();
-        if self.enabled {
-            config.add_vietnamese_app(&self.active_app);
-        } else {
-            config.add_english_app(&self.active_app);
+        self.committed_words.clear();
+        if self.is_auto_learn_enabled {
+            let mut config = CONFIG_MANAGER.lock().unwrap();
+            if self.enabled {
+                config.add_vietnamese_app(&self.active_app);
+            } else {
+                config.add_english_app(&self.active_app);
+            }
         }
         self.new_word();
     }
 
     pub fn set_method(&mut self, method: TypingMethod) {
+        if is_setting_locked(TYPING_METHOD_CONFIG_KEY) {
+            return;
+        }
         self.method = method;
         self.new_word();
         CONFIG_MANAGER
@@ -286,23 +784,94 @@ impl InputState {
         self.method
     }
 
-    pub fn set_hotkey(&mut self, key_sequence: &str) {
-        self.hotkey = Hotkey::from_str(key_sequence);
+    pub fn get_locale(&self) -> Locale {
+        self.locale
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) {
+        if is_setting_locked(LOCALE_CONFIG_KEY) {
+            return;
+        }
+        self.locale = locale;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_locale(&locale.to_string());
+    }
+
+    pub fn set_hotkey(&mut self, key_sequence: &str) -> Result<(), HotkeyParseError> {
+        if is_setting_locked(HOTKEY_CONFIG_KEY) {
+            return Err(HotkeyParseError::Locked);
+        }
+        self.hotkey = Hotkey::from_str(key_sequence)?;
         CONFIG_MANAGER.lock().unwrap().set_hotkey(key_sequence);
         if let Some(event_sink) = UI_EVENT_SINK.get() {
             _ = event_sink.submit_command(UPDATE_UI, (), Target::Auto);
         }
+        Ok(())
     }
 
     pub fn get_hotkey(&self) -> &Hotkey {
         &self.hotkey
     }
 
+    pub fn get_action_hotkeys(&self) -> &HotkeyMap {
+        &self.action_hotkeys
+    }
+
+    /// Binds the action to a hotkey written as in the config, an empty
+    /// string unbinds it.
+    pub fn set_action_hotkey(
+        &mut self,
+        action: HotkeyAction,
+        key_sequence: &str,
+    ) -> Result<(), HotkeyParseError> {
+        let config_key = match action {
+            HotkeyAction::ExpandMacro => MACRO_EXPAND_HOTKEY_CONFIG_KEY,
+            HotkeyAction::TransformWord => TRANSFORM_WORD_HOTKEY_CONFIG_KEY,
+            HotkeyAction::TransformSelection => TRANSFORM_SELECTION_HOTKEY_CONFIG_KEY,
+        };
+        if is_setting_locked(config_key) {
+            return Err(HotkeyParseError::Locked);
+        }
+        let hotkey = if key_sequence.is_empty() {
+            None
+        } else {
+            Some(Hotkey::from_str(key_sequence)?)
+        };
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        match action {
+            HotkeyAction::ExpandMacro => config.set_macro_expand_hotkey(key_sequence),
+            HotkeyAction::TransformWord => config.set_transform_word_hotkey(key_sequence),
+            HotkeyAction::TransformSelection => config.set_transform_selection_hotkey(key_sequence),
+        }
+        self.action_hotkeys.set(action, hotkey);
+        Ok(())
+    }
+
+    pub fn get_globe_key_behavior(&self) -> GlobeKeyBehavior {
+        self.globe_key_behavior
+    }
+
+    pub fn set_globe_key_behavior(&mut self, behavior: GlobeKeyBehavior) {
+        if is_setting_locked(GLOBE_KEY_BEHAVIOR_CONFIG_KEY) {
+            return;
+        }
+        self.globe_key_behavior = behavior;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_globe_key_behavior(&behavior.to_string());
+    }
+
     pub fn is_auto_toggle_enabled(&self) -> bool {
         self.is_auto_toggle_enabled
     }
 
     pub fn toggle_auto_toggle(&mut self) {
+        if is_setting_locked(AUTOS_TOGGLE_ENABLED_CONFIG_KEY) {
+            return;
+        }
         self.is_auto_toggle_enabled = !self.is_auto_toggle_enabled;
         CONFIG_MANAGER
             .lock()
@@ -310,16 +879,227 @@ impl InputState {
             .set_auto_toggle_enabled(self.is_auto_toggle_enabled);
     }
 
+    pub fn get_unknown_app_mode(&self) -> UnknownAppMode {
+        self.unknown_app_mode
+    }
+
+    pub fn set_unknown_app_mode(&mut self, mode: UnknownAppMode) {
+        if is_setting_locked(UNKNOWN_APP_MODE_CONFIG_KEY) {
+            return;
+        }
+        self.unknown_app_mode = mode;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_unknown_app_mode(&mode.to_string());
+    }
+
+    pub fn is_auto_learn_enabled(&self) -> bool {
+        self.is_auto_learn_enabled
+    }
+
+    pub fn toggle_auto_learn(&mut self) {
+        if is_setting_locked(AUTO_LEARN_APPS_CONFIG_KEY) {
+            return;
+        }
+        self.is_auto_learn_enabled = !self.is_auto_learn_enabled;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_auto_learn_enabled(self.is_auto_learn_enabled);
+    }
+
+    pub fn is_low_power_enabled(&self) -> bool {
+        self.is_low_power_enabled
+    }
+
+    pub fn toggle_low_power_enabled(&mut self) {
+        if is_setting_locked(LOW_POWER_ENABLED_CONFIG_KEY) {
+            return;
+        }
+        self.is_low_power_enabled = !self.is_low_power_enabled;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_low_power_enabled(self.is_low_power_enabled);
+        self.refresh_power_state();
+    }
+
+    // Low power mode only kicks in when the user opted in and the machine
+    // is running on battery.
+    pub fn is_low_power_mode(&self) -> bool {
+        self.is_low_power_enabled && self.is_on_battery
+    }
+
+    // The Accessibility API checks are skipped in low power mode, in safe
+    // mode and in the apps where the user enabled fast mode.
+    pub fn should_skip_ax_checks(&self) -> bool {
+        self.is_low_power_mode() || self.is_fast_mode_app || self.is_safe_mode
+    }
+
+    pub fn is_alt_passthrough_app(&self) -> bool {
+        self.is_alt_passthrough_app
+    }
+
+    pub fn set_alt_passthrough_app(&mut self, app: &str, is_passthrough: bool) {
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        config.set_alt_passthrough_app(app, is_passthrough);
+        if app == self.active_app {
+            self.is_alt_passthrough_app = config.is_alt_passthrough_app(app);
+        }
+    }
+
+    /// Starts over from the config, keeping the input mode, the active app
+    /// and what was decided at launch.
+    pub fn reset(&mut self) {
+        let is_safe_mode = self.is_safe_mode;
+        let enabled = self.enabled;
+        let active_app = mem::take(&mut self.active_app);
+        *self = Self::new();
+        self.is_safe_mode = is_safe_mode;
+        self.enabled = enabled;
+        self.active_app = active_app;
+        self.apply_active_app_policy(false);
+        self.refresh_power_state();
+    }
+
+    pub fn is_debug_indicator_enabled(&self) -> bool {
+        self.is_debug_indicator_enabled
+    }
+
+    pub fn toggle_debug_indicator(&mut self) {
+        if is_setting_locked(DEBUG_INDICATOR_CONFIG_KEY) {
+            return;
+        }
+        self.is_debug_indicator_enabled = !self.is_debug_indicator_enabled;
+        self.reported_debug_state = None;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_debug_indicator_enabled(self.is_debug_indicator_enabled);
+    }
+
+    // Whether the word is still tracked, and how many keys are buffered
+    pub fn get_debug_state(&self) -> (bool, usize) {
+        (self.should_track, self.buffer.chars().count())
+    }
+
+    // The debug state, only when the indicator is on and it changed since
+    // the last call, so the UI isn't woken up on every key
+    pub fn take_debug_state_change(&mut self) -> Option<(bool, usize)> {
+        if !self.is_debug_indicator_enabled {
+            return None;
+        }
+        let state = self.get_debug_state();
+        if self.reported_debug_state == Some(state) {
+            return None;
+        }
+        self.reported_debug_state = Some(state);
+        Some(state)
+    }
+
+    // Safe mode only keeps the Telex/VNI transformation: macros and the
+    // workarounds for specific apps are turned off, without touching the config.
+    pub fn enable_safe_mode(&mut self) {
+        self.is_safe_mode = true;
+    }
+
+    // What the user chose, regardless of safe mode
+    pub fn get_features(&self) -> EngineFeatures {
+        self.features
+    }
+
+    pub fn is_feature_enabled(&self, feature: EngineFeatures) -> bool {
+        !self.is_safe_mode && self.features.contains(feature)
+    }
+
+    pub fn set_feature_enabled(&mut self, feature: EngineFeatures, enabled: bool) {
+        if feature == EngineFeatures::MACROS {
+            if self.is_macro_enabled() != enabled {
+                self.toggle_macro_enabled();
+            }
+            return;
+        }
+        if is_setting_locked(ENGINE_FEATURES_CONFIG_KEY) {
+            return;
+        }
+        self.features.set(feature, enabled);
+        if feature == EngineFeatures::ENGLISH_DICTIONARY && enabled {
+            validation::preload_english_words();
+        }
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_engine_features(self.features.to_names());
+    }
+
+    // Edits to the user dictionary are picked up when switching apps
+    pub fn reload_user_dictionary(&mut self) {
+        if !CONFIG_MANAGER.lock().unwrap().is_in_memory() {
+            self.user_dictionary = WordList::load_user_dictionary();
+        }
+    }
+
+    // Auto-capitalize only applies to the first letter after a sentence end
+    pub fn should_capitalize(&self) -> bool {
+        self.is_feature_enabled(EngineFeatures::AUTO_CAPITALIZE)
+            && self.sentence_position == SentencePosition::Start
+            && self.buffer.is_empty()
+    }
+
+    pub fn track_sentence_end(&mut self, c: char, modifiers: KeyModifier) {
+        self.sentence_position = get_sentence_position(self.sentence_position, c, modifiers);
+    }
+
+    /// Returns true if typing in the active app is slow enough
+    /// to suggest fast mode to the user.
+    pub fn record_key_latency(&mut self, elapsed: Duration) -> bool {
+        if self.should_skip_ax_checks() {
+            return false;
+        }
+        self.latency_tracker.record(&self.active_app, elapsed)
+    }
+
+    pub fn enable_fast_mode(&mut self, app: &str) {
+        CONFIG_MANAGER.lock().unwrap().add_fast_mode_app(app);
+        if app == self.active_app {
+            self.is_fast_mode_app = true;
+        }
+    }
+
+    pub fn disable_fast_mode(&mut self, app: &str) {
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        config.remove_fast_mode_app(app);
+        if app == self.active_app {
+            self.is_fast_mode_app = config.is_fast_mode_app(app);
+        }
+    }
+
+    // Querying the power source is not free, so we only do this on app
+    // switches instead of on every keystroke.
+    pub fn refresh_power_state(&mut self) {
+        let default_log_level = *DEFAULT_LOG_LEVEL;
+        self.is_on_battery = is_on_battery_power();
+        if self.is_low_power_mode() {
+            log::set_max_level(default_log_level.min(LevelFilter::Warn));
+        } else {
+            log::set_max_level(default_log_level);
+        }
+    }
+
     pub fn is_macro_enabled(&self) -> bool {
-        self.is_macro_enabled
+        self.features.contains(EngineFeatures::MACROS)
     }
 
     pub fn toggle_macro_enabled(&mut self) {
-        self.is_macro_enabled = !self.is_macro_enabled;
+        if is_setting_locked(MACRO_ENABLED_CONFIG_KEY) {
+            return;
+        }
+        self.features.toggle(EngineFeatures::MACROS);
         CONFIG_MANAGER
             .lock()
             .unwrap()
-            .set_macro_enabled(self.is_macro_enabled);
+            .set_macro_enabled(self.is_macro_enabled());
     }
 
     pub fn get_macro_table(&self) -> &BTreeMap<String, String> {
@@ -327,11 +1107,38 @@ impl InputState {
     }
 
     pub fn delete_macro(&mut self, from: &String) {
+        if is_setting_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
         self.macro_table.remove(from);
+        self.template_macros.retain(|trigger| trigger != from);
         CONFIG_MANAGER.lock().unwrap().delete_macro(from);
     }
 
+    pub fn get_template_macros(&self) -> &Vec<String> {
+        &self.template_macros
+    }
+
+    // Placeholders like {date} are only expanded in the macros marked as
+    // templates, other macros are typed as they were saved
+    pub fn set_template_macro(&mut self, from: &str, is_template: bool) {
+        if is_setting_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
+        self.template_macros.retain(|trigger| trigger != from);
+        if is_template {
+            self.template_macros.push(from.to_string());
+        }
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_template_macro(from, is_template);
+    }
+
     pub fn add_macro(&mut self, from: String, to: String) {
+        if is_setting_locked(MACROS_CONFIG_KEY) {
+            return;
+        }
         CONFIG_MANAGER
             .lock()
             .unwrap()
@@ -339,21 +1146,48 @@ impl InputState {
         self.macro_table.insert(from, to);
     }
 
+    // Settings brought over from another input method, see migration.rs
+    pub fn import_settings(&mut self, settings: &ImportedSettings) {
+        if let Some(method) = settings.method {
+            self.set_method(method);
+        }
+        if let Some(hotkey) = &settings.hotkey {
+            if let Err(err) = self.set_hotkey(hotkey) {
+                error!("Invalid imported hotkey {}: {}", hotkey, err);
+            }
+        }
+        for (from, to) in &settings.macros {
+            self.add_macro(from.clone(), to.clone());
+        }
+    }
+
     pub fn should_transform_keys(&self, c: &char) -> bool {
         self.enabled
     }
 
     pub fn transform_keys(&self) -> Result<(String, TransformResult), ()> {
-        let transform_method = match self.method {
+        self.transform_text(&self.buffer, self.method)
+    }
+
+    fn transform_text(
+        &self,
+        text: &str,
+        method: TypingMethod,
+    ) -> Result<(String, TransformResult), ()> {
+        let transform_method = match method {
             TypingMethod::VNI => vi::vni::transform_buffer,
             TypingMethod::Telex => vi::telex::transform_buffer,
         };
         let result = std::panic::catch_unwind(|| {
             let mut output = String::new();
-            let transform_result = transform_method(self.buffer.chars(), &mut output);
+            let transform_result = transform_method(text.chars(), &mut output);
             (output, transform_result)
         });
         if let Ok((output, transform_result)) = result {
+            let output = resolve_d_stroke_case(text, output);
+            if self.is_feature_enabled(EngineFeatures::OLD_TONE_STYLE) {
+                return Ok((to_old_tone_style(&output), transform_result));
+            }
             return Ok((output, transform_result));
         }
         Err(())
@@ -364,26 +1198,103 @@ impl InputState {
     }
 
     pub fn should_dismiss_selection_if_needed(&self) -> bool {
-        return self.active_app.contains("Firefox");
+        return self.is_feature_enabled(EngineFeatures::FIREFOX_WORKAROUND)
+            && compat::needs_selection_dismissal(&self.active_app);
     }
 
-    pub fn get_backspace_count(&self, is_delete: bool) -> usize {
-        let dp_len = self.display_buffer.chars().count();
-        let backspace_count = if is_delete && dp_len >= 1 {
-            dp_len
-        } else {
-            dp_len - 1
-        };
+    // Selected text, then the selected range, then a guess for the app
+    fn is_in_text_selection(&mut self, text_selection: Option<(bool, SelectionSource)>) -> bool {
+        let (is_selected, source) = text_selection.unwrap_or_else(|| {
+            (
+                compat::assume_text_selection(&self.active_app),
+                SelectionSource::Heuristic,
+            )
+        });
+        self.selection_metrics.record(&self.active_app, source);
+        is_selected
+    }
 
+    // Latin letters typed into CJK or RTL text are rarely meant as Vietnamese
+    pub fn is_foreign_script_context(
+        &self,
+        get_text_before_caret: impl FnOnce(usize) -> Option<String>,
+    ) -> bool {
+        self.is_feature_enabled(EngineFeatures::FOREIGN_SCRIPT_PASSTHROUGH)
+            && get_text_before_caret(compat::SCRIPT_CONTEXT_LENGTH)
+                .is_some_and(|text| compat::is_foreign_script_context(&text))
+    }
+
+    pub fn get_selection_metrics(&self) -> &SelectionMetrics {
+        &self.selection_metrics
+    }
+
+    pub fn get_backspace_count(
+        &mut self,
+        is_delete: bool,
+        get_text_selection: impl FnOnce() -> Option<(bool, SelectionSource)>,
+    ) -> usize {
         // Add an extra backspace to compensate the initial text selection deletion.
         // This is useful in applications like chrome, where the URL bar uses text selection
         // for autocompletion, causing the first backspace to delete the selection instead of
         // the character behind the cursor.
-        if is_in_text_selection() {
-            backspace_count + 1
-        } else {
-            backspace_count
+        // The Accessibility API call is skipped in low power mode and fast mode.
+        let is_in_selection = self.is_feature_enabled(EngineFeatures::SELECTION_COMPENSATION)
+            && !self.should_skip_ax_checks()
+            && self.is_in_text_selection(get_text_selection());
+        compute_backspace_count(
+            self.display_buffer.chars().count(),
+            is_delete,
+            is_in_selection,
+        )
+    }
+
+    // Closing characters an auto-pairing editor put between the word on
+    // screen and the caret. Only checked in the editors known to do this.
+    pub fn get_auto_inserted_suffix(
+        &self,
+        get_text_before_caret: impl FnOnce(usize) -> Option<String>,
+    ) -> String {
+        if self.should_skip_ax_checks() || !compat::is_auto_pair_app(&self.active_app) {
+            return String::new();
         }
+        // The key being processed is not on screen yet
+        let mut word_on_screen = self.display_buffer.clone();
+        word_on_screen.pop();
+        get_text_before_caret(word_on_screen.chars().count() + MAX_AUTO_INSERTED_LENGTH)
+            .map(|text| compat::get_auto_inserted_suffix(&text, &word_on_screen).to_string())
+            .unwrap_or_default()
+    }
+
+    /// The word under the caret, as its parts before and after the caret,
+    /// and what it becomes when typed again. For words typed while gõkey
+    /// was off.
+    pub fn get_caret_word_transform(
+        &self,
+        get_text_around_caret: impl FnOnce(usize) -> Option<(String, String)>,
+    ) -> Option<(String, String, String)> {
+        let (before, after) = get_text_around_caret(MAX_POSSIBLE_WORD_LENGTH + 1)?;
+        let (head, tail) = get_caret_word(&before, &after)?;
+        let word = format!("{}{}", head, tail);
+        let (output, _) = self.transform_text(&word, self.method).ok()?;
+        (output != word).then(|| (head.to_string(), tail.to_string(), output))
+    }
+
+    // The selected text and what it becomes when every word is typed again,
+    // with Telex or VNI picked for each word. Words that don't give a valid
+    // word, like English words and file names, are kept as they are.
+    pub fn get_selection_transform(
+        &self,
+        get_selected_text: impl FnOnce() -> Option<String>,
+    ) -> Option<(String, String)> {
+        let text = get_selected_text()?;
+        let output = transform_words(&text, |word| {
+            self.transform_text(word, detect_typing_method(word))
+                .ok()
+                .map(|(output, _)| output)
+                .filter(|output| self.is_valid_typed_word(word, output))
+                .unwrap_or_else(|| word.to_string())
+        });
+        (output != text).then_some((text, output))
     }
 
     pub fn replace(&mut self, buf: String) {
@@ -397,6 +1308,7 @@ impl InputState {
                 self.display_buffer.remove(0);
             }
         }
+        self.sentence_position = SentencePosition::Middle;
         if self.buffer.len() <= MAX_POSSIBLE_WORD_LENGTH {
             self.buffer.push(c);
             self.display_buffer.push(c);
@@ -410,6 +1322,8 @@ impl InputState {
     pub fn pop(&mut self) {
         self.display_buffer.pop();
         self.buffer = self.display_buffer.clone();
+        // Deleting back into the space or a committed word changes the words
+        // kept for multi-word macros, so they are dropped with new_word
         if self.buffer.is_empty() {
             self.new_word();
         }
@@ -475,8 +1389,281 @@ impl InputState {
         self.previous_modifiers = modifiers;
     }
 
-    pub fn is_allowed_word(&self, word: &str) -> bool {
-        let config = CONFIG_MANAGER.lock().unwrap();
-        return config.is_allowed_word(word);
+    pub fn set_restore_pending(&mut self, is_restore_pending: bool) {
+        self.is_restore_pending = is_restore_pending;
+    }
+
+    pub fn is_restore_pending(&self) -> bool {
+        self.is_restore_pending
+    }
+
+    // The preferred spelling of the finished word, if it's a known slip
+    pub fn get_spelling_correction(&self) -> Option<String> {
+        if !self.is_feature_enabled(EngineFeatures::SPELLING_NORMALIZATION) {
+            return None;
+        }
+        normalize_spelling(&self.display_buffer, &self.spelling_rules)
     }
+
+    pub fn is_valid_word(&self) -> bool {
+        self.is_valid_typed_word(&self.buffer, &self.display_buffer)
+    }
+
+    // Strict mode only warns about misspelled Vietnamese, not about words
+    // restored because they were meant as typed, like English words
+    pub fn is_misspelled_word(&self) -> bool {
+        let english_dictionary = EnglishDictionary::system();
+        let english_dictionary = self
+            .is_feature_enabled(EngineFeatures::ENGLISH_DICTIONARY)
+            .then_some(&english_dictionary);
+        !self.is_valid_word() && !validation::is_word_as_typed(english_dictionary, &self.buffer)
+    }
+
+    // The user's own lists come first, then English, then the Vietnamese rules
+    fn is_valid_typed_word(&self, typed: &str, word: &str) -> bool {
+        let english_dictionary = EnglishDictionary::system();
+        let mut validators: Vec<&dyn WordValidator> =
+            vec![&self.allowed_words, &self.user_dictionary];
+        if self.is_feature_enabled(EngineFeatures::ENGLISH_DICTIONARY) {
+            validators.push(&english_dictionary);
+        }
+        validators.push(&VietnameseValidator);
+        validation::is_valid_word(&validators, typed, word)
+    }
+}
+
+#[test]
+fn test_app_input_mode() {
+    use UnknownAppMode::*;
+    for mode in [KeepCurrent, Vietnamese, English] {
+        // The lists always win over the default mode
+        assert_eq!(get_app_input_mode(AppPolicy::Vietnamese, mode), Some(true));
+        assert_eq!(get_app_input_mode(AppPolicy::English, mode), Some(false));
+        assert_eq!(get_app_input_mode(AppPolicy::Ignored, mode), None);
+    }
+    assert_eq!(get_app_input_mode(AppPolicy::Default, KeepCurrent), None);
+    assert_eq!(
+        get_app_input_mode(AppPolicy::Default, Vietnamese),
+        Some(true)
+    );
+    assert_eq!(get_app_input_mode(AppPolicy::Default, English), Some(false));
+}
+
+#[test]
+fn test_sentence_position() {
+    let get_position = |text: &str| {
+        text.chars().fold(SentencePosition::Middle, |position, c| {
+            get_sentence_position(position, c, KeyModifier::new())
+        })
+    };
+    assert_eq!(get_position("a. "), SentencePosition::Start);
+    assert_eq!(get_position("a!  "), SentencePosition::Start);
+    assert_eq!(get_position("(a?) "), SentencePosition::Start);
+    assert_eq!(get_position("a.\x13"), SentencePosition::Start);
+    assert_eq!(get_position("a.b"), SentencePosition::Middle);
+    assert_eq!(get_position("a."), SentencePosition::AfterEnd);
+    assert_eq!(get_position("a, "), SentencePosition::Middle);
+}
+
+#[test]
+fn test_resolve_d_stroke_case() {
+    let resolve = |buffer: &str, output: &str| resolve_d_stroke_case(buffer, output.to_string());
+    assert_eq!(resolve("dd", "đ"), "đ");
+    assert_eq!(resolve("DD", "Đ"), "Đ");
+    assert_eq!(resolve("Dd", "đ"), "Đ");
+    assert_eq!(resolve("dD", "Đ"), "đ");
+    assert_eq!(resolve("Ddi", "đi"), "Đi");
+    assert_eq!(resolve("DDI", "ĐI"), "ĐI");
+    assert_eq!(resolve("dDaau", "Đâu"), "đâu");
+    // VNI
+    assert_eq!(resolve("D9i", "đi"), "Đi");
+    // Nothing to resolve without a stroke
+    assert_eq!(resolve("Ddd", "Dd"), "Dd");
+    assert_eq!(resolve("", ""), "");
+}
+
+#[test]
+fn test_selection_transform() {
+    let state = InputState::new();
+    let transform = |text: &str| {
+        state
+            .get_selection_transform(|| Some(text.to_string()))
+            .map(|(_, output)| output)
+    };
+    assert_eq!(
+        transform("xin chaof, vieejt nam vie65t"),
+        Some("xin chào, việt nam việt".to_string())
+    );
+    // Not Vietnamese, and nothing that the validators accept
+    assert_eq!(transform("file1 mp3 class 2024"), None);
+    assert_eq!(
+        transform("class tieengs vieejt"),
+        Some("class tiếng việt".to_string())
+    );
+    assert_eq!(transform(""), None);
+}
+
+#[test]
+fn test_multi_word_macro() {
+    let mut state = InputState::new();
+    state.features.insert(EngineFeatures::MACROS);
+    state.macro_table = BTreeMap::from([
+        ("ha noi".to_string(), "Hà Nội".to_string()),
+        ("noi".to_string(), "nói".to_string()),
+    ]);
+    let type_words = |state: &mut InputState, words: &[&str]| {
+        state.new_word();
+        for (index, word) in words.iter().enumerate() {
+            if index > 0 {
+                state.commit_word();
+            }
+            word.chars().for_each(|c| state.push(c));
+        }
+    };
+    let target = "Hà Nội".to_string();
+
+    type_words(&mut state, &["ha", "noi"]);
+    assert_eq!(state.get_macro_target(), Some((target.clone(), 3)));
+    // "ha noi" on screen, the previous word and its space come first
+    assert_eq!(state.get_backspace_count(true, || None) + 3, 6);
+    type_words(&mut state, &["xin", "ha", "noi"]);
+    assert_eq!(state.get_macro_target(), Some((target.clone(), 3)));
+    // The longest trigger wins, a single word still matches on its own
+    type_words(&mut state, &["noi"]);
+    assert_eq!(state.get_macro_target(), Some(("nói".to_string(), 0)));
+
+    // Deleting the space joins "noi" to "ha" on screen
+    type_words(&mut state, &["ha", ""]);
+    state.pop();
+    "noi".chars().for_each(|c| state.push(c));
+    assert_eq!(state.get_macro_target(), Some(("nói".to_string(), 0)));
+    type_words(&mut state, &["ha", "no"]);
+    state.pop();
+    state.pop();
+    state.pop();
+    "noi".chars().for_each(|c| state.push(c));
+    assert_eq!(state.get_macro_target(), Some(("nói".to_string(), 0)));
+
+    // Words typed without tracking or in the other mode are not known
+    type_words(&mut state, &["ha", "noi"]);
+    state.stop_tracking();
+    "noi".chars().for_each(|c| state.push(c));
+    assert_eq!(state.get_macro_target(), Some(("nói".to_string(), 0)));
+    type_words(&mut state, &["ha", ""]);
+    state.toggle_vietnamese();
+    state.toggle_vietnamese();
+    "noi".chars().for_each(|c| state.push(c));
+    assert_eq!(state.get_macro_target(), Some(("nói".to_string(), 0)));
+}
+
+#[test]
+fn test_template_macro() {
+    let mut state = InputState::new();
+    state.features.insert(EngineFeatures::MACROS);
+    state.locale = Locale::English;
+    state.macro_table = BTreeMap::from([
+        ("tong".to_string(), "{=2*3}".to_string()),
+        ("ngoac".to_string(), "{=2*3}".to_string()),
+    ]);
+    state.template_macros = vec!["tong".to_string()];
+    let target = |state: &mut InputState, word: &str| {
+        state.new_word();
+        word.chars().for_each(|c| state.push(c));
+        state.get_macro_target()
+    };
+    assert_eq!(target(&mut state, "tong"), Some(("6".to_string(), 0)));
+    // Saved macros are typed as they are unless marked as templates
+    assert_eq!(target(&mut state, "ngoac"), Some(("{=2*3}".to_string(), 0)));
+}
+
+#[test]
+fn test_get_caret_word() {
+    assert_eq!(get_caret_word("Xin chao", ""), Some(("chao", "")));
+    assert_eq!(get_caret_word("(truong", ") "), Some(("truong", "")));
+    assert_eq!(get_caret_word("vie65t", ""), Some(("vie65t", "")));
+    assert_eq!(get_caret_word("Xin vie", "ejt nam"), Some(("vie", "ejt")));
+    assert_eq!(get_caret_word("chao ", "vieejt"), Some(("", "vieejt")));
+    assert_eq!(get_caret_word("trường", ""), None);
+    assert_eq!(get_caret_word("vie", "ejtư"), None);
+    assert_eq!(get_caret_word("chao ", " "), None);
+    assert_eq!(get_caret_word("", ""), None);
+    assert_eq!(get_caret_word("abcdef", "ghijk"), None);
+}
+
+#[test]
+fn test_caret_word_transform() {
+    let mut state = InputState::new();
+    state.method = TypingMethod::Telex;
+    let transform = |before: &str, after: &str| {
+        state.get_caret_word_transform(|_| Some((before.to_string(), after.to_string())))
+    };
+    assert_eq!(
+        transform("Xin vie", "ejt nam"),
+        Some(("vie".to_string(), "ejt".to_string(), "việt".to_string()))
+    );
+    assert_eq!(
+        transform("Xin chaof", ""),
+        Some(("chaof".to_string(), String::new(), "chào".to_string()))
+    );
+    // Nothing to change
+    assert_eq!(transform("Xin ", "nam"), None);
+    assert_eq!(transform("", ""), None);
+}
+
+#[test]
+fn test_fast_mode() {
+    let app = "/Applications/Slow.app";
+    let mut state = InputState::new();
+    state.set_active_app(app.to_string());
+    state.enable_fast_mode(app);
+    assert!(state.should_skip_ax_checks());
+    state.disable_fast_mode(app);
+    assert!(!state.should_skip_ax_checks());
+    assert!(!CONFIG_MANAGER.lock().unwrap().is_fast_mode_app(app));
+}
+
+#[test]
+fn test_set_action_hotkey() {
+    let mut state = InputState::new();
+    let ctrl_shift = KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SHIFT;
+    assert_eq!(
+        state.set_action_hotkey(HotkeyAction::TransformWord, "ctrl+shift+w"),
+        Ok(())
+    );
+    assert_eq!(
+        state.get_action_hotkeys().get_action(ctrl_shift, Some('w')),
+        Some(HotkeyAction::TransformWord)
+    );
+    // A bad hotkey keeps the one before
+    assert_eq!(
+        state.set_action_hotkey(HotkeyAction::TransformWord, "ctrl+foo"),
+        Err(HotkeyParseError::UnknownKey("foo".to_string()))
+    );
+    assert_eq!(
+        state.get_action_hotkeys().get_action(ctrl_shift, Some('w')),
+        Some(HotkeyAction::TransformWord)
+    );
+    assert_eq!(
+        state.set_action_hotkey(HotkeyAction::TransformWord, ""),
+        Ok(())
+    );
+    assert_eq!(
+        state.get_action_hotkeys().get_action(ctrl_shift, Some('w')),
+        None
+    );
+}
+
+#[test]
+fn test_transform_words() {
+    assert_eq!(detect_typing_method("vieetj"), TypingMethod::Telex);
+    assert_eq!(detect_typing_method("vie65t"), TypingMethod::VNI);
+    assert_eq!(detect_typing_method("2024"), TypingMethod::Telex);
+    let transform = |text| transform_words(text, |word| format!("<{}>", word));
+    assert_eq!(
+        transform("xin chaof, Vieejt Nam!"),
+        "<xin> <chaof>, <Vieejt> <Nam>!"
+    );
+    assert_eq!(transform("đã xong abcdefghijk"), "đã <xong> abcdefghijk");
+    assert_eq!(transform(""), "");
+    assert_eq!(transform("  \n"), "  \n");
 }
@@ -1,51 +1,209 @@
+mod changelog;
+mod cheatsheet;
+mod compat;
 mod config;
 mod hotkey;
+mod injection;
 mod input;
+mod migration;
 mod platform;
+#[cfg(test)]
+mod regressions;
+mod replay;
+mod safe_mode;
 mod scripting;
+mod security;
+mod settings_server;
+mod spelling;
+mod template;
+mod text_replacement;
+mod trace;
+mod tutorial;
 mod ui;
+mod validation;
 
-use std::thread;
+use std::{
+    env,
+    process::{self, Command},
+    sync::Mutex,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
-use druid::{AppLauncher, ExtEventSink, Target, WindowDesc};
-use input::{rebuild_keyboard_layout_map, HOTKEY_MATCHING_CIRCUIT_BREAK, INPUT_STATE};
-use log::debug;
+use druid::{AppLauncher, Application, ExtEventSink, Target, WindowDesc};
+use input::{rebuild_keyboard_layout_map, INPUT_STATE};
+use log::{debug, error, info, warn};
 use once_cell::sync::OnceCell;
 use platform::{
-    add_app_change_callback, ensure_accessibility_permission, run_event_listener, send_backspace,
-    send_string, EventTapType, Handle, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE,
-    KEY_SPACE, KEY_TAB, RAW_KEY_GLOBE,
+    add_app_change_callback, ensure_accessibility_permission, get_focused_field, get_function_key,
+    get_selected_text, get_text_around_caret, get_text_before_caret, get_text_selection,
+    is_accessibility_trusted, open_accessibility_settings, play_alert_sound, run_event_listener,
+    select_around_caret, send_backspace, send_string, set_selected_text, stop_event_listener,
+    EventTapType, FocusedField, Handle, KeyModifier, PressedKey, SelectionSource,
+    ACCESSIBILITY_SETTINGS_URL, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB,
+    LAUNCH_AT_LOGIN_ARG, RAW_KEY_GLOBE,
 };
 
 use crate::{
-    input::{HOTKEY_MATCHING, HOTKEY_MODIFIERS},
+    config::{ConfigStore, CONFIG_MANAGER},
+    hotkey::{GlobeKeyAction, GlobeKeyTracker, HotkeyAction, HotkeyMatcher},
+    injection::{INJECTION_LIMITER, MAX_CHARS_PER_OPERATION},
+    input::{EngineFeatures, GlobeKeyBehavior, GLOBE_KEY_TRACKER, HOTKEY_MATCHER},
     platform::{RAW_ARROW_DOWN, RAW_ARROW_LEFT, RAW_ARROW_RIGHT, RAW_ARROW_UP},
+    trace::{trace_stage, TraceStages},
+};
+use ui::{
+    UIDataAdapter, APP_CHANGED, SHOW_INJECTION_ERROR, SHOW_LATENCY_WARNING, SHOW_MIGRATION,
+    SHOW_TRAY, SHOW_TUTORIAL, SHOW_UI, SHOW_WHATS_NEW, UPDATE_DEBUG_INDICATOR, UPDATE_UI,
 };
-use ui::{UIDataAdapter, UPDATE_UI};
 
 static UI_EVENT_SINK: OnceCell<ExtEventSink> = OnceCell::new();
+static EVENT_LISTENER_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+const APP_CHANGE_DEBOUNCE_INTERVAL: Duration = Duration::from_millis(150);
+const APP_CHANGE_DEBOUNCE_INTERVAL_LOW_POWER: Duration = Duration::from_millis(300);
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+const NO_TRAY_ARG: &str = "--no-tray";
+const SAFE_MODE_ARG: &str = "--safe-mode";
+// goxkey replay trace.json, see replay.rs
+const REPLAY_COMMAND: &str = "replay";
+// Ask for the Accessibility permission in the terminal instead of the setup window
+const CLI_SETUP_ARG: &str = "--setup";
+// Print whether the permission is granted and exit, for install scripts
+const CHECK_PERMISSION_ARG: &str = "--check-permission";
+const PERMISSION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PERMISSION_WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+// Right after login the Accessibility API can fail for a few seconds,
+// even when the permission is granted
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+const PREFLIGHT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+
+// Where the edits go and what is asked about the focused text field: the
+// focused app, or a simulated one when replaying a trace
+trait KeyOutput {
+    fn send_backspace(&mut self, count: usize);
+    fn send_string(&mut self, text: &str);
+    fn play_alert_sound(&mut self);
+    fn get_focused_field(&mut self) -> Option<FocusedField>;
+    fn get_text_selection(&mut self) -> Option<(bool, SelectionSource)>;
+    fn get_selected_text(&mut self) -> Option<String>;
+    fn get_text_before_caret(&mut self, max_chars: usize) -> Option<String>;
+    fn get_text_around_caret(&mut self, max_chars: usize) -> Option<(String, String)>;
+    fn select_around_caret(&mut self, before: &str, after: &str) -> bool;
+    fn set_selected_text(&mut self, text: &str) -> bool;
+}
+
+struct TapOutput(Handle);
+
+impl KeyOutput for TapOutput {
+    fn send_backspace(&mut self, count: usize) {
+        _ = send_backspace(self.0, count);
+    }
+
+    fn send_string(&mut self, text: &str) {
+        _ = send_string(self.0, text);
+    }
+
+    fn play_alert_sound(&mut self) {
+        play_alert_sound();
+    }
+
+    fn get_focused_field(&mut self) -> Option<FocusedField> {
+        get_focused_field()
+    }
+
+    fn get_text_selection(&mut self) -> Option<(bool, SelectionSource)> {
+        get_text_selection()
+    }
+
+    fn get_selected_text(&mut self) -> Option<String> {
+        get_selected_text()
+    }
+
+    fn get_text_before_caret(&mut self, max_chars: usize) -> Option<String> {
+        get_text_before_caret(max_chars)
+    }
 
-fn do_transform_keys(handle: Handle, is_delete: bool) -> bool {
+    fn get_text_around_caret(&mut self, max_chars: usize) -> Option<(String, String)> {
+        get_text_around_caret(max_chars)
+    }
+
+    fn select_around_caret(&mut self, before: &str, after: &str) -> bool {
+        select_around_caret(before, after)
+    }
+
+    fn set_selected_text(&mut self, text: &str) -> bool {
+        set_selected_text(text)
+    }
+}
+
+// Deletes and types through the injection limits, returns false when the
+// edit was blocked and nothing was sent
+fn send_edit(output: &mut dyn KeyOutput, backspace_count: usize, text: &str) -> bool {
+    let now = Instant::now();
     unsafe {
-        if let Ok((output, transform_result)) = INPUT_STATE.transform_keys() {
-            debug!("Transformed: {:?}", output);
-            if INPUT_STATE.should_send_keyboard_event(&output) || is_delete {
+        if let Err(err) = INJECTION_LIMITER.check(backspace_count, text.chars().count(), now) {
+            warn!("Blocked output: {:?}", err);
+            trace_stage!(INJECTION, backspace_count, ?text, ?err, "blocked");
+            if INJECTION_LIMITER.should_report(now) {
+                if let Some(event_sink) = UI_EVENT_SINK.get() {
+                    _ = event_sink.submit_command(
+                        SHOW_INJECTION_ERROR,
+                        err.to_string(),
+                        Target::Auto,
+                    );
+                }
+            }
+            return false;
+        }
+    }
+    debug!("Backspace count: {}", backspace_count);
+    output.send_backspace(backspace_count);
+    output.send_string(text);
+    debug!("Sent: {:?}", text);
+    trace_stage!(INJECTION, backspace_count, ?text, "sent");
+    true
+}
+
+fn do_transform_keys(output: &mut dyn KeyOutput, is_delete: bool) -> bool {
+    unsafe {
+        let transformed = INPUT_STATE.transform_keys();
+        trace_stage!(
+            TRANSFORM,
+            buffer = INPUT_STATE.get_typing_buffer(),
+            output = ?transformed.as_ref().map(|(output, _)| output),
+            "transformed"
+        );
+        if let Ok((word, transform_result)) = transformed {
+            debug!("Transformed: {:?}", word);
+            if INPUT_STATE.should_send_keyboard_event(&word) || is_delete {
                 // This is a workaround for Firefox, where macOS's Accessibility API cannot work.
                 // We cannot get the selected text in the address bar, so we will go with another
                 // hacky way: Always send a space and delete it immediately. This will dismiss the
                 // current pre-selected URL and fix the double character issue.
                 if INPUT_STATE.should_dismiss_selection_if_needed() {
-                    _ = send_string(handle, " ");
-                    _ = send_backspace(handle, 1);
+                    output.send_string(" ");
+                    output.send_backspace(1);
                 }
 
-                let backspace_count = INPUT_STATE.get_backspace_count(is_delete);
-                debug!("Backspace count: {}", backspace_count);
-                _ = send_backspace(handle, backspace_count);
-                _ = send_string(handle, &output);
-                debug!("Sent: {:?}", output);
-                INPUT_STATE.replace(output);
+                // Characters inserted by the editor are deleted and typed again
+                let auto_inserted_suffix = if is_delete {
+                    String::new()
+                } else {
+                    INPUT_STATE.get_auto_inserted_suffix(|max_chars| {
+                        output.get_text_before_caret(max_chars)
+                    })
+                };
+                let backspace_count = INPUT_STATE
+                    .get_backspace_count(is_delete, || output.get_text_selection())
+                    + auto_inserted_suffix.chars().count();
+                if !send_edit(
+                    output,
+                    backspace_count,
+                    &format!("{}{}", word, auto_inserted_suffix),
+                ) {
+                    return false;
+                }
+                INPUT_STATE.replace(word);
                 if transform_result.letter_modification_removed
                     || transform_result.tone_mark_removed
                 {
@@ -58,29 +216,102 @@ fn do_transform_keys(handle: Handle, is_delete: bool) -> bool {
     false
 }
 
-fn do_restore_word(handle: Handle) {
+fn do_restore_word(output: &mut dyn KeyOutput) {
     unsafe {
-        let backspace_count = INPUT_STATE.get_backspace_count(true);
-        debug!("Backspace count: {}", backspace_count);
-        _ = send_backspace(handle, backspace_count);
+        let backspace_count = INPUT_STATE.get_backspace_count(true, || output.get_text_selection());
         let typing_buffer = INPUT_STATE.get_typing_buffer();
-        _ = send_string(handle, typing_buffer);
-        debug!("Sent: {:?}", typing_buffer);
-        INPUT_STATE.replace(typing_buffer.to_owned());
+        if send_edit(output, backspace_count, typing_buffer) {
+            INPUT_STATE.replace(typing_buffer.to_owned());
+        }
+    }
+}
+
+fn do_replace_word(output: &mut dyn KeyOutput, word: String) {
+    unsafe {
+        let backspace_count = INPUT_STATE.get_backspace_count(true, || output.get_text_selection());
+        if send_edit(output, backspace_count, &word) {
+            INPUT_STATE.replace(word);
+        }
     }
 }
 
-fn do_macro_replace(handle: Handle, target: &String) {
+fn do_macro_replace(output: &mut dyn KeyOutput, target: String, previous_length: usize) -> bool {
     unsafe {
-        let backspace_count = INPUT_STATE.get_backspace_count(true);
-        debug!("Backspace count: {}", backspace_count);
-        _ = send_backspace(handle, backspace_count);
-        _ = send_string(handle, target);
-        debug!("Sent: {:?}", target);
-        INPUT_STATE.replace(target.to_owned());
+        // Multi-word triggers also delete the previous words and spaces
+        let backspace_count =
+            INPUT_STATE.get_backspace_count(true, || output.get_text_selection()) + previous_length;
+        if !send_edit(output, backspace_count, &target) {
+            return false;
+        }
+        INPUT_STATE.replace(target);
+        true
     }
 }
 
+// Returns false when there is nothing to do, so the app still gets the key
+unsafe fn handle_hotkey_action(output: &mut dyn KeyOutput, action: HotkeyAction) -> bool {
+    match action {
+        HotkeyAction::ExpandMacro => {
+            let Some((macro_target, previous_length)) = INPUT_STATE.get_forced_macro_target()
+            else {
+                return false;
+            };
+            debug!("Macro: {}", macro_target);
+            if !do_macro_replace(output, macro_target, previous_length) {
+                return false;
+            }
+            INPUT_STATE.new_word();
+        }
+        HotkeyAction::TransformWord => {
+            let Some((head, tail, transformed)) = INPUT_STATE
+                .get_caret_word_transform(|max_chars| output.get_text_around_caret(max_chars))
+            else {
+                return false;
+            };
+            debug!("Transform word: {}{} -> {}", head, tail, transformed);
+            // The whole word is selected and replaced, or typed over when the
+            // app doesn't let the selected text be set
+            if !output.select_around_caret(&head, &tail) {
+                return false;
+            }
+            if !output.set_selected_text(&transformed) && !send_edit(output, 0, &transformed) {
+                return false;
+            }
+            INPUT_STATE.new_word();
+        }
+        HotkeyAction::TransformSelection => {
+            let Some((text, transformed)) =
+                INPUT_STATE.get_selection_transform(|| output.get_selected_text())
+            else {
+                return false;
+            };
+            debug!("Transform selection: {} -> {}", text, transformed);
+            // Told apart from the blocked macros, the selection is left as is
+            let char_count = transformed.chars().count();
+            if char_count > MAX_CHARS_PER_OPERATION {
+                warn!("Selection too long to transform: {} chars", char_count);
+                if let Some(event_sink) = UI_EVENT_SINK.get() {
+                    _ = event_sink.submit_command(
+                        SHOW_INJECTION_ERROR,
+                        format!(
+                            "Vùng chọn có {} ký tự, mỗi lần chỉ chuyển được tối đa {} ký tự. Hãy chọn ít hơn rồi thử lại.",
+                            char_count, MAX_CHARS_PER_OPERATION
+                        ),
+                        Target::Auto,
+                    );
+                }
+                return true;
+            }
+            // Typing over the selection replaces it
+            if !send_edit(output, 0, &transformed) {
+                return false;
+            }
+            INPUT_STATE.new_word();
+        }
+    }
+    true
+}
+
 unsafe fn toggle_vietnamese() {
     INPUT_STATE.toggle_vietnamese();
     if let Some(event_sink) = UI_EVENT_SINK.get() {
@@ -89,11 +320,28 @@ unsafe fn toggle_vietnamese() {
 }
 
 unsafe fn auto_toggle_vietnamese() {
-    if !INPUT_STATE.is_auto_toggle_enabled() {
-        return;
+    // Rapid app switching (e.g. holding Cmd+Tab) fires the app change callback
+    // for every app we pass through. The UI restarts a timer on each one and
+    // only acts on the app that is still frontmost when it fires.
+    let interval = if INPUT_STATE.is_low_power_mode() {
+        APP_CHANGE_DEBOUNCE_INTERVAL_LOW_POWER
+    } else {
+        APP_CHANGE_DEBOUNCE_INTERVAL
+    };
+    match UI_EVENT_SINK.get() {
+        Some(event_sink) => _ = event_sink.submit_command(APP_CHANGED, interval, Target::Auto),
+        None => handle_app_change(),
     }
+}
+
+// Runs on the main thread, like the app change callback
+pub unsafe fn handle_app_change() {
+    let is_reloaded = reload_config_if_changed();
+    INPUT_STATE.refresh_power_state();
+    INPUT_STATE.reload_user_dictionary();
+    // Also after a reload, the reset engine still has the previous app
     let has_change = INPUT_STATE.update_active_app().is_some();
-    if !has_change {
+    if !has_change && !is_reloaded {
         return;
     }
     if let Some(event_sink) = UI_EVENT_SINK.get() {
@@ -101,47 +349,206 @@ unsafe fn auto_toggle_vietnamese() {
     }
 }
 
+// Picks up the edits made by hand in ~/.goxkey, checked when switching apps
+// so saving the file in an editor and going back to typing is enough
+fn reload_config_if_changed() -> bool {
+    {
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        if !config.is_changed_on_disk() {
+            return false;
+        }
+        info!("The config file was changed, reloading it");
+        *config = ConfigStore::new();
+    }
+    configure_trace();
+    restart_engine();
+    true
+}
+
+// Everything that runs before the event tap starts, see test_startup_time
+fn prepare_engine() {
+    rebuild_keyboard_layout_map();
+    unsafe { INPUT_STATE.refresh_power_state() };
+    if unsafe { INPUT_STATE.is_feature_enabled(EngineFeatures::ENGLISH_DICTIONARY) } {
+        validation::preload_english_words();
+    }
+    configure_trace();
+}
+
+fn configure_trace() {
+    let config = CONFIG_MANAGER.lock().unwrap();
+    trace::configure(
+        TraceStages::from_names(config.get_trace_stages()),
+        config.get_trace_app(),
+        unsafe { INPUT_STATE.get_active_app() },
+    );
+}
+
+// For when typing suddenly stops working: the event tap, the keyboard layout
+// map and the engine state are created again, the UI keeps running.
+pub fn restart_engine() {
+    info!("Restarting the input engine");
+    stop_event_listener();
+    rebuild_keyboard_layout_map();
+    // The engine state is reset on the new thread once the old one has
+    // returned, so the two never handle keys at the same time
+    start_event_listener(|| unsafe {
+        INPUT_STATE.reset();
+        HOTKEY_MATCHER = HotkeyMatcher::new();
+        GLOBE_KEY_TRACKER = GlobeKeyTracker::new();
+    });
+}
+
+// Runs the event tap on a new thread, after the previous one has stopped.
+// The setup runs on that thread before the first key is handled.
+fn start_event_listener(setup: impl FnOnce() + Send + 'static) {
+    let mut listener_thread = EVENT_LISTENER_THREAD.lock().unwrap();
+    let previous_thread = listener_thread.take();
+    *listener_thread = Some(thread::spawn(move || {
+        if let Some(previous_thread) = previous_thread {
+            _ = previous_thread.join();
+        }
+        setup();
+        run_event_listener(&event_handler);
+    }));
+}
+
+// Shows the engine state in the menu bar while the debug indicator is on
+unsafe fn report_debug_state() {
+    if let Some(state) = INPUT_STATE.take_debug_state_change() {
+        if let Some(event_sink) = UI_EVENT_SINK.get() {
+            _ = event_sink.submit_command(UPDATE_DEBUG_INDICATOR, state, Target::Auto);
+        }
+    }
+}
+
+// The event tap is only set up at launch, so once the permission has been
+// granted a new instance takes over from the permission screen. From a
+// bundle it goes through `open`, so macOS starts it like from Finder.
+pub fn relaunch_app() {
+    let spawned = env::current_exe().and_then(|exe| {
+        let bundle = exe
+            .ancestors()
+            .find(|path| path.extension().is_some_and(|ext| ext == "app"));
+        match bundle {
+            Some(bundle) => Command::new("open")
+                .arg("-n")
+                .arg(bundle)
+                .arg("--args")
+                .args(env::args().skip(1))
+                .spawn(),
+            None => Command::new(&exe).args(env::args().skip(1)).spawn(),
+        }
+    });
+    match spawned {
+        Ok(_) => Application::global().quit(),
+        Err(err) => error!("Cannot relaunch: {}", err),
+    }
+}
+
+// Suggest fast mode, once, for the apps where the Accessibility API
+// checks make typing noticeably slower.
+unsafe fn check_typing_latency(elapsed: Duration) {
+    if INPUT_STATE.record_key_latency(elapsed) {
+        if let Some(event_sink) = UI_EVENT_SINK.get() {
+            _ = event_sink.submit_command(
+                SHOW_LATENCY_WARNING,
+                INPUT_STATE.get_active_app().to_string(),
+                Target::Auto,
+            );
+        }
+    }
+}
+
+unsafe fn handle_globe_key(event_type: EventTapType) -> bool {
+    let behavior = INPUT_STATE.get_globe_key_behavior();
+    if behavior == GlobeKeyBehavior::PassThrough {
+        return false;
+    }
+    if event_type == EventTapType::KeyUp {
+        match GLOBE_KEY_TRACKER.on_key_up(Instant::now()) {
+            GlobeKeyAction::Toggle => toggle_vietnamese(),
+            GlobeKeyAction::LongPress if behavior == GlobeKeyBehavior::ToggleOrSettings => {
+                if let Some(event_sink) = UI_EVENT_SINK.get() {
+                    _ = event_sink.submit_command(SHOW_UI, (), Target::Auto);
+                }
+            }
+            _ => {}
+        }
+    } else {
+        GLOBE_KEY_TRACKER.on_key_down(Instant::now());
+    }
+    true
+}
+
 fn event_handler(
     handle: Handle,
     event_type: EventTapType,
     pressed_key: Option<PressedKey>,
     modifiers: KeyModifier,
+) -> bool {
+    let is_key_down = event_type == EventTapType::KeyDown;
+    let started_at = Instant::now();
+    let _span = trace::is_enabled(TraceStages::all())
+        .then(|| tracing::trace_span!("key_event", ?event_type, ?pressed_key).entered());
+    let is_handled = process_event(&mut TapOutput(handle), event_type, pressed_key, modifiers);
+    trace_stage!(HANDLER, is_handled, "handled");
+    if is_key_down {
+        unsafe { check_typing_latency(started_at.elapsed()) };
+    }
+    unsafe { report_debug_state() };
+    is_handled
+}
+
+fn process_event(
+    output: &mut dyn KeyOutput,
+    event_type: EventTapType,
+    pressed_key: Option<PressedKey>,
+    modifiers: KeyModifier,
 ) -> bool {
     unsafe {
+        // Apps that the user chose to ignore completely
+        if INPUT_STATE.is_ignored_app() {
+            trace_stage!(HANDLER, "ignored app");
+            return false;
+        }
+
         let pressed_key_code = pressed_key.and_then(|p| match p {
             PressedKey::Char(c) => Some(c),
-            _ => None,
+            PressedKey::Raw(raw_keycode) => get_function_key(raw_keycode),
         });
 
-        if event_type == EventTapType::FlagsChanged {
-            if modifiers.is_empty() {
-                // Modifier keys are released
-                if HOTKEY_MATCHING && !HOTKEY_MATCHING_CIRCUIT_BREAK {
-                    toggle_vietnamese();
-                }
-                HOTKEY_MODIFIERS = KeyModifier::MODIFIER_NONE;
-                HOTKEY_MATCHING = false;
-                HOTKEY_MATCHING_CIRCUIT_BREAK = false;
-            } else {
-                HOTKEY_MODIFIERS.set(modifiers, true);
-            }
+        if event_type == EventTapType::Mouse {
+            HOTKEY_MATCHER.on_mouse_event();
         }
+        if event_type == EventTapType::FlagsChanged
+            && HOTKEY_MATCHER.on_modifiers_changed(modifiers)
+        {
+            toggle_vietnamese();
+        }
+        HOTKEY_MATCHER.match_hotkey(INPUT_STATE.get_hotkey(), pressed_key_code);
 
-        let is_hotkey_matched = INPUT_STATE
-            .get_hotkey()
-            .is_match(HOTKEY_MODIFIERS, pressed_key_code);
-        if HOTKEY_MATCHING && !is_hotkey_matched {
-            HOTKEY_MATCHING_CIRCUIT_BREAK = true;
+        // Settled on the next key, or when the modifiers are all released
+        if INPUT_STATE.is_restore_pending()
+            && (event_type != EventTapType::FlagsChanged || modifiers.is_empty())
+        {
+            INPUT_STATE.set_restore_pending(false);
+            let is_hotkey_action = pressed_key.is_some()
+                && INPUT_STATE
+                    .get_action_hotkeys()
+                    .get_action(modifiers, pressed_key_code)
+                    .is_some();
+            if !is_hotkey_action {
+                do_restore_word(output);
+            }
         }
-        HOTKEY_MATCHING = is_hotkey_matched;
 
         match pressed_key {
             Some(pressed_key) => {
                 match pressed_key {
                     PressedKey::Raw(raw_keycode) => {
                         if raw_keycode == RAW_KEY_GLOBE {
-                            toggle_vietnamese();
-                            return true;
+                            return handle_globe_key(event_type);
                         }
                         if raw_keycode == RAW_ARROW_UP || raw_keycode == RAW_ARROW_DOWN {
                             INPUT_STATE.new_word();
@@ -152,33 +559,78 @@ fn event_handler(
                         }
                     }
                     PressedKey::Char(keycode) => {
+                        if let Some(action) = INPUT_STATE
+                            .get_action_hotkeys()
+                            .get_action(modifiers, Some(keycode))
+                        {
+                            trace_stage!(HANDLER, ?action, "hotkey action");
+                            if handle_hotkey_action(output, action) {
+                                return true;
+                            }
+                        }
                         if INPUT_STATE.is_enabled() {
                             match keycode {
                                 KEY_ENTER | KEY_TAB | KEY_SPACE | KEY_ESCAPE => {
-                                    let is_valid_word = vi::validation::is_valid_word(
-                                        INPUT_STATE.get_displaying_word(),
-                                    );
-                                    let is_allowed_word = INPUT_STATE
-                                        .is_allowed_word(INPUT_STATE.get_displaying_word());
+                                    let is_valid_word = INPUT_STATE.is_valid_word();
                                     let is_transformed_word = !INPUT_STATE
                                         .get_typing_buffer()
                                         .eq(INPUT_STATE.get_displaying_word());
-                                    if is_transformed_word && !is_valid_word && !is_allowed_word {
-                                        do_restore_word(handle);
+                                    trace_stage!(
+                                        HANDLER,
+                                        is_valid_word,
+                                        is_transformed_word,
+                                        "word ended"
+                                    );
+                                    if let Some(correction) = INPUT_STATE.get_spelling_correction()
+                                    {
+                                        debug!("Spelling: {}", correction);
+                                        do_replace_word(output, correction);
+                                    } else if is_transformed_word && !is_valid_word {
+                                        if INPUT_STATE
+                                            .is_feature_enabled(EngineFeatures::STRICT_VIETNAMESE)
+                                        {
+                                            // Learners hear that the word was not valid
+                                            if INPUT_STATE.is_misspelled_word() {
+                                                output.play_alert_sound();
+                                            }
+                                            do_restore_word(output);
+                                        } else if INPUT_STATE
+                                            .is_feature_enabled(EngineFeatures::AUTO_RESTORE)
+                                        {
+                                            do_restore_word(output);
+                                        }
                                     }
 
                                     if INPUT_STATE.previous_word_is_stop_tracking_words() {
                                         INPUT_STATE.clear_previous_word();
                                     }
 
-                                    if keycode == KEY_TAB || keycode == KEY_SPACE {
-                                        if let Some(macro_target) = INPUT_STATE.get_macro_target() {
+                                    // Esc ends the word without expanding it, so a
+                                    // trigger can still be typed as is
+                                    let mut is_macro_replaced = false;
+                                    if (keycode == KEY_TAB || keycode == KEY_SPACE)
+                                        && INPUT_STATE.should_expand_macro(keycode)
+                                    {
+                                        if let Some((macro_target, previous_length)) =
+                                            INPUT_STATE.get_macro_target()
+                                        {
                                             debug!("Macro: {}", macro_target);
-                                            do_macro_replace(handle, macro_target)
+                                            is_macro_replaced = do_macro_replace(
+                                                output,
+                                                macro_target,
+                                                previous_length,
+                                            );
                                         }
                                     }
 
-                                    INPUT_STATE.new_word();
+                                    if keycode == KEY_ENTER || keycode == KEY_SPACE {
+                                        INPUT_STATE.track_sentence_end(keycode, modifiers);
+                                    }
+                                    if keycode == KEY_SPACE && !is_macro_replaced {
+                                        INPUT_STATE.commit_word();
+                                    } else {
+                                        INPUT_STATE.new_word();
+                                    }
                                 }
                                 KEY_DELETE => {
                                     if !modifiers.is_empty() && !modifiers.is_shift() {
@@ -192,24 +644,63 @@ fn event_handler(
                                         || (c.is_numeric() && modifiers.is_shift())
                                     {
                                         // If special characters detected, dismiss the current tracking word
+                                        INPUT_STATE.track_sentence_end(c, modifiers);
                                         if c.is_numeric() {
                                             INPUT_STATE.push(c);
                                         }
                                         INPUT_STATE.new_word();
                                     } else {
                                         // Otherwise, process the character
-                                        if modifiers.is_super() || modifiers.is_alt() {
+                                        if modifiers.is_alt()
+                                            && !modifiers.is_super()
+                                            && INPUT_STATE.is_alt_passthrough_app()
+                                        {
+                                            // The app composes an accent from another
+                                            // language, the rest of the word is left as
+                                            // typed instead of starting a new one
+                                            trace_stage!(HANDLER, "option key passed through");
+                                            INPUT_STATE.stop_tracking();
+                                        } else if modifiers.is_super() || modifiers.is_alt() {
                                             INPUT_STATE.new_word();
                                         } else if INPUT_STATE.is_tracking() {
+                                            // Check the focused field once per word, URL, email
+                                            // and number inputs are left untouched, and so is
+                                            // text next to CJK or RTL scripts
+                                            if INPUT_STATE.is_buffer_empty()
+                                                && !INPUT_STATE.should_skip_ax_checks()
+                                                && (output.get_focused_field().is_some_and(
+                                                    |field| compat::should_pass_through(&field),
+                                                ) || INPUT_STATE.is_foreign_script_context(
+                                                    |max_chars| {
+                                                        output.get_text_before_caret(max_chars)
+                                                    },
+                                                ))
+                                            {
+                                                trace_stage!(HANDLER, "field passed through");
+                                                INPUT_STATE.stop_tracking();
+                                                return false;
+                                            }
+                                            let should_capitalize = c.is_ascii_alphabetic()
+                                                && INPUT_STATE.should_capitalize();
                                             INPUT_STATE.push(
-                                                if modifiers.is_shift() || modifiers.is_capslock() {
+                                                if modifiers.is_shift()
+                                                    || modifiers.is_capslock()
+                                                    || should_capitalize
+                                                {
                                                     c.to_ascii_uppercase()
                                                 } else {
                                                     c
                                                 },
                                             );
-                                            let ret = do_transform_keys(handle, false);
+                                            let ret = do_transform_keys(output, false);
                                             INPUT_STATE.stop_tracking_if_needed();
+                                            // The app would still receive the lowercase key
+                                            if should_capitalize && !ret {
+                                                output.send_string(
+                                                    &c.to_ascii_uppercase().to_string(),
+                                                );
+                                                return true;
+                                            }
                                             return ret;
                                         }
                                     }
@@ -234,12 +725,23 @@ fn event_handler(
                 let previous_modifiers = INPUT_STATE.get_previous_modifiers();
                 if previous_modifiers.is_empty() {
                     if modifiers.is_control() {
+                        // Held back while it may still be a hotkey action that
+                        // needs the word as it is
                         if !INPUT_STATE.get_typing_buffer().is_empty() {
-                            do_restore_word(handle);
+                            if INPUT_STATE
+                                .get_action_hotkeys()
+                                .uses_modifier(KeyModifier::MODIFIER_CONTROL)
+                            {
+                                INPUT_STATE.set_restore_pending(true);
+                            } else {
+                                do_restore_word(output);
+                            }
                         }
                         INPUT_STATE.set_temporary_disabled();
                     }
-                    if modifiers.is_super() || event_type == EventTapType::Other {
+                    if modifiers.is_super()
+                        || matches!(event_type, EventTapType::Mouse | EventTapType::Other)
+                    {
                         INPUT_STATE.new_word();
                     }
                 }
@@ -250,20 +752,137 @@ fn event_handler(
     false
 }
 
+// When installed with brew and started from a terminal, the permission is
+// requested right there, so the setup can be scripted without the GUI.
+fn wait_for_accessibility_permission() -> bool {
+    println!("gõkey needs the Accessibility permission to work.");
+    println!("Please allow it in System Settings > Privacy & Security > Accessibility:");
+    println!("  {ACCESSIBILITY_SETTINGS_URL}");
+    open_accessibility_settings();
+    println!("Waiting for the permission...");
+    let started_at = Instant::now();
+    while started_at.elapsed() < PERMISSION_WAIT_TIMEOUT {
+        if is_accessibility_trusted() {
+            println!("Permission granted, starting gõkey.");
+            return true;
+        }
+        thread::sleep(PERMISSION_POLL_INTERVAL);
+    }
+    eprintln!("Timed out waiting for the Accessibility permission.");
+    false
+}
+
+// Shows the release notes once after an update, not on the first launch
+fn show_release_notes_after_update() {
+    let mut config = CONFIG_MANAGER.lock().unwrap();
+    if config.get_last_seen_version() == Some(APP_VERSION) {
+        return;
+    }
+    let notes = changelog::get_release_notes(changelog::CHANGELOG, config.get_last_seen_version());
+    if !config.is_first_run() && !notes.is_empty() {
+        if let Some(event_sink) = UI_EVENT_SINK.get() {
+            _ = event_sink.submit_command(SHOW_WHATS_NEW, notes, Target::Auto);
+        }
+    }
+    config.set_last_seen_version(APP_VERSION);
+}
+
+// Retries the permission check with backoff before deciding that it's
+// missing, so a launch at login doesn't end up on the permission screen.
+fn preflight_accessibility_permission() -> bool {
+    let started_at = Instant::now();
+    let mut delay = PREFLIGHT_INITIAL_DELAY;
+    loop {
+        if is_accessibility_trusted() {
+            debug!("Permission check passed after {:?}", started_at.elapsed());
+            return true;
+        }
+        if started_at.elapsed() + delay > PREFLIGHT_TIMEOUT {
+            return false;
+        }
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
 fn main() {
-    let app_title = format!("gõkey v{APP_VERSION}");
+    let mut app_title = format!("gõkey v{APP_VERSION}");
     env_logger::init();
-    if !ensure_accessibility_permission() {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == REPLAY_COMMAND) {
+        let Some(path) = args.get(2) else {
+            eprintln!("Usage: goxkey {REPLAY_COMMAND} <trace.json>");
+            process::exit(2);
+        };
+        if let Err(err) = replay::run(path) {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+        process::exit(0);
+    }
+    if args.iter().any(|arg| arg == CHECK_PERMISSION_ARG) {
+        let is_trusted = is_accessibility_trusted();
+        println!(
+            "Accessibility permission: {}",
+            if is_trusted { "granted" } else { "missing" }
+        );
+        process::exit(if is_trusted { 0 } else { 1 });
+    }
+    let is_cli_setup = args.iter().any(|arg| arg == CLI_SETUP_ARG);
+    if is_cli_setup && !is_accessibility_trusted() && !wait_for_accessibility_permission() {
+        process::exit(1);
+    }
+    let is_login_launch = args.iter().any(|arg| arg == LAUNCH_AT_LOGIN_ARG);
+    let is_trusted = (is_login_launch && preflight_accessibility_permission())
+        || ensure_accessibility_permission();
+    if !is_trusted {
         // Show the Accessibility Permission Request screen
         let win = WindowDesc::new(ui::permission_request_ui_builder())
             .title(app_title)
-            .window_size((500.0, 360.0))
+            .window_size((500.0, 380.0))
             .resizable(false);
         let app = AppLauncher::with_window(win);
-        _ = app.launch(());
+        _ = app.launch(false);
     } else {
         // Start the GõKey application
-        rebuild_keyboard_layout_map();
+        let unclean_exits = safe_mode::record_launch();
+        safe_mode::record_stable_run_later();
+        let is_safe_mode = if args.iter().any(|arg| arg == SAFE_MODE_ARG) {
+            warn!("Starting in safe mode");
+            true
+        } else if safe_mode::should_start_in_safe_mode(unclean_exits) {
+            warn!(
+                "Starting in safe mode, the last {} launches did not quit cleanly",
+                unclean_exits
+            );
+            true
+        } else {
+            false
+        };
+        if is_safe_mode {
+            unsafe { INPUT_STATE.enable_safe_mode() };
+            app_title.push_str(" (chế độ an toàn)");
+        }
+        let startup_time = Instant::now();
+        // The event tap goes first and the UI catches up later, so the first
+        // keystrokes after login are not lost while the window and tray are built.
+        prepare_engine();
+        // Without a tray, the window and the hotkeys are the only way in
+        let is_tray_enabled = CONFIG_MANAGER.lock().unwrap().is_tray_enabled()
+            && !args.iter().any(|arg| arg == NO_TRAY_ARG);
+        start_event_listener(move || {
+            debug!("Event tap starting after {:?}", startup_time.elapsed());
+            // Built on the UI thread once it runs, the keys don't wait for it
+            if is_tray_enabled {
+                thread::spawn(move || {
+                    _ = UI_EVENT_SINK
+                        .wait()
+                        .submit_command(SHOW_TRAY, startup_time, Target::Auto);
+                });
+            }
+        });
+        add_app_change_callback(|| unsafe { auto_toggle_vietnamese() });
+
         let win = WindowDesc::new(ui::main_ui_builder())
             .title(app_title)
             .window_size((ui::WINDOW_WIDTH, ui::WINDOW_HEIGHT))
@@ -273,12 +892,47 @@ fn main() {
         let app = AppLauncher::with_window(win);
         let event_sink = app.get_external_handle();
         _ = UI_EVENT_SINK.set(event_sink);
-        thread::spawn(|| {
-            run_event_listener(&event_handler);
-        });
-        add_app_change_callback(|| {
-            unsafe { auto_toggle_vietnamese() };
-        });
-        _ = app.launch(UIDataAdapter::new());
+        settings_server::start();
+        // Offer to bring over the settings of the input method used until now,
+        // or a short typing exercise for those new to Vietnamese typing
+        if CONFIG_MANAGER.lock().unwrap().is_first_run() {
+            let onboarding = if migration::detect_installed_imes().is_empty() {
+                SHOW_TUTORIAL
+            } else {
+                SHOW_MIGRATION
+            };
+            if let Some(event_sink) = UI_EVENT_SINK.get() {
+                _ = event_sink.submit_command(onboarding, (), Target::Auto);
+            }
+        }
+        show_release_notes_after_update();
+        let ui_data = UIDataAdapter::new();
+        debug!("UI ready after {:?}", startup_time.elapsed());
+        _ = app.launch(ui_data);
+        // Cmd+Q and the other ways to quit that don't go through the tray
+        safe_mode::record_clean_exit();
     }
 }
+
+// What runs before the event tap delays the first keys after login. The
+// timings are printed with `cargo test startup -- --nocapture`
+#[test]
+fn test_startup_time() {
+    const ENGINE_BUDGET: Duration = Duration::from_millis(200);
+    let _lock = replay::reset_engine_state();
+    let startup_time = Instant::now();
+    prepare_engine();
+    let engine_time = startup_time.elapsed();
+    let _ui_data = UIDataAdapter::new();
+    let ui_time = startup_time.elapsed() - engine_time;
+    println!(
+        "Engine ready after {:?}, UI data built in {:?}",
+        engine_time, ui_time
+    );
+    assert!(
+        engine_time < ENGINE_BUDGET,
+        "The engine took {:?} to start, over {:?}",
+        engine_time,
+        ENGINE_BUDGET
+    );
+}
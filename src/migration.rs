@@ -0,0 +1,253 @@
+use crate::{
+    input::TypingMethod,
+    platform::{get_home_dir, read_preferences_data, read_preferences_file},
+};
+
+// OpenKey and EVKey, which started as a fork of it, share the same settings
+const IME_PREFERENCES: [(&str, &str); 2] = [
+    ("OpenKey", "com.tuyenmai.openkey"),
+    ("EVKey", "com.lamquangminh.evkey"),
+];
+const INPUT_TYPE_KEY: &str = "InputType";
+const SWITCH_KEY_STATUS_KEY: &str = "SwitchKeyStatus";
+const MACRO_DATA_KEY: &str = "macroData";
+
+// The switch hotkey is saved as a macOS key code in the low byte and one
+// bit per modifier, 0xFE means there is no key, only modifiers.
+const SWITCH_KEY_MASK: u32 = 0xFF;
+const SWITCH_KEY_NONE: u32 = 0xFE;
+const SWITCH_KEY_MODIFIERS: [(u32, &str); 4] = [
+    (0x400, "super"),
+    (0x100, "ctrl"),
+    (0x200, "alt"),
+    (0x800, "shift"),
+];
+const MAC_KEY_CODES: [(u32, &str); 27] = [
+    (0, "a"),
+    (1, "s"),
+    (2, "d"),
+    (3, "f"),
+    (4, "h"),
+    (5, "g"),
+    (6, "z"),
+    (7, "x"),
+    (8, "c"),
+    (9, "v"),
+    (11, "b"),
+    (12, "q"),
+    (13, "w"),
+    (14, "e"),
+    (15, "r"),
+    (16, "y"),
+    (17, "t"),
+    (31, "o"),
+    (32, "u"),
+    (34, "i"),
+    (35, "p"),
+    (37, "l"),
+    (38, "j"),
+    (40, "k"),
+    (45, "n"),
+    (46, "m"),
+    (49, "space"),
+];
+
+/// Settings found in another input method, ready to be applied.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportedSettings {
+    pub method: Option<TypingMethod>,
+    pub hotkey: Option<String>,
+    pub macros: Vec<(String, String)>,
+}
+
+impl ImportedSettings {
+    pub fn is_empty(&self) -> bool {
+        self.method.is_none() && self.hotkey.is_none() && self.macros.is_empty()
+    }
+}
+
+pub struct DetectedIme {
+    pub name: &'static str,
+    pub settings: ImportedSettings,
+}
+
+/// Looks for the input methods whose settings we know how to read.
+pub fn detect_installed_imes() -> Vec<DetectedIme> {
+    let Some(preferences_dir) = get_home_dir().map(|home| home.join("Library/Preferences")) else {
+        return Vec::new();
+    };
+    IME_PREFERENCES
+        .iter()
+        .filter_map(|(name, bundle_id)| {
+            let path = preferences_dir.join(format!("{bundle_id}.plist"));
+            let mut settings = parse_openkey_settings(&read_preferences_file(&path));
+            settings.macros = read_preferences_data(&path, MACRO_DATA_KEY)
+                .map(|data| parse_openkey_macros(&data))
+                .unwrap_or_default();
+            (!settings.is_empty()).then_some(DetectedIme { name, settings })
+        })
+        .collect()
+}
+
+pub fn parse_openkey_settings(entries: &[(String, String)]) -> ImportedSettings {
+    let get_number = |key: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, value)| value.parse::<u32>().ok())
+    };
+    ImportedSettings {
+        // Only Telex and VNI, the other input types have no equivalent here
+        method: get_number(INPUT_TYPE_KEY).and_then(|input_type| match input_type {
+            0 => Some(TypingMethod::Telex),
+            1 => Some(TypingMethod::VNI),
+            _ => None,
+        }),
+        hotkey: get_number(SWITCH_KEY_STATUS_KEY).and_then(parse_switch_key_status),
+        macros: Vec::new(),
+    }
+}
+
+/// Reads the macro table saved by OpenKey: the number of macros, then for
+/// each one the trigger and the text, each after its length in bytes. The
+/// numbers are little endian, the trigger length takes one byte and the
+/// others two. A truncated table keeps the macros read so far.
+pub fn parse_openkey_macros(mut data: &[u8]) -> Vec<(String, String)> {
+    let Some(count) = take_length(&mut data, 2) else {
+        return Vec::new();
+    };
+    let mut macros = Vec::new();
+    for _ in 0..count {
+        let from = take_length(&mut data, 1).and_then(|length| take_bytes(&mut data, length));
+        let Some(from) = from.map(String::from_utf8_lossy) else {
+            break;
+        };
+        let to = take_length(&mut data, 2).and_then(|length| take_bytes(&mut data, length));
+        let Some(to) = to.map(String::from_utf8_lossy) else {
+            break;
+        };
+        if !from.is_empty() && !to.is_empty() {
+            macros.push((from.into_owned(), to.into_owned()));
+        }
+    }
+    macros
+}
+
+// Splits the first bytes off the data
+fn take_bytes<'a>(data: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    let current: &'a [u8] = data;
+    if current.len() < length {
+        return None;
+    }
+    let (bytes, rest) = current.split_at(length);
+    *data = rest;
+    Some(bytes)
+}
+
+// A little endian number of one or two bytes
+fn take_length(data: &mut &[u8], size: usize) -> Option<usize> {
+    let bytes = take_bytes(data, size)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |length, &byte| length << 8 | byte as usize),
+    )
+}
+
+/// Converts a saved switch hotkey to the format used in `~/.goxkey`.
+pub fn parse_switch_key_status(status: u32) -> Option<String> {
+    let mut keys: Vec<&str> = SWITCH_KEY_MODIFIERS
+        .iter()
+        .filter(|(mask, _)| status & mask != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if keys.is_empty() {
+        return None;
+    }
+    let key_code = status & SWITCH_KEY_MASK;
+    if key_code != SWITCH_KEY_NONE {
+        let (_, key) = MAC_KEY_CODES.iter().find(|(code, _)| *code == key_code)?;
+        keys.push(key);
+    }
+    Some(keys.join("+"))
+}
+
+/// Reads a macro file exported by Unikey, OpenKey or EVKey, one `key:value`
+/// per line, lines starting with `;` are comments.
+pub fn parse_macro_file(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with(';'))
+        .filter_map(|line| {
+            let (from, to) = line.split_once(':')?;
+            let (from, to) = (from.trim(), to.trim());
+            (!from.is_empty() && !to.is_empty()).then(|| (from.to_string(), to.to_string()))
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_openkey_settings() {
+    let entries = vec![
+        ("InputType".to_string(), "1".to_string()),
+        ("SwitchKeyStatus".to_string(), "1329".to_string()),
+        ("Spelling".to_string(), "1".to_string()),
+    ];
+    let settings = parse_openkey_settings(&entries);
+    assert_eq!(settings.method, Some(TypingMethod::VNI));
+    // 0x531: super + ctrl + space
+    assert_eq!(settings.hotkey, Some("super+ctrl+space".to_string()));
+    assert!(parse_openkey_settings(&[]).is_empty());
+}
+
+#[test]
+fn test_parse_openkey_macros() {
+    let mut data = vec![2, 0];
+    for (from, to) in [("ko", "không"), ("vn", "Việt Nam")] {
+        data.push(from.len() as u8);
+        data.extend(from.as_bytes());
+        data.extend((to.len() as u16).to_le_bytes());
+        data.extend(to.as_bytes());
+    }
+    assert_eq!(
+        parse_openkey_macros(&data),
+        vec![
+            ("ko".to_string(), "không".to_string()),
+            ("vn".to_string(), "Việt Nam".to_string()),
+        ]
+    );
+    // Cut in the middle of the second macro
+    assert_eq!(
+        parse_openkey_macros(&data[..data.len() - 1]),
+        vec![("ko".to_string(), "không".to_string())]
+    );
+    assert!(parse_openkey_macros(&[]).is_empty());
+    assert!(parse_openkey_macros(&[1]).is_empty());
+}
+
+#[test]
+fn test_parse_switch_key_status() {
+    assert_eq!(
+        parse_switch_key_status(0x9FE),
+        Some("ctrl+shift".to_string())
+    );
+    assert_eq!(parse_switch_key_status(0x200), Some("alt+a".to_string()));
+    // A key without any modifier can't be a hotkey here
+    assert_eq!(parse_switch_key_status(0x31), None);
+    // Unknown key code
+    assert_eq!(parse_switch_key_status(0x17A), None);
+}
+
+#[test]
+fn test_parse_macro_file() {
+    let content = ";DO NOT DELETE THIS LINE*** version=1 ***\nko:không\nvn:Việt Nam\n\nbad line\nurl:https://example.com\n";
+    assert_eq!(
+        parse_macro_file(content),
+        vec![
+            ("ko".to_string(), "không".to_string()),
+            ("vn".to_string(), "Việt Nam".to_string()),
+            ("url".to_string(), "https://example.com".to_string()),
+        ]
+    );
+}
@@ -1,42 +1,182 @@
 // TODO: Implement this
 
-use druid::{commands::CLOSE_WINDOW, Selector};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{File, Permissions},
+    io::{self, Read},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
 
-use super::CallbackFn;
+use druid::ImageBuf;
+
+use super::{CallbackFn, FocusedField, SelectionSource, SystemTrayMenuItemKey, TrayHandle};
 
 pub const SYMBOL_SHIFT: &str = "⇧";
 pub const SYMBOL_CTRL: &str = "⌃";
 pub const SYMBOL_SUPER: &str = "❖";
 pub const SYMBOL_ALT: &str = "⌥";
+pub const ACCESSIBILITY_SETTINGS_URL: &str = "";
+
+// Nothing is sent to the apps here yet
+pub type Handle = ();
+
+// There is no system tray here yet, every operation is a no-op
+#[derive(Default)]
+pub struct SystemTray;
+
+impl SystemTray {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl TrayHandle for SystemTray {
+    fn set_title(&self, _title: &str) {}
+
+    fn set_menu_item_title(&self, _key: SystemTrayMenuItemKey, _label: &str) {}
+
+    fn set_menu_item_callback(&self, _key: SystemTrayMenuItemKey, _cb: Box<dyn Fn() + Send>) {}
+
+    fn remove(&self) {}
+}
 
 pub fn get_home_dir() -> Option<PathBuf> {
     env::var("HOME").ok().map(PathBuf::from)
 }
 
-pub fn send_backspace(count: usize) -> Result<(), ()> {
-    todo!()
+pub fn get_keyboard_layout_map(_chars: &[char]) -> HashMap<char, char> {
+    HashMap::new()
+}
+
+pub fn send_backspace(_handle: Handle, _count: usize) -> Result<(), ()> {
+    Err(())
 }
 
-pub fn send_string(string: &str) -> Result<(), ()> {
-    todo!()
+pub fn send_string(_handle: Handle, _string: &str) -> Result<(), ()> {
+    Err(())
 }
 
-pub fn run_event_listener(callback: &CallbackFn) {
-    todo!()
+pub fn run_event_listener(_callback: &CallbackFn) {}
+
+pub fn stop_event_listener() {}
+
+pub fn add_app_change_callback<F>(_cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+}
+
+pub fn remove_app_change_callbacks() {}
+
+pub fn get_active_app_name() -> String {
+    "/Unknown.app".to_string()
 }
 
 pub fn ensure_accessibility_permission() -> bool {
     true
 }
 
-pub fn is_in_text_selection() -> bool {
-    todo!()
+pub fn is_accessibility_trusted() -> bool {
+    true
+}
+
+pub fn open_accessibility_settings() {}
+
+pub fn open_url(_url: &str) {}
+
+pub fn reveal_in_file_manager(_path: &Path) {}
+
+pub fn open_in_text_editor(_path: &Path) {}
+
+pub fn get_text_selection() -> Option<(bool, SelectionSource)> {
+    None
+}
+
+pub fn get_selected_text() -> Option<String> {
+    None
+}
+
+pub fn get_focused_field() -> Option<FocusedField> {
+    None
+}
+
+pub fn get_text_before_caret(_max_chars: usize) -> Option<String> {
+    None
+}
+
+pub fn get_text_around_caret(_max_chars: usize) -> Option<(String, String)> {
+    None
+}
+
+pub fn select_around_caret(_before: &str, _after: &str) -> bool {
+    false
+}
+
+pub fn set_selected_text(_text: &str) -> bool {
+    false
+}
+
+pub fn get_function_key(_raw_keycode: u16) -> Option<char> {
+    None
+}
+
+pub fn is_function_key_standard() -> bool {
+    true
+}
+
+pub fn get_managed_config() -> Vec<(String, String)> {
+    Vec::new()
+}
+
+pub fn read_preferences_file(_path: &Path) -> Vec<(String, String)> {
+    Vec::new()
+}
+
+pub fn read_preferences_data(_path: &Path, _key: &str) -> Option<Vec<u8>> {
+    None
+}
+
+pub fn play_alert_sound() {}
+
+pub fn fill_random_bytes(buffer: &mut [u8]) -> bool {
+    File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(buffer))
+        .is_ok()
+}
+
+pub fn set_owner_only_permissions(file: &File) -> io::Result<()> {
+    file.set_permissions(Permissions::from_mode(0o600))
+}
+
+pub fn is_on_battery_power() -> bool {
+    false
+}
+
+pub fn get_running_apps() -> Vec<String> {
+    Vec::new()
+}
+
+pub fn get_text_replacements() -> Vec<(String, String)> {
+    Vec::new()
+}
+
+pub fn get_app_display_name(app_path: &str) -> String {
+    Path::new(app_path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| app_path.to_string())
+}
+
+pub fn get_app_icon(_app_path: &str) -> Option<ImageBuf> {
+    None
 }
 
-pub fn update_launch_on_login(is_enable: bool) {
-    todo!()
+pub fn update_launch_on_login(_is_enable: bool) -> Result<(), auto_launch::Error> {
+    Ok(())
 }
 
-pub fn is_launch_on_login() {
-    todo!()
+pub fn is_launch_on_login() -> bool {
+    false
 }
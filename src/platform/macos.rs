@@ -1,5 +1,11 @@
+use std::collections::HashMap;
 use std::env::current_exe;
+use std::fs::{File, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
 use std::{env, path::PathBuf, ptr};
 
 mod macos_ext;
@@ -7,7 +13,7 @@ use auto_launch::{AutoLaunch, AutoLaunchBuilder};
 use cocoa::base::id;
 use cocoa::{
     base::{nil, YES},
-    foundation::NSDictionary,
+    foundation::{NSDictionary, NSSize, NSString},
 };
 use core_graphics::{
     event::{
@@ -16,61 +22,117 @@ use core_graphics::{
     },
     sys,
 };
-use objc::{class, msg_send, sel, sel_impl};
+use druid::ImageBuf;
+use objc::{class, msg_send, runtime::Class, sel, sel_impl};
 
 pub use macos_ext::SystemTray;
-pub use macos_ext::SystemTrayMenuItemKey;
 use once_cell::sync::Lazy;
+use rdev::{Keyboard, KeyboardState};
 
-use crate::input::KEYBOARD_LAYOUT_CHARACTER_MAP;
+use crate::{
+    hotkey::function_key,
+    input::{get_layout_char, KEYBOARD_LAYOUT_CHARACTER_MAP},
+    trace::trace_stage,
+};
 use accessibility::{AXAttribute, AXUIElement};
-use accessibility_sys::{kAXFocusedUIElementAttribute, kAXSelectedTextAttribute};
+use accessibility_sys::{
+    kAXErrorSuccess, kAXFocusedUIElementAttribute, kAXNumberOfCharactersAttribute,
+    kAXRoleAttribute, kAXSelectedTextAttribute, kAXSelectedTextRangeAttribute,
+    kAXStringForRangeParameterizedAttribute, kAXSubroleAttribute, kAXValueTypeCFRange,
+    AXUIElementCopyParameterizedAttributeValue, AXUIElementSetAttributeValue, AXValueCreate,
+    AXValueGetValue, AXValueRef,
+};
 use core_foundation::{
-    runloop::{kCFRunLoopCommonModes, CFRunLoop},
+    base::{CFRange, CFRelease, CFType, CFTypeRef, TCFType},
+    boolean::CFBoolean,
+    data::CFData,
+    dictionary::CFDictionary,
+    mach_port::CFMachPortRef,
+    number::CFNumber,
+    propertylist::{create_with_data, kCFPropertyListImmutable, CFPropertyList},
+    runloop::{kCFRunLoopCommonModes, CFRunLoop, CFRunLoopRef, CFRunLoopStop},
     string::CFString,
 };
+use libc::c_void;
 
 pub use self::macos_ext::Handle;
 use self::macos_ext::{
-    kAXTrustedCheckOptionPrompt, new_tap, AXIsProcessTrustedWithOptions,
+    kAXTrustedCheckOptionPrompt, kSecRandomDefault, new_tap, AXIsProcessTrustedWithOptions,
     CGEventCreateKeyboardEvent, CGEventKeyboardSetUnicodeString, CGEventTapPostEvent,
+    IOPSCopyPowerSourcesInfo, IOPSGetProvidingPowerSourceType, NSBeep, SecRandomCopyBytes,
 };
 
 use super::{
-    CallbackFn, EventTapType, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE,
-    KEY_SPACE, KEY_TAB,
+    CallbackFn, EventTapType, FocusedField, KeyModifier, PressedKey, SelectionSource, KEY_DELETE,
+    KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB, LAUNCH_AT_LOGIN_ARG, RAW_KEY_GLOBE,
 };
 
 pub const SYMBOL_SHIFT: &str = "⇧";
 pub const SYMBOL_CTRL: &str = "⌃";
 pub const SYMBOL_SUPER: &str = "⌘";
 pub const SYMBOL_ALT: &str = "⌥";
+pub const ACCESSIBILITY_SETTINGS_URL: &str =
+    "x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility";
 
 impl From<CGEventType> for EventTapType {
     fn from(value: CGEventType) -> Self {
         match value {
             CGEventType::KeyDown => EventTapType::KeyDown,
+            CGEventType::KeyUp => EventTapType::KeyUp,
             CGEventType::FlagsChanged => EventTapType::FlagsChanged,
+            CGEventType::LeftMouseDown
+            | CGEventType::RightMouseDown
+            | CGEventType::OtherMouseDown
+            | CGEventType::LeftMouseDragged
+            | CGEventType::RightMouseDragged => EventTapType::Mouse,
             _ => EventTapType::Other,
         }
     }
 }
 
+// A launch agent rather than a login item, only it can pass the argument that
+// tells a launch at login apart
 static AUTO_LAUNCH: Lazy<AutoLaunch> = Lazy::new(|| {
-    let app_path = get_current_app_path();
-    let app_name = Path::new(&app_path)
-        .file_stem()
-        .and_then(|f| f.to_str())
-        .unwrap();
+    let exe_path = current_exe().unwrap().canonicalize().unwrap();
     AutoLaunchBuilder::new()
-        .set_app_name(app_name)
-        .set_app_path(&app_path)
+        .set_app_name(&get_current_app_name())
+        .set_app_path(&exe_path.display().to_string())
+        .set_use_launch_agent(true)
+        .set_args(&[LAUNCH_AT_LOGIN_ARG])
         .build()
         .unwrap()
 });
 
+// The login item added by older versions, replaced by the launch agent
+static LEGACY_AUTO_LAUNCH: Lazy<AutoLaunch> = Lazy::new(|| {
+    AutoLaunchBuilder::new()
+        .set_app_name(&get_current_app_name())
+        .set_app_path(&get_current_app_path())
+        .build()
+        .unwrap()
+});
+
+// The running event tap and its run loop, kept so they can be torn down on quit
+static EVENT_TAP_PORT: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static EVENT_TAP_RUN_LOOP: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+// Resolving names and icons hits the file system, cache them by app path.
+static APP_NAME_CACHE: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static APP_ICON_CACHE: Lazy<Mutex<HashMap<String, Option<ImageBuf>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
 /// On macOS, current_exe gives path to /Applications/Example.app/MacOS/Example but this results in seeing a Unix Executable in macOS login items. It must be: /Applications/Example.app
 /// If it didn't find exactly a single occurrence of .app, it will default to exe path to not break it.
+fn get_current_app_name() -> String {
+    let app_path = get_current_app_path();
+    Path::new(&app_path)
+        .file_stem()
+        .and_then(|f| f.to_str())
+        .unwrap()
+        .to_string()
+}
+
 fn get_current_app_path() -> String {
     let current_exe = current_exe().unwrap();
     let exe_path = current_exe.canonicalize().unwrap().display().to_string();
@@ -104,56 +166,125 @@ pub fn get_home_dir() -> Option<PathBuf> {
     env::var("HOME").ok().map(PathBuf::from)
 }
 
+fn get_key_from_char(c: char) -> rdev::Key {
+    use rdev::Key::*;
+    match &c {
+        'a' => KeyA,
+        '`' => BackQuote,
+        '1' => Num1,
+        '2' => Num2,
+        '3' => Num3,
+        '4' => Num4,
+        '5' => Num5,
+        '6' => Num6,
+        '7' => Num7,
+        '8' => Num8,
+        '9' => Num9,
+        '0' => Num0,
+        '-' => Minus,
+        '=' => Equal,
+        'q' => KeyQ,
+        'w' => KeyW,
+        'e' => KeyE,
+        'r' => KeyR,
+        't' => KeyT,
+        'y' => KeyY,
+        'u' => KeyU,
+        'i' => KeyI,
+        'o' => KeyO,
+        'p' => KeyP,
+        '[' => LeftBracket,
+        ']' => RightBracket,
+        's' => KeyS,
+        'd' => KeyD,
+        'f' => KeyF,
+        'g' => KeyG,
+        'h' => KeyH,
+        'j' => KeyJ,
+        'k' => KeyK,
+        'l' => KeyL,
+        ';' => SemiColon,
+        '\'' => Quote,
+        '\\' => BackSlash,
+        'z' => KeyZ,
+        'x' => KeyX,
+        'c' => KeyC,
+        'v' => KeyV,
+        'b' => KeyB,
+        'n' => KeyN,
+        'm' => KeyM,
+        ',' => Comma,
+        '.' => Dot,
+        '/' => Slash,
+        _ => Unknown(0),
+    }
+}
+
+/// The characters typed by the keys in the current layout, for keys given
+/// by their QWERTY character.
+pub fn get_keyboard_layout_map(chars: &[char]) -> HashMap<char, char> {
+    let mut map = HashMap::new();
+    let mut kb = Keyboard::new().unwrap();
+    for &c in chars {
+        let key = rdev::EventType::KeyPress(get_key_from_char(c));
+        if let Some(s) = kb.add(&key) {
+            let ch = s.chars().last().unwrap();
+            map.insert(c, ch);
+        }
+    }
+    map
+}
+
 // List of keycode: https://eastmanreference.com/complete-list-of-applescript-key-codes
 fn get_char(keycode: CGKeyCode) -> Option<PressedKey> {
     if let Some(key_map) = unsafe { KEYBOARD_LAYOUT_CHARACTER_MAP.get() } {
         return match keycode {
-            0 => Some(PressedKey::Char(key_map[&'a'])),
-            1 => Some(PressedKey::Char(key_map[&'s'])),
-            2 => Some(PressedKey::Char(key_map[&'d'])),
-            3 => Some(PressedKey::Char(key_map[&'f'])),
-            4 => Some(PressedKey::Char(key_map[&'h'])),
-            5 => Some(PressedKey::Char(key_map[&'g'])),
-            6 => Some(PressedKey::Char(key_map[&'z'])),
-            7 => Some(PressedKey::Char(key_map[&'x'])),
-            8 => Some(PressedKey::Char(key_map[&'c'])),
-            9 => Some(PressedKey::Char(key_map[&'v'])),
-            11 => Some(PressedKey::Char(key_map[&'b'])),
-            12 => Some(PressedKey::Char(key_map[&'q'])),
-            13 => Some(PressedKey::Char(key_map[&'w'])),
-            14 => Some(PressedKey::Char(key_map[&'e'])),
-            15 => Some(PressedKey::Char(key_map[&'r'])),
-            16 => Some(PressedKey::Char(key_map[&'y'])),
-            17 => Some(PressedKey::Char(key_map[&'t'])),
-            31 => Some(PressedKey::Char(key_map[&'o'])),
-            32 => Some(PressedKey::Char(key_map[&'u'])),
-            34 => Some(PressedKey::Char(key_map[&'i'])),
-            35 => Some(PressedKey::Char(key_map[&'p'])),
-            37 => Some(PressedKey::Char(key_map[&'l'])),
-            38 => Some(PressedKey::Char(key_map[&'j'])),
-            40 => Some(PressedKey::Char(key_map[&'k'])),
-            45 => Some(PressedKey::Char(key_map[&'n'])),
-            46 => Some(PressedKey::Char(key_map[&'m'])),
-            18 => Some(PressedKey::Char(key_map[&'1'])),
-            19 => Some(PressedKey::Char(key_map[&'2'])),
-            20 => Some(PressedKey::Char(key_map[&'3'])),
-            21 => Some(PressedKey::Char(key_map[&'4'])),
-            22 => Some(PressedKey::Char(key_map[&'6'])),
-            23 => Some(PressedKey::Char(key_map[&'5'])),
-            25 => Some(PressedKey::Char(key_map[&'9'])),
-            26 => Some(PressedKey::Char(key_map[&'7'])),
-            28 => Some(PressedKey::Char(key_map[&'8'])),
-            29 => Some(PressedKey::Char(key_map[&'0'])),
-            27 => Some(PressedKey::Char(key_map[&'-'])),
-            33 => Some(PressedKey::Char(key_map[&'['])),
-            30 => Some(PressedKey::Char(key_map[&']'])),
-            41 => Some(PressedKey::Char(key_map[&';'])),
-            43 => Some(PressedKey::Char(key_map[&','])),
-            24 => Some(PressedKey::Char(key_map[&'='])),
-            42 => Some(PressedKey::Char(key_map[&'\\'])),
-            44 => Some(PressedKey::Char(key_map[&'/'])),
-            39 => Some(PressedKey::Char(key_map[&'\''])),
-            47 => Some(PressedKey::Char(key_map[&'.'])),
+            0 => Some(PressedKey::Char(get_layout_char(key_map, 'a'))),
+            1 => Some(PressedKey::Char(get_layout_char(key_map, 's'))),
+            2 => Some(PressedKey::Char(get_layout_char(key_map, 'd'))),
+            3 => Some(PressedKey::Char(get_layout_char(key_map, 'f'))),
+            4 => Some(PressedKey::Char(get_layout_char(key_map, 'h'))),
+            5 => Some(PressedKey::Char(get_layout_char(key_map, 'g'))),
+            6 => Some(PressedKey::Char(get_layout_char(key_map, 'z'))),
+            7 => Some(PressedKey::Char(get_layout_char(key_map, 'x'))),
+            8 => Some(PressedKey::Char(get_layout_char(key_map, 'c'))),
+            9 => Some(PressedKey::Char(get_layout_char(key_map, 'v'))),
+            11 => Some(PressedKey::Char(get_layout_char(key_map, 'b'))),
+            12 => Some(PressedKey::Char(get_layout_char(key_map, 'q'))),
+            13 => Some(PressedKey::Char(get_layout_char(key_map, 'w'))),
+            14 => Some(PressedKey::Char(get_layout_char(key_map, 'e'))),
+            15 => Some(PressedKey::Char(get_layout_char(key_map, 'r'))),
+            16 => Some(PressedKey::Char(get_layout_char(key_map, 'y'))),
+            17 => Some(PressedKey::Char(get_layout_char(key_map, 't'))),
+            31 => Some(PressedKey::Char(get_layout_char(key_map, 'o'))),
+            32 => Some(PressedKey::Char(get_layout_char(key_map, 'u'))),
+            34 => Some(PressedKey::Char(get_layout_char(key_map, 'i'))),
+            35 => Some(PressedKey::Char(get_layout_char(key_map, 'p'))),
+            37 => Some(PressedKey::Char(get_layout_char(key_map, 'l'))),
+            38 => Some(PressedKey::Char(get_layout_char(key_map, 'j'))),
+            40 => Some(PressedKey::Char(get_layout_char(key_map, 'k'))),
+            45 => Some(PressedKey::Char(get_layout_char(key_map, 'n'))),
+            46 => Some(PressedKey::Char(get_layout_char(key_map, 'm'))),
+            18 => Some(PressedKey::Char(get_layout_char(key_map, '1'))),
+            19 => Some(PressedKey::Char(get_layout_char(key_map, '2'))),
+            20 => Some(PressedKey::Char(get_layout_char(key_map, '3'))),
+            21 => Some(PressedKey::Char(get_layout_char(key_map, '4'))),
+            22 => Some(PressedKey::Char(get_layout_char(key_map, '6'))),
+            23 => Some(PressedKey::Char(get_layout_char(key_map, '5'))),
+            25 => Some(PressedKey::Char(get_layout_char(key_map, '9'))),
+            26 => Some(PressedKey::Char(get_layout_char(key_map, '7'))),
+            28 => Some(PressedKey::Char(get_layout_char(key_map, '8'))),
+            29 => Some(PressedKey::Char(get_layout_char(key_map, '0'))),
+            27 => Some(PressedKey::Char(get_layout_char(key_map, '-'))),
+            33 => Some(PressedKey::Char(get_layout_char(key_map, '['))),
+            30 => Some(PressedKey::Char(get_layout_char(key_map, ']'))),
+            41 => Some(PressedKey::Char(get_layout_char(key_map, ';'))),
+            43 => Some(PressedKey::Char(get_layout_char(key_map, ','))),
+            24 => Some(PressedKey::Char(get_layout_char(key_map, '='))),
+            42 => Some(PressedKey::Char(get_layout_char(key_map, '\\'))),
+            44 => Some(PressedKey::Char(get_layout_char(key_map, '/'))),
+            39 => Some(PressedKey::Char(get_layout_char(key_map, '\''))),
+            47 => Some(PressedKey::Char(get_layout_char(key_map, '.'))),
             36 | 52 => Some(PressedKey::Char(KEY_ENTER)), // ENTER
             49 => Some(PressedKey::Char(KEY_SPACE)),      // SPACE
             48 => Some(PressedKey::Char(KEY_TAB)),        // TAB
@@ -165,29 +296,264 @@ fn get_char(keycode: CGKeyCode) -> Option<PressedKey> {
     None
 }
 
-pub fn is_in_text_selection() -> bool {
+// Function keys are sent as raw keys, so they are never typed into a word
+pub fn get_function_key(raw_keycode: u16) -> Option<char> {
+    let number = match raw_keycode {
+        122 => 1,
+        120 => 2,
+        99 => 3,
+        118 => 4,
+        96 => 5,
+        97 => 6,
+        98 => 7,
+        100 => 8,
+        101 => 9,
+        109 => 10,
+        103 => 11,
+        111 => 12,
+        _ => return None,
+    };
+    function_key(number)
+}
+
+/// Returns true if "Use F1, F2, etc. keys as standard function keys" is on.
+/// Otherwise the F-keys control the media and fn has to be held for them.
+pub fn is_function_key_standard() -> bool {
+    unsafe {
+        let user_defaults: id = msg_send![class!(NSUserDefaults), standardUserDefaults];
+        let key = NSString::alloc(nil).init_str(FN_STATE_DEFAULTS_KEY);
+        let is_standard: bool = msg_send![user_defaults, boolForKey: key];
+        let _: () = msg_send![key, release];
+        is_standard
+    }
+}
+
+/// The text replacements set in System Settings > Keyboard, as
+/// (shortcut, phrase) pairs.
+pub fn get_text_replacements() -> Vec<(String, String)> {
+    unsafe {
+        let user_defaults: id = msg_send![class!(NSUserDefaults), standardUserDefaults];
+        let key = NSString::alloc(nil).init_str(TEXT_REPLACEMENTS_DEFAULTS_KEY);
+        let items: id = msg_send![user_defaults, arrayForKey: key];
+        let _: () = msg_send![key, release];
+        if items == nil {
+            return Vec::new();
+        }
+        let replace_key = NSString::alloc(nil).init_str("replace");
+        let with_key = NSString::alloc(nil).init_str("with");
+        let count: usize = msg_send![items, count];
+        let mut replacements = Vec::with_capacity(count);
+        for i in 0..count {
+            let item: id = msg_send![items, objectAtIndex: i];
+            let shortcut: id = msg_send![item, objectForKey: replace_key];
+            let phrase: id = msg_send![item, objectForKey: with_key];
+            if shortcut == nil || phrase == nil {
+                continue;
+            }
+            if let (Some(shortcut), Some(phrase)) =
+                (nsstring_to_string!(shortcut), nsstring_to_string!(phrase))
+            {
+                replacements.push((shortcut, phrase));
+            }
+        }
+        let _: () = msg_send![replace_key, release];
+        let _: () = msg_send![with_key, release];
+        replacements
+    }
+}
+
+// Native text fields expose their NSTextContentType here, when it's set
+const AX_TEXT_CONTENT_TYPE_ATTRIBUTE: &str = "AXTextContentType";
+const FN_STATE_DEFAULTS_KEY: &str = "com.apple.keyboard.fnState";
+const TEXT_REPLACEMENTS_DEFAULTS_KEY: &str = "NSUserDictionaryReplacementItems";
+const MANAGED_PREFERENCES_PATH: &str = "/Library/Managed Preferences/com.goxkey.plist";
+
+fn get_focused_element() -> Option<AXUIElement> {
     let system_element = AXUIElement::system_wide();
-    let Some(selected_element) = system_element
+    system_element
         .attribute(&AXAttribute::new(&CFString::from_static_string(
             kAXFocusedUIElementAttribute,
         )))
-        .map(|elemenet| elemenet.downcast_into::<AXUIElement>())
+        .map(|element| element.downcast_into::<AXUIElement>())
         .ok()
         .flatten()
-    else {
-        return false;
+}
+
+fn get_string_attribute(element: &AXUIElement, name: &str) -> Option<String> {
+    element
+        .attribute(&AXAttribute::new(&CFString::new(name)))
+        .map(|value| value.downcast_into::<CFString>())
+        .ok()
+        .flatten()
+        .map(|value| value.to_string())
+}
+
+fn get_selected_range(element: &AXUIElement) -> Option<CFRange> {
+    let selected_range = element
+        .attribute(&AXAttribute::new(&CFString::from_static_string(
+            kAXSelectedTextRangeAttribute,
+        )))
+        .ok()?;
+    let mut range = CFRange::init(0, 0);
+    let is_range = unsafe {
+        AXValueGetValue(
+            selected_range.as_CFTypeRef() as AXValueRef,
+            kAXValueTypeCFRange,
+            &mut range as *mut CFRange as *mut c_void,
+        )
+    };
+    is_range.then_some(range)
+}
+
+fn create_range_value(range: CFRange) -> Option<CFType> {
+    let value = unsafe {
+        AXValueCreate(
+            kAXValueTypeCFRange,
+            &range as *const CFRange as *const c_void,
+        )
     };
-    let Some(selected_text) = selected_element
+    (!value.is_null()).then(|| unsafe { CFType::wrap_under_create_rule(value as CFTypeRef) })
+}
+
+// Only the text in the range is copied, not the whole document
+fn get_string_for_range(element: &AXUIElement, range: CFRange) -> Option<String> {
+    if range.length <= 0 {
+        return Some(String::new());
+    }
+    let range_value = create_range_value(range)?;
+    let attribute = CFString::from_static_string(kAXStringForRangeParameterizedAttribute);
+    let mut value: CFTypeRef = ptr::null();
+    let error = unsafe {
+        AXUIElementCopyParameterizedAttributeValue(
+            element.as_concrete_TypeRef(),
+            attribute.as_concrete_TypeRef(),
+            range_value.as_CFTypeRef(),
+            &mut value,
+        )
+    };
+    if error != kAXErrorSuccess || value.is_null() {
+        return None;
+    }
+    unsafe { CFType::wrap_under_create_rule(value) }
+        .downcast_into::<CFString>()
+        .map(|value| value.to_string())
+}
+
+fn get_character_count(element: &AXUIElement) -> Option<isize> {
+    element
         .attribute(&AXAttribute::new(&CFString::from_static_string(
-            kAXSelectedTextAttribute,
+            kAXNumberOfCharactersAttribute,
         )))
-        .map(|text| text.downcast_into::<CFString>())
-        .ok()
-        .flatten()
-    else {
+        .ok()?
+        .downcast_into::<CFNumber>()?
+        .to_i64()
+        .map(|count| count as isize)
+}
+
+fn set_attribute(element: &AXUIElement, name: &'static str, value: &CFType) -> bool {
+    let attribute = CFString::from_static_string(name);
+    let error = unsafe {
+        AXUIElementSetAttributeValue(
+            element.as_concrete_TypeRef(),
+            attribute.as_concrete_TypeRef(),
+            value.as_CFTypeRef(),
+        )
+    };
+    error == kAXErrorSuccess
+}
+
+/// Returns up to `max_chars` UTF-16 code units on each side of the caret in
+/// the focused text field. None when text is selected or the app doesn't
+/// expose its text.
+pub fn get_text_around_caret(max_chars: usize) -> Option<(String, String)> {
+    let element = get_focused_element()?;
+    let range = get_selected_range(&element)?;
+    if range.length != 0 {
+        return None;
+    }
+    let max_chars = max_chars as isize;
+    let start = (range.location - max_chars).max(0);
+    let end = (range.location + max_chars).min(get_character_count(&element)?);
+    Some((
+        get_string_for_range(&element, CFRange::init(start, range.location - start))?,
+        get_string_for_range(
+            &element,
+            CFRange::init(range.location, end - range.location),
+        )?,
+    ))
+}
+
+/// Selects the given text on each side of the caret, as returned by
+/// get_text_around_caret.
+pub fn select_around_caret(before: &str, after: &str) -> bool {
+    let Some(element) = get_focused_element() else {
         return false;
     };
-    !selected_text.to_string().is_empty()
+    let Some(range) = get_selected_range(&element) else {
+        return false;
+    };
+    // Accessibility ranges are counted in UTF-16 code units
+    let before_length = before.encode_utf16().count() as isize;
+    let length = before_length + after.encode_utf16().count() as isize;
+    create_range_value(CFRange::init(range.location - before_length, length))
+        .is_some_and(|value| set_attribute(&element, kAXSelectedTextRangeAttribute, &value))
+}
+
+/// Replaces the selected text in the focused element. Returns false when
+/// the app doesn't allow it, the text can still be typed over the selection.
+pub fn set_selected_text(text: &str) -> bool {
+    get_focused_element().is_some_and(|element| {
+        set_attribute(
+            &element,
+            kAXSelectedTextAttribute,
+            &CFString::new(text).as_CFType(),
+        )
+    })
+}
+
+/// Returns whether the focused element has text selected, and which
+/// attribute told us. Sandboxed apps often hide the selected text but still
+/// expose the selected range. None when neither is available.
+pub fn get_text_selection() -> Option<(bool, SelectionSource)> {
+    let element = get_focused_element()?;
+    // Some apps give an empty text even with a selection, the range tells
+    let selected_text =
+        get_string_attribute(&element, kAXSelectedTextAttribute).filter(|text| !text.is_empty());
+    if selected_text.is_some() {
+        return Some((true, SelectionSource::SelectedText));
+    }
+    let range = get_selected_range(&element)?;
+    Some((range.length > 0, SelectionSource::SelectedRange))
+}
+
+/// Returns the selected text in the focused element, if the app exposes it.
+pub fn get_selected_text() -> Option<String> {
+    let element = get_focused_element()?;
+    get_string_attribute(&element, kAXSelectedTextAttribute).filter(|text| !text.is_empty())
+}
+
+pub fn get_focused_field() -> Option<FocusedField> {
+    let element = get_focused_element()?;
+    Some(FocusedField {
+        role: get_string_attribute(&element, kAXRoleAttribute).unwrap_or_default(),
+        subrole: get_string_attribute(&element, kAXSubroleAttribute).unwrap_or_default(),
+        content_type: get_string_attribute(&element, AX_TEXT_CONTENT_TYPE_ATTRIBUTE)
+            .unwrap_or_default(),
+    })
+}
+
+/// Returns up to `max_chars` characters before the caret in the focused
+/// text field, if the app exposes them.
+pub fn get_text_before_caret(max_chars: usize) -> Option<String> {
+    let element = get_focused_element()?;
+    let range = get_selected_range(&element)?;
+    // Accessibility ranges are counted in UTF-16 code units, at most two
+    // for each character
+    let start = (range.location - 2 * max_chars as isize).max(0);
+    let text_before_caret =
+        get_string_for_range(&element, CFRange::init(start, range.location - start))?;
+    let skipped = text_before_caret.chars().count().saturating_sub(max_chars);
+    Some(text_before_caret.chars().skip(skipped).collect())
 }
 
 pub fn send_backspace(handle: Handle, count: usize) -> Result<(), ()> {
@@ -228,6 +594,10 @@ where
     macos_ext::add_app_change_callback(cb);
 }
 
+pub fn remove_app_change_callbacks() {
+    macos_ext::remove_app_change_callbacks();
+}
+
 pub fn run_event_listener(callback: &CallbackFn) {
     let current = CFRunLoop::get_current();
     if let Ok(event_tap) = new_tap::CGEventTap::new(
@@ -236,9 +606,12 @@ pub fn run_event_listener(callback: &CallbackFn) {
         CGEventTapOptions::Default,
         vec![
             CGEventType::KeyDown,
+            CGEventType::KeyUp,
             CGEventType::RightMouseDown,
             CGEventType::LeftMouseDown,
             CGEventType::OtherMouseDown,
+            CGEventType::LeftMouseDragged,
+            CGEventType::RightMouseDragged,
             CGEventType::FlagsChanged,
         ],
         |proxy, _, event| {
@@ -271,6 +644,7 @@ pub fn run_event_listener(callback: &CallbackFn) {
             }
 
             let event_tap_type: EventTapType = EventTapType::from(event.get_type());
+            trace_stage!(TAP, ?event_tap_type, ?modifiers, "event");
             match event_tap_type {
                 EventTapType::KeyDown => {
                     let source_state_id =
@@ -280,12 +654,29 @@ pub fn run_event_listener(callback: &CallbackFn) {
                             .get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE)
                             as CGKeyCode;
 
-                        if callback(proxy, event_tap_type, get_char(key_code), modifiers) {
+                        let pressed_key = get_char(key_code);
+                        trace_stage!(CHAR, key_code, ?pressed_key, "resolved");
+                        if callback(proxy, event_tap_type, pressed_key, modifiers) {
                             // block the key if already processed
                             return None;
                         }
                     }
                 }
+                EventTapType::KeyUp => {
+                    let key_code = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE)
+                        as CGKeyCode;
+                    // Key up events are only needed to detect long presses of the Globe key
+                    if key_code == RAW_KEY_GLOBE
+                        && callback(
+                            proxy,
+                            event_tap_type,
+                            Some(PressedKey::Raw(key_code)),
+                            modifiers,
+                        )
+                    {
+                        return None;
+                    }
+                }
                 EventTapType::FlagsChanged => {
                     callback(proxy, event_tap_type, None, modifiers);
                 }
@@ -300,11 +691,39 @@ pub fn run_event_listener(callback: &CallbackFn) {
             let loop_source = event_tap.mach_port.create_runloop_source(0).expect("Cannot start event tap. Make sure you have granted Accessibility Access for the application.");
             current.add_source(&loop_source, kCFRunLoopCommonModes);
             event_tap.enable();
+            let event_tap_port = event_tap.mach_port.as_concrete_TypeRef() as *mut c_void;
+            let run_loop = current.as_concrete_TypeRef() as *mut c_void;
+            EVENT_TAP_PORT.store(event_tap_port, Ordering::SeqCst);
+            EVENT_TAP_RUN_LOOP.store(run_loop, Ordering::SeqCst);
             CFRunLoop::run_current();
+            // After a restart, the new listener may already be running
+            _ = EVENT_TAP_PORT.compare_exchange(
+                event_tap_port,
+                ptr::null_mut(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+            _ = EVENT_TAP_RUN_LOOP.compare_exchange(
+                run_loop,
+                ptr::null_mut(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
         }
     }
 }
 
+pub fn stop_event_listener() {
+    let event_tap_port = EVENT_TAP_PORT.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !event_tap_port.is_null() {
+        new_tap::set_tap_enabled(event_tap_port as CFMachPortRef, false);
+    }
+    let run_loop = EVENT_TAP_RUN_LOOP.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !run_loop.is_null() {
+        unsafe { CFRunLoopStop(run_loop as CFRunLoopRef) };
+    }
+}
+
 pub fn is_process_trusted() -> bool {
     unsafe { accessibility_sys::AXIsProcessTrusted() }
 }
@@ -320,6 +739,73 @@ pub fn ensure_accessibility_permission() -> bool {
     }
 }
 
+// Unlike ensure_accessibility_permission, this never shows the system prompt
+pub fn is_accessibility_trusted() -> bool {
+    unsafe { AXIsProcessTrustedWithOptions(nil as _) }
+}
+
+pub fn open_accessibility_settings() {
+    open_url(ACCESSIBILITY_SETTINGS_URL);
+}
+
+/// Opens the URL in the app registered for it, the browser for web pages.
+pub fn open_url(url: &str) {
+    unsafe {
+        let url_string = NSString::alloc(nil).init_str(url);
+        let url: id = msg_send![class!(NSURL), URLWithString: url_string];
+        let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let _: bool = msg_send![shared_workspace, openURL: url];
+        let _: () = msg_send![url_string, release];
+    }
+}
+
+unsafe fn file_url(path: &Path) -> id {
+    let path_string = NSString::alloc(nil).init_str(&path.to_string_lossy());
+    let url: id = msg_send![class!(NSURL), fileURLWithPath: path_string];
+    let _: () = msg_send![path_string, release];
+    url
+}
+
+pub fn reveal_in_file_manager(path: &Path) {
+    unsafe {
+        let urls: id = msg_send![class!(NSArray), arrayWithObject: file_url(path)];
+        let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let _: () = msg_send![shared_workspace, activateFileViewerSelectingURLs: urls];
+    }
+}
+
+// Files without an extension, like ~/.goxkey, have no default app, so this
+// asks for the app that edits plain text
+pub fn open_in_text_editor(path: &Path) {
+    unsafe {
+        let url = file_url(path);
+        let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        // UTType is only there since macOS 11
+        let editor_url: id = match Class::get("UTType") {
+            Some(ut_type) => {
+                let identifier = NSString::alloc(nil).init_str("public.plain-text");
+                let plain_text: id = msg_send![ut_type, typeWithIdentifier: identifier];
+                let _: () = msg_send![identifier, release];
+                msg_send![shared_workspace, URLForApplicationToOpenContentType: plain_text]
+            }
+            None => nil,
+        };
+        if editor_url == nil {
+            let _: bool = msg_send![shared_workspace, openURL: url];
+            return;
+        }
+        let urls: id = msg_send![class!(NSArray), arrayWithObject: url];
+        let configuration: id = msg_send![class!(NSWorkspaceOpenConfiguration), configuration];
+        let _: () = msg_send![
+            shared_workspace,
+            openURLs: urls
+            withApplicationAtURL: editor_url
+            configuration: configuration
+            completionHandler: nil
+        ];
+    }
+}
+
 pub fn get_active_app_name() -> String {
     unsafe {
         let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
@@ -330,6 +816,188 @@ pub fn get_active_app_name() -> String {
     }
 }
 
+// NSApplicationActivationPolicyRegular, apps with a Dock icon
+const ACTIVATION_POLICY_REGULAR: i64 = 0;
+
+/// Paths of the running apps that show up in the Dock.
+pub fn get_running_apps() -> Vec<String> {
+    unsafe {
+        let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let running_apps: id = msg_send![shared_workspace, runningApplications];
+        let count: usize = msg_send![running_apps, count];
+        let mut apps = Vec::with_capacity(count);
+        for i in 0..count {
+            let app: id = msg_send![running_apps, objectAtIndex: i];
+            let activation_policy: i64 = msg_send![app, activationPolicy];
+            if activation_policy != ACTIVATION_POLICY_REGULAR {
+                continue;
+            }
+            let bundle_url: id = msg_send![app, bundleURL];
+            if bundle_url == nil {
+                continue;
+            }
+            let path: id = msg_send![bundle_url, path];
+            if let Some(path) = nsstring_to_string!(path) {
+                apps.push(path);
+            }
+        }
+        apps
+    }
+}
+
+/// Plays the system alert sound.
+pub fn play_alert_sound() {
+    unsafe { NSBeep() };
+}
+
+/// Fills the buffer from the system's secure random number generator.
+pub fn fill_random_bytes(buffer: &mut [u8]) -> bool {
+    unsafe {
+        SecRandomCopyBytes(
+            kSecRandomDefault,
+            buffer.len(),
+            buffer.as_mut_ptr() as *mut c_void,
+        ) == 0
+    }
+}
+
+/// Only lets the owner read and write the file, for files holding secrets.
+pub fn set_owner_only_permissions(file: &File) -> io::Result<()> {
+    file.set_permissions(Permissions::from_mode(0o600))
+}
+
+pub fn is_on_battery_power() -> bool {
+    unsafe {
+        let snapshot = IOPSCopyPowerSourcesInfo();
+        if snapshot.is_null() {
+            return false;
+        }
+        let source_type = IOPSGetProvidingPowerSourceType(snapshot);
+        let on_battery = !source_type.is_null()
+            && CFString::wrap_under_get_rule(source_type).to_string() == "Battery Power";
+        CFRelease(snapshot);
+        on_battery
+    }
+}
+
+/// Reads the settings pushed by an MDM configuration profile. The keys are the
+/// same as in `~/.goxkey`, list values are comma separated strings.
+pub fn get_managed_config() -> Vec<(String, String)> {
+    read_preferences_file(Path::new(MANAGED_PREFERENCES_PATH))
+}
+
+fn read_property_list(path: &Path) -> Option<CFDictionary> {
+    let bytes = std::fs::read(path).ok()?;
+    let (plist, _) =
+        create_with_data(CFData::from_buffer(&bytes), kCFPropertyListImmutable).ok()?;
+    unsafe { CFPropertyList::wrap_under_create_rule(plist) }.downcast_into::<CFDictionary>()
+}
+
+/// Reads the string, boolean and number values at the top level of a
+/// property list file, other values are skipped.
+pub fn read_preferences_file(path: &Path) -> Vec<(String, String)> {
+    let Some(dictionary) = read_property_list(path) else {
+        return Vec::new();
+    };
+    let (keys, values) = dictionary.get_keys_and_values();
+    keys.into_iter()
+        .zip(values)
+        .filter_map(|(key, value)| {
+            let key = unsafe { CFType::wrap_under_get_rule(key) }.downcast::<CFString>()?;
+            let value = unsafe { CFType::wrap_under_get_rule(value) };
+            let value = if let Some(value) = value.downcast::<CFString>() {
+                value.to_string()
+            } else if let Some(value) = value.downcast::<CFBoolean>() {
+                bool::from(value).to_string()
+            } else {
+                value.downcast::<CFNumber>()?.to_i64()?.to_string()
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Reads a data value at the top level of a property list file.
+pub fn read_preferences_data(path: &Path, name: &str) -> Option<Vec<u8>> {
+    let (keys, values) = read_property_list(path)?.get_keys_and_values();
+    keys.into_iter().zip(values).find_map(|(key, value)| {
+        let key = unsafe { CFType::wrap_under_get_rule(key) }.downcast::<CFString>()?;
+        if key.to_string() != name {
+            return None;
+        }
+        let value = unsafe { CFType::wrap_under_get_rule(value) }.downcast::<CFData>()?;
+        Some(value.bytes().to_vec())
+    })
+}
+
+/// Returns the localized display name of an app bundle, e.g. "Safari" for
+/// `/Applications/Safari.app`.
+pub fn get_app_display_name(app_path: &str) -> String {
+    let mut cache = APP_NAME_CACHE.lock().unwrap();
+    if let Some(name) = cache.get(app_path) {
+        return name.clone();
+    }
+    let name = unsafe {
+        let file_manager: id = msg_send![class!(NSFileManager), defaultManager];
+        let path = NSString::alloc(nil).init_str(app_path);
+        let display_name: id = msg_send![file_manager, displayNameAtPath: path];
+        let _: () = msg_send![path, release];
+        nsstring_to_string!(display_name)
+    }
+    .map(|name| name.trim_end_matches(".app").to_string())
+    .filter(|name| !name.is_empty())
+    .unwrap_or_else(|| {
+        Path::new(app_path)
+            .file_stem()
+            .and_then(|f| f.to_str())
+            .unwrap_or(app_path)
+            .to_string()
+    });
+    cache.insert(app_path.to_string(), name.clone());
+    name
+}
+
+pub fn get_app_icon(app_path: &str) -> Option<ImageBuf> {
+    let mut cache = APP_ICON_CACHE.lock().unwrap();
+    if let Some(icon) = cache.get(app_path) {
+        return icon.clone();
+    }
+    let icon =
+        unsafe { get_app_icon_png_data(app_path) }.and_then(|data| ImageBuf::from_data(&data).ok());
+    cache.insert(app_path.to_string(), icon.clone());
+    icon
+}
+
+unsafe fn get_app_icon_png_data(app_path: &str) -> Option<Vec<u8>> {
+    // NSBitmapImageFileTypePNG
+    const PNG_FILE_TYPE: u64 = 4;
+    let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+    let path = NSString::alloc(nil).init_str(app_path);
+    let icon: id = msg_send![shared_workspace, iconForFile: path];
+    let _: () = msg_send![path, release];
+    if icon == nil {
+        return None;
+    }
+    let _: () = msg_send![icon, setSize: NSSize::new(32.0, 32.0)];
+    let tiff_data: id = msg_send![icon, TIFFRepresentation];
+    if tiff_data == nil {
+        return None;
+    }
+    let bitmap: id = msg_send![class!(NSBitmapImageRep), imageRepWithData: tiff_data];
+    if bitmap == nil {
+        return None;
+    }
+    let properties: id = msg_send![class!(NSDictionary), dictionary];
+    let png_data: id =
+        msg_send![bitmap, representationUsingType: PNG_FILE_TYPE properties: properties];
+    if png_data == nil {
+        return None;
+    }
+    let length: usize = msg_send![png_data, length];
+    let bytes: *const u8 = msg_send![png_data, bytes];
+    Some(std::slice::from_raw_parts(bytes, length).to_vec())
+}
+
 pub fn update_launch_on_login(is_enable: bool) -> Result<(), auto_launch::Error> {
     match is_enable {
         true => AUTO_LAUNCH.enable(),
@@ -338,5 +1006,12 @@ pub fn update_launch_on_login(is_enable: bool) -> Result<(), auto_launch::Error>
 }
 
 pub fn is_launch_on_login() -> bool {
-    AUTO_LAUNCH.is_enabled().unwrap()
+    if AUTO_LAUNCH.is_enabled().unwrap() {
+        return true;
+    }
+    if LEGACY_AUTO_LAUNCH.is_enabled().unwrap_or(false) {
+        _ = LEGACY_AUTO_LAUNCH.disable();
+        return AUTO_LAUNCH.enable().is_ok();
+    }
+    false
 }
@@ -3,6 +3,7 @@ use cocoa::appkit::{
 };
 use cocoa::base::{id, nil, YES};
 use cocoa::foundation::{NSAutoreleasePool, NSString};
+use core_foundation::base::CFTypeRef;
 use core_foundation::dictionary::CFDictionaryRef;
 use core_foundation::string::CFStringRef;
 use core_graphics::{
@@ -21,6 +22,9 @@ use objc::{
 use objc_foundation::{INSObject, NSObject};
 use objc_id::Id;
 use std::mem;
+use std::sync::Mutex;
+
+use crate::platform::{SystemTrayMenuItemKey, TrayHandle};
 
 #[derive(Clone, PartialEq, Eq)]
 struct Wrapper(*mut objc::runtime::Object);
@@ -30,18 +34,11 @@ impl Data for Wrapper {
     }
 }
 
-pub enum SystemTrayMenuItemKey {
-    ShowUI,
-    Enable,
-    TypingMethodTelex,
-    TypingMethodVNI,
-    Exit,
-}
-
 #[derive(Clone, Data, Lens, PartialEq, Eq)]
 pub struct SystemTray {
     _pool: Wrapper,
     menu: Wrapper,
+    app_menu: Wrapper,
     item: Wrapper,
 }
 
@@ -56,11 +53,13 @@ impl SystemTray {
             let item = NSStatusBar::systemStatusBar(nil).statusItemWithLength_(-1.0);
             let title = NSString::alloc(nil).init_str("VN");
             NSButton::setTitle_(item, title);
+            let _: () = msg_send![title, release];
             item.setMenu_(menu);
 
             let s = Self {
                 _pool: Wrapper(pool),
                 menu: Wrapper(menu),
+                app_menu: Wrapper(NSMenu::new(nil).autorelease()),
                 item: Wrapper(item),
             };
             s.init_menu_items();
@@ -68,23 +67,26 @@ impl SystemTray {
         }
     }
 
-    pub fn set_title(&mut self, title: &str) {
-        unsafe {
-            let title = NSString::alloc(nil).init_str(title);
-            NSButton::setTitle_(self.item.0, title);
-            let _: () = msg_send![title, release];
-        }
-    }
-
     pub fn init_menu_items(&self) {
         self.add_menu_item("Bật bảng điều khiển", || ());
+        self.add_menu_item("Hướng dẫn gõ", || ());
         self.add_menu_separator();
         self.add_menu_item("Tắt gõ tiếng việt", || ());
+        self.add_submenu("Ứng dụng hiện tại", self.app_menu.0);
         self.add_menu_separator();
         self.add_menu_item("Telex ✓", || ());
         self.add_menu_item("VNI", || ());
         self.add_menu_separator();
+        self.add_menu_item("Mở tệp cài đặt", || ());
+        self.add_menu_item("Hiện tệp cài đặt trong Finder", || ());
+        self.add_menu_separator();
+        self.add_menu_item("Khởi động lại bộ gõ", || ());
         self.add_menu_item("Thoát ứng dụng", || ());
+
+        self.add_item_to_menu(self.app_menu.0, "Luôn tiếng Việt", || ());
+        self.add_item_to_menu(self.app_menu.0, "Luôn tiếng Anh", || ());
+        self.add_item_to_menu(self.app_menu.0, "Theo mặc định", || ());
+        self.add_item_to_menu(self.app_menu.0, "Bỏ qua hoàn toàn", || ());
     }
 
     pub fn add_menu_separator(&self) {
@@ -94,6 +96,26 @@ impl SystemTray {
     }
 
     pub fn add_menu_item<F>(&self, label: &str, cb: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.add_item_to_menu(self.menu.0, label, cb);
+    }
+
+    pub fn add_submenu(&self, label: &str, submenu: id) {
+        unsafe {
+            let no_key = NSString::alloc(nil).init_str("");
+            let itemtitle = NSString::alloc(nil).init_str(label);
+            let action = sel!(call);
+            let item = NSMenuItem::alloc(nil)
+                .initWithTitle_action_keyEquivalent_(itemtitle, action, no_key);
+            item.setSubmenu_(submenu);
+
+            NSMenu::addItem_(self.menu.0, item);
+        }
+    }
+
+    fn add_item_to_menu<F>(&self, menu: id, label: &str, cb: F)
     where
         F: Fn() + Send + 'static,
     {
@@ -107,36 +129,59 @@ impl SystemTray {
                 .initWithTitle_action_keyEquivalent_(itemtitle, action, no_key);
             let _: () = msg_send![item, setTarget: cb_obj];
 
-            NSMenu::addItem_(self.menu.0, item);
+            NSMenu::addItem_(menu, item);
         }
     }
 
-    pub fn get_menu_item_index_by_key(&self, key: SystemTrayMenuItemKey) -> i64 {
-        match key {
-            SystemTrayMenuItemKey::ShowUI => 0,
-            SystemTrayMenuItemKey::Enable => 2,
-            SystemTrayMenuItemKey::TypingMethodTelex => 4,
-            SystemTrayMenuItemKey::TypingMethodVNI => 5,
-            SystemTrayMenuItemKey::Exit => 7,
+    fn get_menu_item_by_key(&self, key: SystemTrayMenuItemKey) -> id {
+        let (menu, index) = match key {
+            SystemTrayMenuItemKey::ShowUI => (self.menu.0, 0),
+            SystemTrayMenuItemKey::CheatSheet => (self.menu.0, 1),
+            SystemTrayMenuItemKey::Enable => (self.menu.0, 3),
+            SystemTrayMenuItemKey::CurrentApp => (self.menu.0, 4),
+            SystemTrayMenuItemKey::TypingMethodTelex => (self.menu.0, 6),
+            SystemTrayMenuItemKey::TypingMethodVNI => (self.menu.0, 7),
+            SystemTrayMenuItemKey::OpenConfigFile => (self.menu.0, 9),
+            SystemTrayMenuItemKey::RevealConfigFile => (self.menu.0, 10),
+            SystemTrayMenuItemKey::RestartEngine => (self.menu.0, 12),
+            SystemTrayMenuItemKey::Exit => (self.menu.0, 13),
+            SystemTrayMenuItemKey::AppPolicyVietnamese => (self.app_menu.0, 0),
+            SystemTrayMenuItemKey::AppPolicyEnglish => (self.app_menu.0, 1),
+            SystemTrayMenuItemKey::AppPolicyDefault => (self.app_menu.0, 2),
+            SystemTrayMenuItemKey::AppPolicyIgnored => (self.app_menu.0, 3),
+        };
+        unsafe { menu.itemAtIndex_(index) }
+    }
+}
+
+impl TrayHandle for SystemTray {
+    fn set_title(&self, title: &str) {
+        unsafe {
+            let title = NSString::alloc(nil).init_str(title);
+            NSButton::setTitle_(self.item.0, title);
+            let _: () = msg_send![title, release];
         }
     }
 
-    pub fn set_menu_item_title(&self, key: SystemTrayMenuItemKey, label: &str) {
+    fn set_menu_item_title(&self, key: SystemTrayMenuItemKey, label: &str) {
         unsafe {
             let item_title = NSString::alloc(nil).init_str(label);
-            let index = self.get_menu_item_index_by_key(key);
-            NSButton::setTitle_(self.menu.0.itemAtIndex_(index), item_title);
+            NSButton::setTitle_(self.get_menu_item_by_key(key), item_title);
+            let _: () = msg_send![item_title, release];
         }
     }
 
-    pub fn set_menu_item_callback<F>(&self, key: SystemTrayMenuItemKey, cb: F)
-    where
-        F: Fn() + Send + 'static,
-    {
-        let cb_obj = Callback::from(Box::new(cb));
+    fn set_menu_item_callback(&self, key: SystemTrayMenuItemKey, cb: Box<dyn Fn() + Send>) {
+        let cb_obj = Callback::from(cb);
+        unsafe {
+            let _: () = msg_send![self.get_menu_item_by_key(key), setTarget: cb_obj];
+        }
+    }
+
+    fn remove(&self) {
         unsafe {
-            let index = self.get_menu_item_index_by_key(key);
-            let _: () = msg_send![self.menu.0.itemAtIndex_(index), setTarget: cb_obj];
+            let status_bar = NSStatusBar::systemStatusBar(nil);
+            let _: () = msg_send![status_bar, removeStatusItem: self.item.0];
         }
     }
 }
@@ -272,6 +317,10 @@ pub mod new_tap {
             unsafe { CGEventTapEnable(self.mach_port.as_concrete_TypeRef(), true) }
         }
     }
+
+    pub fn set_tap_enabled(tap: CFMachPortRef, enabled: bool) {
+        unsafe { CGEventTapEnable(tap, enabled) }
+    }
 }
 
 pub(crate) enum Callback {}
@@ -344,11 +393,28 @@ extern "C" {
     pub static kAXTrustedCheckOptionPrompt: CFStringRef;
 }
 
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    pub fn IOPSCopyPowerSourcesInfo() -> CFTypeRef;
+    pub fn IOPSGetProvidingPowerSourceType(snapshot: CFTypeRef) -> CFStringRef;
+}
+
+#[link(name = "Security", kind = "framework")]
+extern "C" {
+    pub static kSecRandomDefault: *const c_void;
+    pub fn SecRandomCopyBytes(rnd: *const c_void, count: usize, bytes: *mut c_void) -> i32;
+}
+
 #[link(name = "AppKit", kind = "framework")]
 extern "C" {
     pub static NSWorkspaceDidActivateApplicationNotification: CFStringRef;
+    pub fn NSBeep();
 }
 
+// Observers registered to the workspace notification center. The notification
+// center does not retain them, so we keep them alive until they're removed.
+static APP_CHANGE_OBSERVERS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
 pub fn add_app_change_callback<F>(cb: F)
 where
     F: Fn() + Send + 'static,
@@ -357,12 +423,27 @@ where
         let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
         let notification_center: id = msg_send![shared_workspace, notificationCenter];
         let cb_obj = Callback::from(Box::new(cb));
+        let observer = &*cb_obj as *const Callback as id;
+        mem::forget(cb_obj);
 
         let _: id = msg_send![notification_center,
-            addObserver:cb_obj
+            addObserver:observer
             selector:sel!(call)
             name:NSWorkspaceDidActivateApplicationNotification
             object:nil
         ];
+        APP_CHANGE_OBSERVERS.lock().unwrap().push(observer as usize);
+    }
+}
+
+pub fn remove_app_change_callbacks() {
+    unsafe {
+        let shared_workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let notification_center: id = msg_send![shared_workspace, notificationCenter];
+        for observer in APP_CHANGE_OBSERVERS.lock().unwrap().drain(..) {
+            let observer = observer as id;
+            let _: () = msg_send![notification_center, removeObserver: observer];
+            let _: () = msg_send![observer, release];
+        }
     }
 }
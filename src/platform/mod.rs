@@ -1,21 +1,180 @@
 #[cfg_attr(target_os = "macos", path = "macos.rs")]
 #[cfg_attr(target_os = "linux", path = "linux.rs")]
-#[cfg_attr(target_os = "window", path = "window.rs")]
+#[cfg_attr(target_os = "windows", path = "windows.rs")]
 mod os;
 
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
 
 use bitflags::bitflags;
+use druid::Data;
 pub use os::{
-    add_app_change_callback, ensure_accessibility_permission, get_active_app_name, get_home_dir,
-    is_in_text_selection, is_launch_on_login, run_event_listener, send_backspace, send_string,
-    update_launch_on_login, Handle, SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT, SYMBOL_SUPER,
+    add_app_change_callback, ensure_accessibility_permission, fill_random_bytes,
+    get_active_app_name, get_app_display_name, get_app_icon, get_focused_field, get_function_key,
+    get_home_dir, get_keyboard_layout_map, get_managed_config, get_running_apps, get_selected_text,
+    get_text_around_caret, get_text_before_caret, get_text_replacements, get_text_selection,
+    is_accessibility_trusted, is_function_key_standard, is_launch_on_login, is_on_battery_power,
+    open_accessibility_settings, open_in_text_editor, open_url, play_alert_sound,
+    read_preferences_data, read_preferences_file, remove_app_change_callbacks,
+    reveal_in_file_manager, run_event_listener, select_around_caret, send_backspace, send_string,
+    set_owner_only_permissions, set_selected_text, stop_event_listener, update_launch_on_login,
+    Handle, ACCESSIBILITY_SETTINGS_URL, SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT, SYMBOL_SUPER,
 };
 
-#[cfg(target_os = "macos")]
 pub use os::SystemTray;
-pub use os::SystemTrayMenuItemKey;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemTrayMenuItemKey {
+    ShowUI,
+    CheatSheet,
+    Enable,
+    TypingMethodTelex,
+    TypingMethodVNI,
+    CurrentApp,
+    AppPolicyVietnamese,
+    AppPolicyEnglish,
+    AppPolicyDefault,
+    AppPolicyIgnored,
+    OpenConfigFile,
+    RevealConfigFile,
+    RestartEngine,
+    Exit,
+}
+
+/// The operations the UI needs from a system tray, so it does not depend
+/// on a specific platform's status bar.
+pub trait TrayHandle {
+    fn set_title(&self, title: &str);
+    fn set_menu_item_title(&self, key: SystemTrayMenuItemKey, label: &str);
+    fn set_menu_item_callback(&self, key: SystemTrayMenuItemKey, cb: Box<dyn Fn() + Send>);
+    fn remove(&self);
+}
+
+/// A shared tray handle. Two handles are the same if they point to the same tray.
+///
+/// Titles only reach the tray when they change: each one set in AppKit is a
+/// new NSString, and some menu bar managers redraw on every change.
+#[derive(Clone)]
+pub struct Tray {
+    handle: Arc<dyn TrayHandle>,
+    titles: Arc<Mutex<TrayTitles>>,
+}
+
+#[derive(Default)]
+struct TrayTitles {
+    title: Option<String>,
+    menu_items: HashMap<SystemTrayMenuItemKey, String>,
+}
+
+impl Tray {
+    pub fn new(handle: impl TrayHandle + 'static) -> Self {
+        Self::from(Arc::new(handle))
+    }
+
+    pub fn set_title(&self, title: &str) {
+        let mut titles = self.titles.lock().unwrap();
+        if titles.title.as_deref() != Some(title) {
+            self.handle.set_title(title);
+            titles.title = Some(title.to_string());
+        }
+    }
+
+    pub fn set_menu_item_title(&self, key: SystemTrayMenuItemKey, label: &str) {
+        let mut titles = self.titles.lock().unwrap();
+        if titles.menu_items.get(&key).map(String::as_str) != Some(label) {
+            self.handle.set_menu_item_title(key, label);
+            titles.menu_items.insert(key, label.to_string());
+        }
+    }
+
+    pub fn set_menu_item_callback<F>(&self, key: SystemTrayMenuItemKey, cb: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.handle.set_menu_item_callback(key, Box::new(cb));
+    }
+
+    pub fn remove(&self) {
+        self.handle.remove();
+    }
+}
+
+impl<T: TrayHandle + 'static> From<Arc<T>> for Tray {
+    fn from(handle: Arc<T>) -> Self {
+        Self {
+            handle,
+            titles: Arc::new(Mutex::new(TrayTitles::default())),
+        }
+    }
+}
+
+impl PartialEq for Tray {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.handle, &other.handle)
+    }
+}
+
+impl Eq for Tray {}
+
+impl Data for Tray {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// A tray that only records what it was told, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockTray {
+    pub title: std::sync::Mutex<String>,
+    pub menu_item_titles:
+        std::sync::Mutex<std::collections::HashMap<SystemTrayMenuItemKey, String>>,
+    pub is_removed: std::sync::atomic::AtomicBool,
+    // Every title change that reached the tray
+    pub update_count: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl MockTray {
+    pub fn get_title(&self) -> String {
+        self.title.lock().unwrap().clone()
+    }
+
+    pub fn get_menu_item_title(&self, key: SystemTrayMenuItemKey) -> Option<String> {
+        self.menu_item_titles.lock().unwrap().get(&key).cloned()
+    }
+}
+
+#[cfg(test)]
+impl TrayHandle for MockTray {
+    fn set_title(&self, title: &str) {
+        *self.title.lock().unwrap() = title.to_string();
+        self.update_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn set_menu_item_title(&self, key: SystemTrayMenuItemKey, label: &str) {
+        self.update_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.menu_item_titles
+            .lock()
+            .unwrap()
+            .insert(key, label.to_string());
+    }
+
+    fn set_menu_item_callback(&self, _key: SystemTrayMenuItemKey, _cb: Box<dyn Fn() + Send>) {}
+
+    fn remove(&self) {
+        self.is_removed
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+// Passed by the login item, see is_launch_on_login
+pub const LAUNCH_AT_LOGIN_ARG: &str = "--launched-at-login";
 pub const RAW_KEY_GLOBE: u16 = 0xb3;
 pub const RAW_ARROW_DOWN: u16 = 0x7d;
 pub const RAW_ARROW_UP: u16 = 0x7e;
@@ -26,6 +185,8 @@ pub const KEY_SPACE: char = '\u{0020}';
 pub const KEY_TAB: char = '\x09';
 pub const KEY_DELETE: char = '\x08';
 pub const KEY_ESCAPE: char = '\x26';
+// F1 to F12 use the same private use characters as NSF1FunctionKey and onwards
+pub const KEY_F1: char = '\u{F704}';
 
 bitflags! {
     pub struct KeyModifier: u32 {
@@ -129,8 +290,50 @@ pub enum PressedKey {
 #[derive(Debug, PartialEq, Eq)]
 pub enum EventTapType {
     KeyDown,
+    KeyUp,
     FlagsChanged,
+    Mouse,
     Other,
 }
 
+/// Accessibility details of the focused text field, empty when not exposed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FocusedField {
+    pub role: String,
+    pub subrole: String,
+    pub content_type: String,
+}
+
+/// Which step of the text selection check gave the answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionSource {
+    SelectedText,
+    SelectedRange,
+    // Neither attribute was available, the per-app guess was used
+    Heuristic,
+}
+
 pub type CallbackFn = dyn Fn(os::Handle, EventTapType, Option<PressedKey>, KeyModifier) -> bool;
+
+#[test]
+fn test_tray_is_compared_by_identity() {
+    let tray = Tray::new(MockTray::default());
+    let other_tray = Tray::new(MockTray::default());
+    assert!(tray.same(&tray.clone()));
+    assert!(!tray.same(&other_tray));
+}
+
+#[test]
+fn test_mock_tray_records_titles() {
+    let tray = MockTray::default();
+    tray.set_title("VN");
+    tray.set_menu_item_title(SystemTrayMenuItemKey::Enable, "Tắt gõ tiếng Việt");
+    tray.remove();
+    assert_eq!(tray.get_title(), "VN");
+    assert_eq!(
+        tray.get_menu_item_title(SystemTrayMenuItemKey::Enable),
+        Some("Tắt gõ tiếng Việt".to_string())
+    );
+    assert_eq!(tray.get_menu_item_title(SystemTrayMenuItemKey::Exit), None);
+    assert!(tray.is_removed.load(std::sync::atomic::Ordering::SeqCst));
+}
@@ -0,0 +1,95 @@
+//! Behaviors behind bugs that were reported and fixed before. Each test types
+//! the keys from the report into a simulated app and checks what ends up on
+//! screen, so a refactor that brings one back fails here instead of in
+//! someone's URL bar.
+
+use std::collections::HashMap;
+
+use crate::{
+    input::{get_layout_char, EngineFeatures},
+    replay::{parse_trace, replay_in, ReplayApp},
+};
+
+const VIET_KEYS: &str = r#"["v", "i", "e", "e", "t", "j"]"#;
+
+fn keys(trace: &str) -> Vec<char> {
+    parse_trace(trace).unwrap()
+}
+
+// Firefox: typing in the URL bar doubled the first character, because the
+// autocompleted part is selected and Firefox hides it from the Accessibility API.
+#[test]
+fn firefox_url_bar_selection_is_dismissed() {
+    let firefox = ReplayApp {
+        path: "/Applications/Firefox.app",
+        completion: Some("tnamnet.vn"),
+        hides_selection: true,
+    };
+    assert_eq!(replay_in(firefox, &keys(VIET_KEYS), |_| {}), "việt");
+    assert_ne!(
+        replay_in(firefox, &keys(VIET_KEYS), |state| {
+            state.set_feature_enabled(EngineFeatures::FIREFOX_WORKAROUND, false)
+        }),
+        "việt"
+    );
+}
+
+// Chrome: "vieet" in the URL bar kept part of the old word in front of the
+// new one. The first backspace only removed the autocompleted selection.
+#[test]
+fn chrome_url_bar_selection_takes_an_extra_backspace() {
+    let chrome = ReplayApp {
+        path: "/Applications/Google Chrome.app",
+        completion: Some("tnamnet.vn"),
+        hides_selection: false,
+    };
+    assert_eq!(replay_in(chrome, &keys(VIET_KEYS), |_| {}), "việt");
+    assert_ne!(
+        replay_in(chrome, &keys(VIET_KEYS), |state| {
+            state.set_feature_enabled(EngineFeatures::SELECTION_COMPENSATION, false)
+        }),
+        "việt"
+    );
+}
+
+// Spotlight: same double character as Chrome, but the selection is not
+// exposed at all, so it has to be assumed.
+#[test]
+fn spotlight_selection_is_assumed() {
+    let spotlight = ReplayApp {
+        path: "/System/Library/CoreServices/Spotlight.app",
+        completion: Some("tnam.app"),
+        hides_selection: true,
+    };
+    assert_eq!(replay_in(spotlight, &keys(VIET_KEYS), |_| {}), "việt");
+    // Other apps that hide the selection are not assumed to have one
+    let notes = ReplayApp {
+        path: "/Applications/Notes.app",
+        hides_selection: true,
+        ..Default::default()
+    };
+    assert_eq!(replay_in(notes, &keys(VIET_KEYS), |_| {}), "việt");
+}
+
+// Dvorak and layouts with dead keys: a key missing from the layout map
+// crashed the event tap on the first keystroke.
+#[test]
+fn keys_missing_from_the_layout_do_not_crash() {
+    // The QWERTY keys under v, i, e, t and j on Dvorak, space is not mapped
+    let dvorak = HashMap::from([('.', 'v'), ('g', 'i'), ('d', 'e'), ('k', 't'), ('c', 'j')]);
+    let typed = ['.', 'g', 'd', 'd', 'k', 'c', ' '].map(|c| get_layout_char(&dvorak, c));
+    assert_eq!(replay_in(ReplayApp::default(), &typed, |_| {}), "việt ");
+}
+
+// Pressing a key with nothing tracked yet underflowed the backspace count.
+#[test]
+fn empty_word_needs_no_backspace() {
+    let trace = keys(r#"["backspace", "backspace", "a", "a"]"#);
+    assert_eq!(replay_in(ReplayApp::default(), &trace, |_| {}), "â");
+    let chrome = ReplayApp {
+        path: "/Applications/Google Chrome.app",
+        completion: Some("mazon.com"),
+        hides_selection: false,
+    };
+    assert_eq!(replay_in(chrome, &trace, |_| {}), "â");
+}
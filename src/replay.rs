@@ -0,0 +1,410 @@
+//! `goxkey replay trace.json` feeds a recorded list of keys through the
+//! engine and prints what would end up on screen, along with the decisions
+//! taken on the way. The keys go through `process_event` in main.rs like
+//! the ones from the event tap, with the edits going to a simulated text
+//! field instead of the focused app. The default config is used, ~/.goxkey
+//! is never read.
+
+use std::{fs, str::Chars};
+
+use crate::{
+    config::use_in_memory_config,
+    input::INPUT_STATE,
+    platform::{
+        EventTapType, FocusedField, KeyModifier, PressedKey, SelectionSource, KEY_DELETE,
+        KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB,
+    },
+    process_event, KeyOutput,
+};
+
+fn parse_key(name: &str) -> Result<char, String> {
+    Ok(match name {
+        "space" => KEY_SPACE,
+        "enter" => KEY_ENTER,
+        "tab" => KEY_TAB,
+        "esc" => KEY_ESCAPE,
+        "backspace" => KEY_DELETE,
+        key if key.chars().count() == 1 => key.chars().next().unwrap(),
+        key => return Err(format!("Unknown key {:?}", key)),
+    })
+}
+
+fn parse_hex_code(chars: &mut Chars) -> Result<u32, String> {
+    let hex = chars.take(4).collect::<String>();
+    if hex.len() != 4 {
+        return Err("Unterminated \\u escape".to_string());
+    }
+    u32::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape \\u{}", hex))
+}
+
+// The part after \u, characters outside the BMP come as a surrogate pair
+fn parse_unicode_escape(chars: &mut Chars) -> Result<char, String> {
+    let mut code = parse_hex_code(chars)?;
+    if (0xd800..0xdc00).contains(&code) {
+        if chars.next() != Some('\\') || chars.next() != Some('u') {
+            return Err(format!("Unpaired surrogate \\u{:04x}", code));
+        }
+        let low = parse_hex_code(chars)?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(format!("Unpaired surrogate \\u{:04x}", code));
+        }
+        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+    }
+    char::from_u32(code).ok_or_else(|| format!("Invalid escape \\u{:04x}", code))
+}
+
+/// Reads a trace, a JSON array with one key per item: a character, or one
+/// of "space", "enter", "tab", "esc" and "backspace".
+pub fn parse_trace(json: &str) -> Result<Vec<char>, String> {
+    let mut chars = json.trim().chars();
+    if chars.next() != Some('[') {
+        return Err("A trace must be a JSON array".to_string());
+    }
+    let mut keys = Vec::new();
+    let mut next_token = chars.find(|c| !c.is_whitespace());
+    if next_token == Some(']') {
+        return Ok(keys);
+    }
+    loop {
+        if next_token != Some('"') {
+            return Err("Keys must be strings".to_string());
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => name.push('\n'),
+                    Some('t') => name.push('\t'),
+                    Some('r') => name.push('\r'),
+                    Some('b') => name.push('\x08'),
+                    Some('f') => name.push('\x0c'),
+                    Some('u') => name.push(parse_unicode_escape(&mut chars)?),
+                    Some(c) => name.push(c),
+                    None => return Err("Unterminated string".to_string()),
+                },
+                Some(c) => name.push(c),
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+        keys.push(parse_key(&name)?);
+        match chars.find(|c| !c.is_whitespace()) {
+            Some(',') => next_token = chars.find(|c| !c.is_whitespace()),
+            Some(']') => return Ok(keys),
+            _ => return Err("Expected , or ]".to_string()),
+        }
+    }
+}
+
+/// How the simulated app treats the text, to bring back the app specific
+/// bugs. The default is a plain text field.
+#[derive(Clone, Copy, Default)]
+pub struct ReplayApp {
+    pub path: &'static str,
+    // Selected after the caret on each key typed, like URL bar completions
+    pub completion: Option<&'static str>,
+    // The Accessibility API doesn't tell about the selection
+    pub hides_selection: bool,
+}
+
+// Collects what would be typed into the focused app
+#[derive(Default)]
+struct ReplayOutput {
+    app: ReplayApp,
+    // The text before the caret, and the selected text after it
+    screen: String,
+    selection: String,
+    log: Vec<String>,
+    key: char,
+}
+
+impl ReplayOutput {
+    // Typing replaces the selection, the app may complete the text again
+    fn type_text(&mut self, text: &str) {
+        self.screen.push_str(text);
+        self.selection = match self.app.completion {
+            Some(completion) if !text.ends_with(char::is_whitespace) => completion.to_string(),
+            _ => String::new(),
+        };
+    }
+
+    // The first backspace only deletes the selection
+    fn delete_backward(&mut self) {
+        if self.selection.is_empty() {
+            self.screen.pop();
+        } else {
+            self.selection.clear();
+        }
+    }
+
+    // What the app does with a key that the engine let through
+    fn type_key(&mut self, key: char) {
+        match key {
+            KEY_DELETE => self.delete_backward(),
+            KEY_ENTER => self.type_text("\n"),
+            KEY_ESCAPE => {}
+            c => self.type_text(&c.to_string()),
+        }
+    }
+}
+
+impl KeyOutput for ReplayOutput {
+    fn send_backspace(&mut self, count: usize) {
+        if count > 0 {
+            self.log
+                .push(format!("{:?}: {} backspaces", self.key, count));
+        }
+        for _ in 0..count {
+            self.delete_backward();
+        }
+    }
+
+    fn send_string(&mut self, text: &str) {
+        self.log.push(format!("{:?}: sent {:?}", self.key, text));
+        self.type_text(text);
+    }
+
+    fn play_alert_sound(&mut self) {
+        self.log.push(format!("{:?}: beep", self.key));
+    }
+
+    fn get_focused_field(&mut self) -> Option<FocusedField> {
+        None
+    }
+
+    fn get_text_selection(&mut self) -> Option<(bool, SelectionSource)> {
+        (!self.app.hides_selection)
+            .then(|| (!self.selection.is_empty(), SelectionSource::SelectedText))
+    }
+
+    fn get_selected_text(&mut self) -> Option<String> {
+        self.get_text_selection()
+            .is_some_and(|(is_selected, _)| is_selected)
+            .then(|| self.selection.clone())
+    }
+
+    fn get_text_before_caret(&mut self, max_chars: usize) -> Option<String> {
+        let skipped = self.screen.chars().count().saturating_sub(max_chars);
+        Some(self.screen.chars().skip(skipped).collect())
+    }
+
+    // Nothing is after the caret, the keys are only ever typed at the end
+    fn get_text_around_caret(&mut self, max_chars: usize) -> Option<(String, String)> {
+        if !self.selection.is_empty() {
+            return None;
+        }
+        Some((self.get_text_before_caret(max_chars)?, String::new()))
+    }
+
+    fn select_around_caret(&mut self, before: &str, after: &str) -> bool {
+        if !self.selection.is_empty() || !after.is_empty() || !self.screen.ends_with(before) {
+            return false;
+        }
+        self.screen.truncate(self.screen.len() - before.len());
+        self.selection = before.to_string();
+        true
+    }
+
+    fn set_selected_text(&mut self, text: &str) -> bool {
+        if self.app.hides_selection {
+            return false;
+        }
+        self.log.push(format!(
+            "{:?}: replaced the selection with {:?}",
+            self.key, text
+        ));
+        self.screen.push_str(text);
+        self.selection.clear();
+        true
+    }
+}
+
+/// Returns the text before the caret after the keys, and what the engine
+/// did. The engine state is kept from the previous keys.
+pub fn replay(keys: &[char], app: ReplayApp) -> (String, Vec<String>) {
+    let keys = keys
+        .iter()
+        .map(|&key| (key, KeyModifier::new()))
+        .collect::<Vec<_>>();
+    replay_modified(&keys, app)
+}
+
+/// Same as `replay`, with the modifiers held down for each key.
+pub fn replay_modified(keys: &[(char, KeyModifier)], app: ReplayApp) -> (String, Vec<String>) {
+    let mut output = ReplayOutput {
+        app,
+        ..Default::default()
+    };
+    unsafe {
+        INPUT_STATE.set_active_app(app.path.to_string());
+    }
+    for &(key, modifiers) in keys {
+        output.key = key;
+        let is_handled = process_event(
+            &mut output,
+            EventTapType::KeyDown,
+            Some(PressedKey::Char(key)),
+            modifiers,
+        );
+        if !is_handled {
+            output.type_key(key);
+        }
+    }
+    (output.screen, output.log)
+}
+
+pub fn run(path: &str) -> Result<(), String> {
+    use_in_memory_config();
+    let json = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    let keys = parse_trace(&json)?;
+    unsafe {
+        println!("Method: {}", INPUT_STATE.get_method());
+    }
+    let (screen, log) = replay(&keys, ReplayApp::default());
+    for line in log {
+        println!("{}", line);
+    }
+    println!("Committed: {:?}", screen);
+    Ok(())
+}
+
+// Every replay in the tests goes through the same engine state
+#[cfg(test)]
+static REPLAY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Replays the keys in the app on a fresh engine with the default config,
+/// after `setup` has changed what the test needs.
+#[cfg(test)]
+pub fn replay_in(
+    app: ReplayApp,
+    keys: &[char],
+    setup: impl FnOnce(&mut crate::input::InputState),
+) -> String {
+    let keys = keys
+        .iter()
+        .map(|&key| (key, KeyModifier::new()))
+        .collect::<Vec<_>>();
+    replay_modified_in(app, &keys, setup)
+}
+
+#[cfg(test)]
+pub fn replay_modified_in(
+    app: ReplayApp,
+    keys: &[(char, KeyModifier)],
+    setup: impl FnOnce(&mut crate::input::InputState),
+) -> String {
+    let _lock = reset_engine_state();
+    unsafe { setup(&mut INPUT_STATE) };
+    replay_modified(keys, app).0
+}
+
+/// Gives a test the engine state to itself, with the default config, until
+/// the returned guard is dropped.
+#[cfg(test)]
+pub fn reset_engine_state() -> std::sync::MutexGuard<'static, ()> {
+    let lock = REPLAY_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    // Settings changed by the previous replays are dropped too
+    *crate::config::CONFIG_MANAGER.lock().unwrap() = crate::config::ConfigStore::new();
+    unsafe { *INPUT_STATE = crate::input::InputState::new() };
+    lock
+}
+
+#[cfg(test)]
+pub fn replay_with(keys: &str, setup: impl FnOnce(&mut crate::input::InputState)) -> String {
+    replay_in(ReplayApp::default(), &parse_trace(keys).unwrap(), setup)
+}
+
+#[test]
+fn test_parse_trace() {
+    assert_eq!(
+        parse_trace(r#"["v", "i", "e", "e", "t", "space", "\"", "backspace"]"#),
+        Ok(vec!['v', 'i', 'e', 'e', 't', KEY_SPACE, '"', KEY_DELETE])
+    );
+    assert_eq!(parse_trace(" [ ] "), Ok(vec![]));
+    assert!(parse_trace(r#"{"keys": []}"#).is_err());
+    assert!(parse_trace(r#"["ctrl"]"#).is_err());
+    assert!(parse_trace(r#"["a", 1]"#).is_err());
+    assert!(parse_trace(r#"["a""#).is_err());
+    assert_eq!(
+        parse_trace(r#"["\u0111", "\ud83d\ude00", "\\"]"#),
+        Ok(vec!['đ', '😀', '\\'])
+    );
+    assert!(parse_trace(r#"["\u01"]"#).is_err());
+    assert!(parse_trace(r#"["\ud83d"]"#).is_err());
+}
+
+#[test]
+fn test_replay() {
+    use crate::input::{EngineFeatures, TypingMethod};
+
+    assert_eq!(
+        replay_with(r#"["v", "i", "e", "e", "t", "j", "space"]"#, |_| {}),
+        "việt "
+    );
+    assert_eq!(
+        replay_with(r#"["v", "i", "e", "6", "5", "t"]"#, |state| {
+            state.set_method(TypingMethod::VNI)
+        }),
+        "việt"
+    );
+    // The backspace is let through to the app
+    assert_eq!(replay_with(r#"["d", "d", "backspace"]"#, |_| {}), "");
+    assert_eq!(
+        replay_with(r#"["h", "n", "space"]"#, |state| {
+            state.set_feature_enabled(EngineFeatures::MACROS, true);
+            state.add_macro("hn".to_string(), "Hà Nội".to_string());
+        }),
+        "Hà Nội "
+    );
+    let hanoi = |state: &mut crate::input::InputState| {
+        state.set_feature_enabled(EngineFeatures::MACROS, true);
+        state.add_macro("ha noi".to_string(), "Hà Nội".to_string());
+    };
+    assert_eq!(
+        replay_with(
+            r#"["x", "i", "n", "space", "h", "a", "space", "n", "o", "i", "space"]"#,
+            hanoi
+        ),
+        "xin Hà Nội "
+    );
+    // The space was deleted, "hanoi" is not the trigger
+    assert_eq!(
+        replay_with(
+            r#"["h", "a", "space", "backspace", "n", "o", "i", "space"]"#,
+            hanoi
+        ),
+        "hanoi "
+    );
+    let capitalize = |state: &mut crate::input::InputState| {
+        state.set_feature_enabled(EngineFeatures::AUTO_CAPITALIZE, true)
+    };
+    assert_eq!(
+        replay_with(r#"["a", ".", "space", "b"]"#, capitalize),
+        "a. B"
+    );
+    assert_eq!(replay_with(r#"["a", ".", "b"]"#, capitalize), "a.b");
+}
+
+#[test]
+fn test_replay_alt_passthrough() {
+    let app = ReplayApp {
+        path: "/Applications/Pages.app",
+        ..Default::default()
+    };
+    let mut alt = KeyModifier::new();
+    alt.add_alt();
+    let keys = ['v', 'i', 'e', 'e', 'e', 'a', 'a']
+        .iter()
+        .enumerate()
+        .map(|(index, &key)| (key, if index == 4 { alt } else { KeyModifier::new() }))
+        .collect::<Vec<_>>();
+    // The Option key starts a new word by default
+    assert_eq!(replay_modified_in(app, &keys, |_| {}), "viêeâ");
+    // In the chosen apps the rest of the word is left as typed
+    assert_eq!(
+        replay_modified_in(app, &keys, |state| {
+            state.set_alt_passthrough_app(app.path, true)
+        }),
+        "viêeaa"
+    );
+}
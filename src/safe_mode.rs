@@ -0,0 +1,58 @@
+use std::{fs, path::PathBuf, thread, time::Duration};
+
+use crate::platform::get_home_dir;
+
+// Removed on a clean quit or once GõKey has been running for a while, so
+// the count only grows when it crashes early, again and again. Quits that
+// skip the shutdown, like a logout or a reboot, are not counted either.
+const UNCLEAN_EXITS_FILE: &str = ".goxkey.unclean-exits";
+const MAX_UNCLEAN_EXITS: u32 = 3;
+const STABLE_RUN_DURATION: Duration = Duration::from_secs(60);
+
+fn get_unclean_exits_path() -> Option<PathBuf> {
+    get_home_dir().map(|home| home.join(UNCLEAN_EXITS_FILE))
+}
+
+fn parse_unclean_exits(content: &str) -> u32 {
+    content.trim().parse().unwrap_or(0)
+}
+
+/// Records the start of a launch and returns how many launches in a row
+/// before this one did not quit cleanly.
+pub fn record_launch() -> u32 {
+    let Some(path) = get_unclean_exits_path() else {
+        return 0;
+    };
+    let unclean_exits = fs::read_to_string(&path)
+        .map(|content| parse_unclean_exits(&content))
+        .unwrap_or(0);
+    _ = fs::write(&path, (unclean_exits + 1).to_string());
+    unclean_exits
+}
+
+pub fn record_clean_exit() {
+    if let Some(path) = get_unclean_exits_path() {
+        _ = fs::remove_file(path);
+    }
+}
+
+// A launch that made it this far didn't crash at startup
+pub fn record_stable_run_later() {
+    thread::spawn(|| {
+        thread::sleep(STABLE_RUN_DURATION);
+        record_clean_exit();
+    });
+}
+
+pub fn should_start_in_safe_mode(unclean_exits: u32) -> bool {
+    unclean_exits >= MAX_UNCLEAN_EXITS
+}
+
+#[test]
+fn test_safe_mode_after_repeated_unclean_exits() {
+    assert_eq!(parse_unclean_exits("2\n"), 2);
+    assert_eq!(parse_unclean_exits("garbage"), 0);
+    assert!(!should_start_in_safe_mode(parse_unclean_exits("")));
+    assert!(!should_start_in_safe_mode(2));
+    assert!(should_start_in_safe_mode(3));
+}
@@ -0,0 +1,120 @@
+//! Everything GõKey downloads (rule packs, macro packs, updates) must come
+//! with a detached ed25519 signature from one of the trusted keys. The app
+//! holds an event tap, so unsigned content is only loaded when the user
+//! turns on `allow-unsigned-content` in `~/.goxkey` by hand.
+
+use std::{fmt::Display, fs, path::Path};
+
+use ed25519_dalek::{Signature, VerifyingKey, SIGNATURE_LENGTH};
+use log::warn;
+
+use crate::config::CONFIG_MANAGER;
+
+// Public keys allowed to sign downloaded content. Empty until the release
+// signing key is published, so only the override lets content through.
+const TRUSTED_PUBLIC_KEYS: &[[u8; 32]] = &[];
+const SIGNATURE_EXTENSION: &str = "sig";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerificationError {
+    MissingSignature,
+    MalformedSignature,
+    UntrustedSignature,
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingSignature => write!(f, "content is not signed"),
+            Self::MalformedSignature => write!(f, "signature is malformed"),
+            Self::UntrustedSignature => write!(f, "signature does not match any trusted key"),
+        }
+    }
+}
+
+fn verify_with_keys(
+    content: &[u8],
+    signature: Option<&[u8]>,
+    public_keys: &[[u8; 32]],
+) -> Result<(), VerificationError> {
+    let signature = signature.ok_or(VerificationError::MissingSignature)?;
+    let signature: [u8; SIGNATURE_LENGTH] = signature
+        .try_into()
+        .map_err(|_| VerificationError::MalformedSignature)?;
+    let signature = Signature::from_bytes(&signature);
+    public_keys
+        .iter()
+        .filter_map(|key| VerifyingKey::from_bytes(key).ok())
+        .any(|key| key.verify_strict(content, &signature).is_ok())
+        .then_some(())
+        .ok_or(VerificationError::UntrustedSignature)
+}
+
+pub fn verify_content(content: &[u8], signature: Option<&[u8]>) -> Result<(), VerificationError> {
+    verify_with_keys(content, signature, TRUSTED_PUBLIC_KEYS)
+}
+
+/// Reads a downloaded file along with its signature in `<path>.sig`.
+/// Returns None when the file can't be read or fails the verification.
+// Nothing is downloaded yet, this is the one way in for when it is
+#[allow(dead_code)]
+pub fn read_signed_file(path: &Path) -> Option<Vec<u8>> {
+    let content = fs::read(path).ok()?;
+    let signature = fs::read(path.with_extension(match path.extension() {
+        Some(extension) => format!("{}.{}", extension.to_string_lossy(), SIGNATURE_EXTENSION),
+        None => SIGNATURE_EXTENSION.to_string(),
+    }))
+    .ok();
+    match verify_content(&content, signature.as_deref()) {
+        Ok(()) => Some(content),
+        Err(err) if CONFIG_MANAGER.lock().unwrap().is_unsigned_content_allowed() => {
+            warn!("Loading {} anyway: {}", path.display(), err);
+            Some(content)
+        }
+        Err(err) => {
+            warn!("Refusing to load {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_verify_content() {
+    // Test vector 1 from RFC 8032, an empty message
+    let public_key: [u8; 32] =
+        from_hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+            .try_into()
+            .unwrap();
+    let signature = from_hex(concat!(
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555",
+        "fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+    ));
+    assert_eq!(
+        verify_with_keys(b"", Some(&signature), &[public_key]),
+        Ok(())
+    );
+    assert_eq!(
+        verify_with_keys(b"tampered", Some(&signature), &[public_key]),
+        Err(VerificationError::UntrustedSignature)
+    );
+    assert_eq!(
+        verify_with_keys(b"", Some(&signature), &[]),
+        Err(VerificationError::UntrustedSignature)
+    );
+    assert_eq!(
+        verify_with_keys(b"", Some(&signature[..10]), &[public_key]),
+        Err(VerificationError::MalformedSignature)
+    );
+    assert_eq!(
+        verify_with_keys(b"", None, &[public_key]),
+        Err(VerificationError::MissingSignature)
+    );
+}
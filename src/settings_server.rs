@@ -0,0 +1,577 @@
+//! Optional settings page served on localhost, for editing the macros and
+//! the app rules from a browser. It's turned on by hand with
+//! `settings-server = true` in `~/.goxkey`, and every API call must carry the
+//! token from the same file in an `X-Goxkey-Token` header. Browsers don't
+//! send custom headers cross-origin without a CORS preflight, which is never
+//! answered, so other websites can't use the API.
+//!
+//! Changes go through the UI commands, like the ones made in the settings
+//! window, and settings locked by an administrator are refused with a 403.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use druid::Target;
+use log::{error, info, warn};
+
+use crate::{
+    config::{AppPolicy, CONFIG_MANAGER, MACROS_CONFIG_KEY},
+    platform::fill_random_bytes,
+    ui::{DELETE_MACRO, SET_APP_POLICY, SET_MACRO},
+    UI_EVENT_SINK,
+};
+
+pub const DEFAULT_PORT: u16 = 7413;
+const TOKEN_HEADER: &str = "x-goxkey-token";
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADER_COUNT: usize = 64;
+// For the whole request, so a slow client can't hold the server for longer
+const REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+const SETTINGS_PAGE: &str = include_str!("../assets/settings.html");
+
+static IS_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpRequest {
+    fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    ShowPage,
+    ListMacros,
+    SetMacro(String, String),
+    DeleteMacro(String),
+    ListApps,
+    SetAppPolicy(String, AppPolicy),
+}
+
+fn percent_decode(text: &str) -> String {
+    let input = text.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut index = 0;
+    while index < input.len() {
+        let escaped = input
+            .get(index + 1..index + 3)
+            .filter(|_| input[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (input[index], escaped) {
+            (_, Some(byte)) => {
+                bytes.push(byte);
+                index += 3;
+            }
+            (b'+', None) => {
+                bytes.push(b' ');
+                index += 1;
+            }
+            // A lone % is kept as is
+            (byte, None) => {
+                bytes.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parses `a=1&b=2`, as sent in query strings and HTML forms.
+fn parse_form(text: &str) -> Vec<(String, String)> {
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+// Every value ends up on a single line of ~/.goxkey, so a new line in it
+// would add settings of its own
+fn get_field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+        .filter(|value| !value.is_empty() && !value.chars().any(char::is_control))
+}
+
+fn parse_app_policy(name: &str) -> Option<AppPolicy> {
+    match name {
+        "vietnamese" => Some(AppPolicy::Vietnamese),
+        "english" => Some(AppPolicy::English),
+        "ignored" => Some(AppPolicy::Ignored),
+        "default" => Some(AppPolicy::Default),
+        _ => None,
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn macros_to_json(macros: &BTreeMap<String, String>) -> String {
+    let entries = macros
+        .iter()
+        .map(|(from, to)| format!("{}:{}", escape_json(from), escape_json(to)))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", entries.join(","))
+}
+
+fn apps_to_json(apps: &[(&str, &str)]) -> String {
+    let entries = apps
+        .iter()
+        .map(|(path, policy)| {
+            format!(
+                "{{\"path\":{},\"policy\":{}}}",
+                escape_json(path),
+                escape_json(policy)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", entries.join(","))
+}
+
+// Compares the whole token, so the time taken doesn't tell how much matched
+fn is_same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Pages from another host name pointing at 127.0.0.1 are turned away
+fn is_local_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    matches!(name, "127.0.0.1" | "localhost")
+}
+
+/// Decides what to do with a request, or the HTTP status to answer with.
+fn route(request: &HttpRequest, token: &str) -> Result<Action, u16> {
+    if !request.get_header("host").is_some_and(is_local_host) {
+        return Err(403);
+    }
+    if request.path == "/" {
+        return match request.method.as_str() {
+            "GET" => Ok(Action::ShowPage),
+            _ => Err(405),
+        };
+    }
+    if !request.path.starts_with("/api/") {
+        return Err(404);
+    }
+    let is_authorized = match request.get_header(TOKEN_HEADER) {
+        Some(given) => !token.is_empty() && is_same_token(given, token),
+        None => false,
+    };
+    if !is_authorized {
+        return Err(401);
+    }
+    let form = parse_form(&request.body);
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/macros") => Ok(Action::ListMacros),
+        ("POST", "/api/macros") => {
+            let from = get_field(&form, "from").ok_or(400u16)?;
+            let to = get_field(&form, "to").ok_or(400u16)?;
+            Ok(Action::SetMacro(from.to_string(), to.to_string()))
+        }
+        ("DELETE", "/api/macros") => {
+            let from = get_field(&request.query, "from").ok_or(400u16)?;
+            Ok(Action::DeleteMacro(from.to_string()))
+        }
+        ("GET", "/api/apps") => Ok(Action::ListApps),
+        ("POST", "/api/apps") => {
+            let path = get_field(&form, "path").ok_or(400u16)?;
+            let policy = get_field(&form, "policy")
+                .and_then(parse_app_policy)
+                .ok_or(400u16)?;
+            Ok(Action::SetAppPolicy(path.to_string(), policy))
+        }
+        (_, "/api/macros" | "/api/apps") => Err(405),
+        _ => Err(404),
+    }
+}
+
+// Gives the next read the time left until the deadline
+fn set_deadline(stream: &TcpStream, deadline: Instant) -> Option<()> {
+    let remaining = deadline.checked_duration_since(Instant::now())?;
+    stream.set_read_timeout(Some(remaining)).ok()
+}
+
+fn read_line(
+    reader: &mut BufReader<&TcpStream>,
+    line: &mut String,
+    deadline: Instant,
+) -> Option<()> {
+    line.clear();
+    set_deadline(reader.get_ref(), deadline)?;
+    reader
+        .by_ref()
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(line)
+        .ok()?;
+    // Cut short by the limit or the end of the stream
+    line.ends_with('\n').then_some(())
+}
+
+fn read_request(stream: &TcpStream) -> Option<HttpRequest> {
+    let deadline = Instant::now() + REQUEST_DEADLINE;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line, deadline)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = HttpRequest {
+        method,
+        path: path.to_string(),
+        query: parse_form(query),
+        ..Default::default()
+    };
+    loop {
+        read_line(&mut reader, &mut line, deadline)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if request.headers.len() == MAX_HEADER_COUNT {
+            return None;
+        }
+        let (name, value) = header.split_once(':')?;
+        request
+            .headers
+            .push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+    let content_length = request
+        .get_header("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_LENGTH {
+        return None;
+    }
+    let mut body = vec![0; content_length];
+    let mut length = 0;
+    while length < content_length {
+        set_deadline(stream, deadline)?;
+        match reader.read(&mut body[length..]).ok()? {
+            0 => return None,
+            count => length += count,
+        }
+    }
+    request.body = String::from_utf8(body).ok()?;
+    Some(request)
+}
+
+fn write_response(mut stream: &TcpStream, status: u16, content_type: &str, body: &str) {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        warn!("Settings server: cannot answer: {}", err);
+    }
+}
+
+fn submit_ui_command<T: Send + 'static>(selector: druid::Selector<T>, payload: T) {
+    if let Some(event_sink) = UI_EVENT_SINK.get() {
+        _ = event_sink.submit_command(selector, payload, Target::Auto);
+    }
+}
+
+fn run_action(stream: &TcpStream, action: Action) {
+    const JSON: &str = "application/json; charset=utf-8";
+    match action {
+        Action::ShowPage => write_response(stream, 200, "text/html; charset=utf-8", SETTINGS_PAGE),
+        Action::ListMacros => {
+            let json = macros_to_json(CONFIG_MANAGER.lock().unwrap().get_macro_table());
+            write_response(stream, 200, JSON, &json);
+        }
+        Action::SetMacro(..) | Action::DeleteMacro(_)
+            if CONFIG_MANAGER.lock().unwrap().is_locked(MACROS_CONFIG_KEY) =>
+        {
+            write_response(stream, 403, "text/plain", "");
+        }
+        Action::SetAppPolicy(..) if CONFIG_MANAGER.lock().unwrap().is_app_policy_locked() => {
+            write_response(stream, 403, "text/plain", "");
+        }
+        Action::SetMacro(from, to) => {
+            submit_ui_command(SET_MACRO, (from, to));
+            write_response(stream, 200, JSON, "{}");
+        }
+        Action::DeleteMacro(from) => {
+            submit_ui_command(DELETE_MACRO, from);
+            write_response(stream, 200, JSON, "{}");
+        }
+        Action::ListApps => {
+            let json = {
+                let config = CONFIG_MANAGER.lock().unwrap();
+                let apps = config
+                    .get_vietnamese_apps()
+                    .iter()
+                    .map(|path| (path.as_str(), "vietnamese"))
+                    .chain(
+                        config
+                            .get_english_apps()
+                            .iter()
+                            .map(|path| (path.as_str(), "english")),
+                    )
+                    .chain(
+                        config
+                            .get_ignored_apps()
+                            .iter()
+                            .map(|path| (path.as_str(), "ignored")),
+                    )
+                    .collect::<Vec<_>>();
+                apps_to_json(&apps)
+            };
+            write_response(stream, 200, JSON, &json);
+        }
+        Action::SetAppPolicy(path, policy) => {
+            submit_ui_command(SET_APP_POLICY, (path, policy));
+            write_response(stream, 200, JSON, "{}");
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, token: &str) {
+    let Some(request) = read_request(&stream) else {
+        write_response(&stream, 400, "text/plain", "");
+        return;
+    };
+    match route(&request, token) {
+        Ok(action) => run_action(&stream, action),
+        Err(status) => write_response(&stream, status, "text/plain", ""),
+    }
+}
+
+// 128 random bits from the system, written as hex
+fn generate_token() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    if !fill_random_bytes(&mut bytes) {
+        return None;
+    }
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The page address, with the token after the # so it's never sent to
+/// the server or kept in the browser history of other sites.
+pub fn get_settings_page_url() -> Option<String> {
+    let config = CONFIG_MANAGER.lock().unwrap();
+    if !config.is_settings_server_enabled() || config.get_settings_server_token().is_empty() {
+        return None;
+    }
+    Some(format!(
+        "http://127.0.0.1:{}/#token={}",
+        config.get_settings_server_port().unwrap_or(DEFAULT_PORT),
+        config.get_settings_server_token()
+    ))
+}
+
+/// Starts the server when it's turned on in the config. Only the first call
+/// does anything.
+pub fn start() {
+    let (port, token) = {
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        if !config.is_settings_server_enabled() {
+            return;
+        }
+        if config.get_settings_server_token().is_empty() {
+            let Some(token) = generate_token() else {
+                error!("Settings server: cannot generate a token");
+                return;
+            };
+            config.set_settings_server_token(&token);
+        }
+        (
+            config.get_settings_server_port().unwrap_or(DEFAULT_PORT),
+            config.get_settings_server_token().to_string(),
+        )
+    };
+    if IS_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Settings server: cannot listen on port {}: {}", port, err);
+            IS_STARTED.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+    info!("Settings page at http://127.0.0.1:{}/", port);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => handle_connection(stream, &token),
+                Err(err) => warn!("Settings server: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+fn test_request(method: &str, path: &str, token: Option<&str>, body: &str) -> HttpRequest {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut headers = vec![("host".to_string(), "127.0.0.1:7413".to_string())];
+    if let Some(token) = token {
+        headers.push((TOKEN_HEADER.to_string(), token.to_string()));
+    }
+    HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: parse_form(query),
+        headers,
+        body: body.to_string(),
+    }
+}
+
+#[test]
+fn test_route() {
+    let token = "secret";
+    let check = |method, path, given_token, body| {
+        route(&test_request(method, path, given_token, body), token)
+    };
+    assert_eq!(check("GET", "/", None, ""), Ok(Action::ShowPage));
+    assert_eq!(check("GET", "/api/macros", None, ""), Err(401));
+    assert_eq!(check("GET", "/api/macros", Some("secreT"), ""), Err(401));
+    assert_eq!(
+        check("GET", "/api/macros", Some(token), ""),
+        Ok(Action::ListMacros)
+    );
+    assert_eq!(
+        check(
+            "POST",
+            "/api/macros",
+            Some(token),
+            "from=hn&to=H%C3%A0+N%E1%BB%99i"
+        ),
+        Ok(Action::SetMacro("hn".to_string(), "Hà Nội".to_string()))
+    );
+    assert_eq!(
+        check("POST", "/api/macros", Some(token), "from=hn"),
+        Err(400)
+    );
+    assert_eq!(
+        check(
+            "POST",
+            "/api/macros",
+            Some(token),
+            "from=hn&to=x%0Asettings-server-port+%3D+80"
+        ),
+        Err(400)
+    );
+    assert_eq!(
+        check("DELETE", "/api/macros?from=hn", Some(token), ""),
+        Ok(Action::DeleteMacro("hn".to_string()))
+    );
+    assert_eq!(
+        check(
+            "POST",
+            "/api/apps",
+            Some(token),
+            "path=%2FApplications%2FTerminal.app&policy=english"
+        ),
+        Ok(Action::SetAppPolicy(
+            "/Applications/Terminal.app".to_string(),
+            AppPolicy::English
+        ))
+    );
+    assert_eq!(
+        check("POST", "/api/apps", Some(token), "path=a&policy=x"),
+        Err(400)
+    );
+    assert_eq!(check("PUT", "/api/apps", Some(token), ""), Err(405));
+    assert_eq!(check("GET", "/favicon.ico", None, ""), Err(404));
+
+    let mut request = test_request("GET", "/api/macros", Some(token), "");
+    request.headers[0].1 = "evil.example:7413".to_string();
+    assert_eq!(route(&request, token), Err(403));
+    // Without a token in the config, nothing gets through
+    assert_eq!(
+        route(&test_request("GET", "/api/apps", Some(""), ""), ""),
+        Err(401)
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let macros = BTreeMap::from([
+        ("hn".to_string(), "Hà Nội".to_string()),
+        ("q".to_string(), "\"quote\"\n".to_string()),
+    ]);
+    assert_eq!(
+        macros_to_json(&macros),
+        r#"{"hn":"Hà Nội","q":"\"quote\"\n"}"#
+    );
+    assert_eq!(
+        apps_to_json(&[("/Applications/Terminal.app", "english")]),
+        r#"[{"path":"/Applications/Terminal.app","policy":"english"}]"#
+    );
+    assert_eq!(apps_to_json(&[]), "[]");
+}
+
+#[test]
+fn test_parse_form() {
+    assert_eq!(
+        parse_form("from=a%2Bb&to=x+y%&empty=&flag"),
+        vec![
+            ("from".to_string(), "a+b".to_string()),
+            ("to".to_string(), "x y%".to_string()),
+            ("empty".to_string(), String::new()),
+            ("flag".to_string(), String::new()),
+        ]
+    );
+}
@@ -0,0 +1,141 @@
+// The five tone marks in order: sắc, huyền, hỏi, ngã, nặng
+const TONED_VOWELS: [(char, [char; 5]); 5] = [
+    ('a', ['á', 'à', 'ả', 'ã', 'ạ']),
+    ('e', ['é', 'è', 'ẻ', 'ẽ', 'ẹ']),
+    ('o', ['ó', 'ò', 'ỏ', 'õ', 'ọ']),
+    ('u', ['ú', 'ù', 'ủ', 'ũ', 'ụ']),
+    ('y', ['ý', 'ỳ', 'ỷ', 'ỹ', 'ỵ']),
+];
+
+fn with_case_of(c: char, reference: char) -> char {
+    if reference.is_uppercase() {
+        c.to_uppercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+fn split_tone(c: char) -> Option<(char, usize)> {
+    let lowercase = c.to_lowercase().next()?;
+    TONED_VOWELS.iter().find_map(|(base, toned)| {
+        let tone = toned.iter().position(|t| *t == lowercase)?;
+        Some((with_case_of(*base, c), tone))
+    })
+}
+
+fn add_tone(c: char, tone: usize) -> Option<char> {
+    let lowercase = c.to_lowercase().next()?;
+    let (_, toned) = TONED_VOWELS.iter().find(|(base, _)| *base == lowercase)?;
+    Some(with_case_of(toned[tone], c))
+}
+
+/// Moves the tone mark of words ending in "oa", "oe" and "uy" to the first
+/// vowel, e.g. "hoà" becomes "hòa" and "thuý" becomes "thúy".
+pub fn to_old_tone_style(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    let [.., first, second] = chars[..] else {
+        return word.to_string();
+    };
+    let Some((second_base, tone)) = split_tone(second) else {
+        return word.to_string();
+    };
+    let ending: String = [first, second_base]
+        .iter()
+        .flat_map(|c| c.to_lowercase())
+        .collect();
+    // The "u" in "qu" is part of the consonant, so "quý" keeps its tone
+    let is_after_q = chars.len() > 2 && chars[chars.len() - 3].eq_ignore_ascii_case(&'q');
+    if !matches!(ending.as_str(), "oa" | "oe" | "uy") || is_after_q {
+        return word.to_string();
+    }
+    let Some(first_toned) = add_tone(first, tone) else {
+        return word.to_string();
+    };
+    let mut result: String = chars[..chars.len() - 2].iter().collect();
+    result.push(first_toned);
+    result.push(second_base);
+    result
+}
+
+// Slips from writing words the way they are pronounced in some regions.
+// Only spellings that can't be meant as written, "dữ" and "giữ" are both words.
+const REGIONAL_SPELLING_RULES: [(&str, &str); 12] = [
+    ("iêu", "yêu"),
+    ("iên", "yên"),
+    ("nghành", "ngành"),
+    ("ngiêm", "nghiêm"),
+    ("ngĩa", "nghĩa"),
+    ("giòng", "dòng"),
+    ("dúp", "giúp"),
+    ("diêng", "riêng"),
+    ("chiện", "chuyện"),
+    ("dzậy", "vậy"),
+    ("dzui", "vui"),
+    ("dzô", "vô"),
+];
+
+/// The user's own `from:to` rules, then the built-in ones. The first match
+/// wins, so a user rule can override a built-in one.
+pub fn get_spelling_rules(user_rules: &[String]) -> Vec<(String, String)> {
+    user_rules
+        .iter()
+        .filter_map(|rule| {
+            let (from, to) = rule.split_once(':')?;
+            Some((from.trim().to_lowercase(), to.trim().to_string()))
+        })
+        .chain(
+            REGIONAL_SPELLING_RULES
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string())),
+        )
+        .collect()
+}
+
+/// Returns the preferred spelling when a rule matches the whole word,
+/// with the case of the word kept, e.g. "Nghành" becomes "Ngành".
+pub fn normalize_spelling(word: &str, rules: &[(String, String)]) -> Option<String> {
+    let lowercase = word.to_lowercase();
+    let (_, to) = rules.iter().find(|(from, _)| *from == lowercase)?;
+    let mut chars = word.chars();
+    let first = chars.next()?;
+    if word.chars().count() > 1 && chars.all(char::is_uppercase) && first.is_uppercase() {
+        return Some(to.to_uppercase());
+    }
+    let mut to_chars = to.chars();
+    let to_first = to_chars.next()?;
+    Some(with_case_of(to_first, first).to_string() + to_chars.as_str())
+}
+
+#[test]
+fn test_normalize_spelling() {
+    let rules = get_spelling_rules(&["hok:không".to_string(), "bad rule".to_string()]);
+    assert_eq!(
+        normalize_spelling("nghành", &rules),
+        Some("ngành".to_string())
+    );
+    assert_eq!(
+        normalize_spelling("Chiện", &rules),
+        Some("Chuyện".to_string())
+    );
+    assert_eq!(normalize_spelling("DZẬY", &rules), Some("VẬY".to_string()));
+    assert_eq!(normalize_spelling("hok", &rules), Some("không".to_string()));
+    assert_eq!(normalize_spelling("giữ", &rules), None);
+    assert_eq!(normalize_spelling("", &rules), None);
+}
+
+#[test]
+fn test_old_tone_style() {
+    assert_eq!(to_old_tone_style("hoà"), "hòa");
+    assert_eq!(to_old_tone_style("thuý"), "thúy");
+    assert_eq!(to_old_tone_style("khoẻ"), "khỏe");
+    assert_eq!(to_old_tone_style("HOÀ"), "HÒA");
+}
+
+#[test]
+fn test_old_tone_style_keeps_other_words() {
+    assert_eq!(to_old_tone_style("quý"), "quý");
+    assert_eq!(to_old_tone_style("hoàng"), "hoàng");
+    assert_eq!(to_old_tone_style("hoa"), "hoa");
+    assert_eq!(to_old_tone_style("à"), "à");
+    assert_eq!(to_old_tone_style(""), "");
+}
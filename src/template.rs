@@ -0,0 +1,308 @@
+use std::{fmt::Display, str::FromStr};
+
+use druid::Data;
+
+/// Controls how numbers and dates are written by dynamic macros.
+#[derive(PartialEq, Eq, Data, Clone, Copy, Debug)]
+pub enum Locale {
+    // vi_VN: 1.234,5 - 15/10/2026 - 14:05
+    Vietnamese,
+    // en_US: 1,234.5 - 10/15/2026 - 2:05 PM
+    English,
+}
+
+impl FromStr for Locale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "en_us" => Locale::English,
+            _ => Locale::Vietnamese,
+        })
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Vietnamese => "vi_VN",
+                Self::English => "en_US",
+            }
+        )
+    }
+}
+
+pub struct LocalDateTime {
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+}
+
+impl LocalDateTime {
+    pub fn now() -> Self {
+        unsafe {
+            let mut now: libc::time_t = 0;
+            libc::time(&mut now);
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            Self {
+                year: tm.tm_year + 1900,
+                month: tm.tm_mon as u32 + 1,
+                day: tm.tm_mday as u32,
+                hour: tm.tm_hour as u32,
+                minute: tm.tm_min as u32,
+            }
+        }
+    }
+}
+
+impl Locale {
+    pub fn format_number(&self, value: f64) -> String {
+        let (group_separator, decimal_separator) = match self {
+            Self::Vietnamese => ('.', ','),
+            Self::English => (',', '.'),
+        };
+        let formatted = format!("{:.6}", value.abs());
+        let (integer_part, fraction_part) = formatted
+            .split_once('.')
+            .unwrap_or((formatted.as_str(), ""));
+        let fraction_part = fraction_part.trim_end_matches('0');
+
+        let mut result = String::new();
+        if value < 0.0 && (integer_part != "0" || !fraction_part.is_empty()) {
+            result.push('-');
+        }
+        for (i, c) in integer_part.chars().enumerate() {
+            if i > 0 && (integer_part.len() - i) % 3 == 0 {
+                result.push(group_separator);
+            }
+            result.push(c);
+        }
+        if !fraction_part.is_empty() {
+            result.push(decimal_separator);
+            result.push_str(fraction_part);
+        }
+        result
+    }
+
+    pub fn format_date(&self, date: &LocalDateTime) -> String {
+        match self {
+            Self::Vietnamese => format!("{:02}/{:02}/{}", date.day, date.month, date.year),
+            Self::English => format!("{:02}/{:02}/{}", date.month, date.day, date.year),
+        }
+    }
+
+    pub fn format_time(&self, time: &LocalDateTime) -> String {
+        match self {
+            Self::Vietnamese => format!("{:02}:{:02}", time.hour, time.minute),
+            Self::English => {
+                let period = if time.hour < 12 { "AM" } else { "PM" };
+                let hour = match time.hour % 12 {
+                    0 => 12,
+                    h => h,
+                };
+                format!("{}:{:02} {}", hour, time.minute, period)
+            }
+        }
+    }
+
+    pub fn format_date_time(&self, date_time: &LocalDateTime) -> String {
+        match self {
+            Self::Vietnamese => format!(
+                "{} {}",
+                self.format_time(date_time),
+                self.format_date(date_time)
+            ),
+            Self::English => format!(
+                "{} {}",
+                self.format_date(date_time),
+                self.format_time(date_time)
+            ),
+        }
+    }
+}
+
+/// Expands the placeholders in the target of a template macro:
+///
+/// - `{date}`, `{time}`, `{datetime}`: the current date and time
+/// - `{=1200*1.1}`: the result of a simple arithmetic expression
+///
+/// Unknown or invalid placeholders are kept as is.
+pub fn expand_template(template: &str, locale: Locale, now: &LocalDateTime) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 1..end];
+        let expanded = match placeholder {
+            "date" => Some(locale.format_date(now)),
+            "time" => Some(locale.format_time(now)),
+            "datetime" => Some(locale.format_date_time(now)),
+            p => p
+                .strip_prefix('=')
+                .and_then(evaluate_expression)
+                .map(|value| locale.format_number(value)),
+        };
+        match expanded {
+            Some(expanded) => result.push_str(&expanded),
+            None => result.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Parentheses and minus signs nested deeper than this are rejected, so a
+// macro can't overflow the stack of the event tap thread
+const MAX_EXPRESSION_DEPTH: usize = 32;
+
+// A tiny recursive descent parser for +, -, *, / and parentheses
+fn evaluate_expression(input: &str) -> Option<f64> {
+    let tokens: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut pos = 0;
+    let value = parse_sum(&tokens, &mut pos, 0)?;
+    if pos == tokens.len() && value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn parse_sum(tokens: &[char], pos: &mut usize, depth: usize) -> Option<f64> {
+    let mut value = parse_product(tokens, pos, depth)?;
+    while let Some(&op) = tokens.get(*pos) {
+        match op {
+            '+' | '-' => {
+                *pos += 1;
+                let rhs = parse_product(tokens, pos, depth)?;
+                value = if op == '+' { value + rhs } else { value - rhs };
+            }
+            _ => break,
+        }
+    }
+    Some(value)
+}
+
+fn parse_product(tokens: &[char], pos: &mut usize, depth: usize) -> Option<f64> {
+    let mut value = parse_factor(tokens, pos, depth)?;
+    while let Some(&op) = tokens.get(*pos) {
+        match op {
+            '*' | '/' => {
+                *pos += 1;
+                let rhs = parse_factor(tokens, pos, depth)?;
+                value = if op == '*' { value * rhs } else { value / rhs };
+            }
+            _ => break,
+        }
+    }
+    Some(value)
+}
+
+fn parse_factor(tokens: &[char], pos: &mut usize, depth: usize) -> Option<f64> {
+    if depth > MAX_EXPRESSION_DEPTH {
+        return None;
+    }
+    match tokens.get(*pos)? {
+        '-' => {
+            *pos += 1;
+            parse_factor(tokens, pos, depth + 1).map(|value| -value)
+        }
+        '(' => {
+            *pos += 1;
+            let value = parse_sum(tokens, pos, depth + 1)?;
+            if tokens.get(*pos) != Some(&')') {
+                return None;
+            }
+            *pos += 1;
+            Some(value)
+        }
+        _ => {
+            let start = *pos;
+            while tokens
+                .get(*pos)
+                .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+            {
+                *pos += 1;
+            }
+            tokens[start..*pos].iter().collect::<String>().parse().ok()
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_date_time() -> LocalDateTime {
+    LocalDateTime {
+        year: 2026,
+        month: 3,
+        day: 7,
+        hour: 14,
+        minute: 5,
+    }
+}
+
+#[test]
+fn test_format_number() {
+    assert_eq!(Locale::Vietnamese.format_number(1234567.5), "1.234.567,5");
+    assert_eq!(Locale::English.format_number(1234567.5), "1,234,567.5");
+    assert_eq!(Locale::English.format_number(-1000.0), "-1,000");
+    assert_eq!(Locale::Vietnamese.format_number(0.25), "0,25");
+    assert_eq!(Locale::Vietnamese.format_number(999.0), "999");
+}
+
+#[test]
+fn test_format_date_time() {
+    let now = test_date_time();
+    assert_eq!(Locale::Vietnamese.format_date(&now), "07/03/2026");
+    assert_eq!(Locale::English.format_date(&now), "03/07/2026");
+    assert_eq!(Locale::Vietnamese.format_time(&now), "14:05");
+    assert_eq!(Locale::English.format_time(&now), "2:05 PM");
+}
+
+#[test]
+fn test_expand_template() {
+    let now = test_date_time();
+    assert_eq!(
+        expand_template("Hà Nội, ngày {date}", Locale::Vietnamese, &now),
+        "Hà Nội, ngày 07/03/2026"
+    );
+    assert_eq!(
+        expand_template("total: {=1200*1.5+(2-1)}", Locale::English, &now),
+        "total: 1,801"
+    );
+    assert_eq!(expand_template("{=10/4}", Locale::Vietnamese, &now), "2,5");
+}
+
+#[test]
+fn test_expand_template_keeps_invalid_placeholders() {
+    let now = test_date_time();
+    assert_eq!(
+        expand_template("{unknown} {=1/0} {=2+} {date", Locale::English, &now),
+        "{unknown} {=1/0} {=2+} {date"
+    );
+    assert_eq!(
+        expand_template("no placeholder", Locale::English, &now),
+        "no placeholder"
+    );
+    // Too deep to evaluate
+    let nested = format!("{{={}1{}}}", "(".repeat(1000), ")".repeat(1000));
+    assert_eq!(expand_template(&nested, Locale::English, &now), nested);
+    let negated = format!("{{={}1}}", "-".repeat(1000));
+    assert_eq!(expand_template(&negated, Locale::English, &now), negated);
+    assert_eq!(expand_template("{=((-(2)))}", Locale::English, &now), "-2");
+}
+
+#[test]
+fn test_parse_locale() {
+    assert_eq!(Locale::from_str("en_US"), Ok(Locale::English));
+    assert_eq!(Locale::from_str("vi_VN"), Ok(Locale::Vietnamese));
+    assert_eq!(Locale::English.to_string(), "en_US");
+}
@@ -0,0 +1,113 @@
+//! One-way export of the macro table to macOS text replacements. There is no
+//! public API to write them, so the macros are saved as a property list that
+//! can be dragged into System Settings > Keyboard > Text Replacements, from
+//! where iCloud also brings them to iOS.
+
+use std::collections::BTreeMap;
+
+use crate::template::{expand_template, LocalDateTime, Locale};
+
+pub const EXPORT_FILE_NAME: &str = "Gõ tắt.plist";
+
+/// A macro whose trigger is already a text replacement for another phrase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextReplacementConflict {
+    pub shortcut: String,
+    pub macro_target: String,
+    pub existing_phrase: String,
+}
+
+// Dates and calculations would be frozen at the time of the export
+fn is_static_target(target: &str) -> bool {
+    expand_template(target, Locale::Vietnamese, &LocalDateTime::now()) == target
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The macros in the format System Settings imports. Template macros with
+/// dynamic placeholders are left out.
+pub fn build_text_replacements_plist(
+    macros: &BTreeMap<String, String>,
+    template_macros: &[String],
+) -> String {
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<array>\n",
+    );
+    let is_exported =
+        |from: &String, to: &str| !template_macros.contains(from) || is_static_target(to);
+    for (shortcut, phrase) in macros.iter().filter(|(from, to)| is_exported(from, to)) {
+        plist.push_str(&format!(
+            "\t<dict>\n\t\t<key>phrase</key>\n\t\t<string>{}</string>\n\t\t<key>shortcut</key>\n\t\t<string>{}</string>\n\t</dict>\n",
+            escape_xml(phrase),
+            escape_xml(shortcut)
+        ));
+    }
+    plist.push_str("</array>\n</plist>\n");
+    plist
+}
+
+/// Lists the macros that would replace an existing text replacement with a
+/// different phrase.
+pub fn find_conflicts(
+    macros: &BTreeMap<String, String>,
+    existing: &[(String, String)],
+) -> Vec<TextReplacementConflict> {
+    existing
+        .iter()
+        .filter_map(|(shortcut, phrase)| {
+            let target = macros.get(shortcut)?;
+            (target != phrase).then(|| TextReplacementConflict {
+                shortcut: shortcut.clone(),
+                macro_target: target.clone(),
+                existing_phrase: phrase.clone(),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_build_text_replacements_plist() {
+    let macros = BTreeMap::from([
+        ("hn".to_string(), "Hà Nội".to_string()),
+        ("rd".to_string(), "R&D <team>".to_string()),
+        ("nay".to_string(), "Hôm nay {date}".to_string()),
+        ("ngoac".to_string(), "{date}".to_string()),
+    ]);
+    let plist = build_text_replacements_plist(&macros, &["nay".to_string()]);
+    assert!(plist.contains(
+        "<key>phrase</key>\n\t\t<string>Hà Nội</string>\n\t\t<key>shortcut</key>\n\t\t<string>hn</string>"
+    ));
+    assert!(plist.contains("<string>R&amp;D &lt;team&gt;</string>"));
+    assert!(!plist.contains("nay"));
+    // Not a template, typed as is
+    assert!(plist.contains("<string>{date}</string>"));
+    assert!(plist.ends_with("</array>\n</plist>\n"));
+}
+
+#[test]
+fn test_find_conflicts() {
+    let macros = BTreeMap::from([
+        ("hn".to_string(), "Hà Nội".to_string()),
+        ("omw".to_string(), "Đang tới".to_string()),
+    ]);
+    let existing = vec![
+        ("omw".to_string(), "On my way!".to_string()),
+        ("hn".to_string(), "Hà Nội".to_string()),
+        ("tks".to_string(), "Thanks".to_string()),
+    ];
+    assert_eq!(
+        find_conflicts(&macros, &existing),
+        vec![TextReplacementConflict {
+            shortcut: "omw".to_string(),
+            macro_target: "Đang tới".to_string(),
+            existing_phrase: "On my way!".to_string(),
+        }]
+    );
+}
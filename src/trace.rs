@@ -0,0 +1,130 @@
+//! Traces of the key event pipeline, from the event tap to the keys sent
+//! back to the app, to find out why an app misbehaves without a rebuild.
+//! The stages to trace are set by hand in ~/.goxkey, for example:
+//!
+//! ```text
+//! trace-stages = tap,char,handler,transform,injection
+//! trace-app = Terminal
+//! ```
+//!
+//! `trace-app` limits the traces to the apps whose path contains it. The
+//! events go through the `log` crate at trace level, so they show up next to
+//! the other logs when started with `RUST_LOG=trace`.
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct TraceStages: u32 {
+        // Events as they come out of the event tap
+        const TAP = 0b00001;
+        // Key codes resolved to characters
+        const CHAR = 0b00010;
+        // The branch taken in event_handler
+        const HANDLER = 0b00100;
+        // Engine output for the typing buffer
+        const TRANSFORM = 0b01000;
+        // Backspaces and text sent to the app
+        const INJECTION = 0b10000;
+    }
+}
+
+const TRACE_STAGE_NAMES: [(TraceStages, &str); 5] = [
+    (TraceStages::TAP, "tap"),
+    (TraceStages::CHAR, "char"),
+    (TraceStages::HANDLER, "handler"),
+    (TraceStages::TRANSFORM, "transform"),
+    (TraceStages::INJECTION, "injection"),
+];
+
+static CONFIGURED_STAGES: AtomicU32 = AtomicU32::new(0);
+// What is_enabled reads on each key, updated on app switches
+static ENABLED_STAGES: AtomicU32 = AtomicU32::new(0);
+static TRACED_APP: Mutex<Option<String>> = Mutex::new(None);
+
+impl TraceStages {
+    pub fn from_names(names: &[String]) -> Self {
+        TRACE_STAGE_NAMES
+            .iter()
+            .filter(|(_, name)| names.iter().any(|n| n == name))
+            .fold(Self::empty(), |stages, (stage, _)| stages | *stage)
+    }
+}
+
+fn get_enabled_stages(stages: TraceStages, traced_app: Option<&str>, app: &str) -> TraceStages {
+    match traced_app {
+        Some(traced_app) if !app.contains(traced_app) => TraceStages::empty(),
+        _ => stages,
+    }
+}
+
+pub fn configure(stages: TraceStages, traced_app: Option<&str>, app: &str) {
+    CONFIGURED_STAGES.store(stages.bits(), Ordering::Relaxed);
+    *TRACED_APP.lock().unwrap() = traced_app.map(str::to_string);
+    on_app_changed(app);
+}
+
+pub fn on_app_changed(app: &str) {
+    let stages = TraceStages::from_bits_truncate(CONFIGURED_STAGES.load(Ordering::Relaxed));
+    let enabled_stages = get_enabled_stages(stages, TRACED_APP.lock().unwrap().as_deref(), app);
+    ENABLED_STAGES.store(enabled_stages.bits(), Ordering::Relaxed);
+}
+
+pub fn is_enabled(stages: TraceStages) -> bool {
+    ENABLED_STAGES.load(Ordering::Relaxed) & stages.bits() != 0
+}
+
+// Records an event for one stage, the fields are only evaluated when the
+// stage is traced
+macro_rules! trace_stage {
+    ($stage:ident, $($field:tt)+) => {
+        if $crate::trace::is_enabled($crate::trace::TraceStages::$stage) {
+            tracing::trace!(stage = stringify!($stage), $($field)+);
+        }
+    };
+}
+pub(crate) use trace_stage;
+
+#[test]
+fn test_trace_stages_from_names() {
+    let names = ["tap", "injection", "unknown"].map(String::from);
+    assert_eq!(
+        TraceStages::from_names(&names),
+        TraceStages::TAP | TraceStages::INJECTION
+    );
+    assert_eq!(TraceStages::from_names(&[]), TraceStages::empty());
+}
+
+#[test]
+fn test_trace_app_filter() {
+    let stages = TraceStages::TAP | TraceStages::CHAR;
+    assert_eq!(
+        get_enabled_stages(stages, None, "/Applications/Safari.app"),
+        stages
+    );
+    assert_eq!(
+        get_enabled_stages(
+            stages,
+            Some("Terminal"),
+            "/System/Applications/Utilities/Terminal.app"
+        ),
+        stages
+    );
+    assert_eq!(
+        get_enabled_stages(stages, Some("Terminal"), "/Applications/Safari.app"),
+        TraceStages::empty()
+    );
+}
+
+#[test]
+fn test_trace_stages_cleared_on_reload() {
+    configure(TraceStages::TAP, None, "/Applications/Safari.app");
+    assert!(is_enabled(TraceStages::TAP));
+    // A config without trace-stages turns the traces off again
+    configure(TraceStages::empty(), None, "/Applications/Safari.app");
+    assert!(!is_enabled(TraceStages::TAP));
+}
@@ -0,0 +1,91 @@
+//! A short typing exercise for new users: a few words to type with the
+//! chosen method, checked once typed, with hints taken from the cheat-sheet
+//! rules for the letters that need them.
+
+use crate::cheatsheet::RuleDescription;
+
+// Words that are written the same with both tone styles
+pub const EXERCISES: [&str; 5] = ["Việt Nam", "xin chào", "tiếng Việt", "cảm ơn", "đường phố"];
+
+// Each vowel followed by its toned forms, in the order of TONE_SAMPLES
+const TONE_GROUPS: [&str; 12] = [
+    "aáàảãạ",
+    "ăắằẳẵặ",
+    "âấầẩẫậ",
+    "eéèẻẽẹ",
+    "êếềểễệ",
+    "iíìỉĩị",
+    "oóòỏõọ",
+    "ôốồổỗộ",
+    "ơớờởỡợ",
+    "uúùủũụ",
+    "ưứừửữự",
+    "yýỳỷỹỵ",
+];
+// How the cheat-sheet descriptions show each tone, see cheatsheet::TONES
+const TONE_SAMPLES: [char; 5] = ['á', 'à', 'ả', 'ã', 'ạ'];
+const MODIFIED_LETTERS: [char; 7] = ['ă', 'â', 'ê', 'ô', 'ơ', 'ư', 'đ'];
+
+pub fn is_correct(word: &str, typed: &str) -> bool {
+    typed.trim() == word
+}
+
+// The letter without its tone, and the tone sample if it has one
+fn split_tone(c: char) -> (char, Option<char>) {
+    let c = c.to_lowercase().next().unwrap_or(c);
+    for group in TONE_GROUPS {
+        if let Some(index) = group.chars().position(|toned| toned == c) {
+            let letter = group.chars().next().unwrap_or(c);
+            return (letter, index.checked_sub(1).map(|tone| TONE_SAMPLES[tone]));
+        }
+    }
+    (c, None)
+}
+
+/// The rules used to type a word, in the order they are needed.
+pub fn get_hints(word: &str, rules: &[RuleDescription]) -> Vec<RuleDescription> {
+    let mut hints: Vec<RuleDescription> = Vec::new();
+    for c in word.chars() {
+        let (letter, tone) = split_tone(c);
+        let patterns = MODIFIED_LETTERS
+            .contains(&letter)
+            .then(|| format!("→ {}", letter))
+            .into_iter()
+            .chain(tone.map(|sample| format!("({})", sample)));
+        for pattern in patterns {
+            let rule = rules
+                .iter()
+                .find(|rule| rule.description.contains(&pattern));
+            if let Some(rule) = rule.filter(|rule| !hints.contains(rule)) {
+                hints.push(rule.clone());
+            }
+        }
+    }
+    hints
+}
+
+#[test]
+fn test_tutorial_hints() {
+    use crate::{cheatsheet::describe_method, input::TypingMethod};
+
+    let keys = |word: &str, method| {
+        get_hints(word, &describe_method(method))
+            .into_iter()
+            .map(|rule| rule.keys)
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(keys("Việt Nam", TypingMethod::Telex), ["e", "j"]);
+    assert_eq!(keys("Việt Nam", TypingMethod::VNI), ["6", "5"]);
+    assert_eq!(
+        keys("đường phố", TypingMethod::Telex),
+        ["d", "w", "f", "o", "s"]
+    );
+    assert_eq!(keys("xin chao", TypingMethod::Telex), Vec::<String>::new());
+}
+
+#[test]
+fn test_tutorial_answer() {
+    assert!(is_correct("Việt Nam", "Việt Nam "));
+    assert!(!is_correct("Việt Nam", "Viet Nam"));
+    assert!(!is_correct("Việt Nam", ""));
+}
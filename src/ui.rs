@@ -1,36 +1,96 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    input::{rebuild_keyboard_layout_map, TypingMethod, INPUT_STATE},
+    cheatsheet::{describe_method, RuleDescription},
+    compat,
+    config::{
+        AppPolicy, ConfigStore, AUTOS_TOGGLE_ENABLED_CONFIG_KEY, AUTO_LEARN_APPS_CONFIG_KEY,
+        CONFIG_MANAGER, DEBUG_INDICATOR_CONFIG_KEY, ENGINE_FEATURES_CONFIG_KEY,
+        GLOBE_KEY_BEHAVIOR_CONFIG_KEY, HOTKEY_CONFIG_KEY, LOCALE_CONFIG_KEY,
+        LOW_POWER_ENABLED_CONFIG_KEY, MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY, MACRO_ENABLED_CONFIG_KEY,
+        TYPING_METHOD_CONFIG_KEY, UNKNOWN_APP_MODE_CONFIG_KEY,
+    },
+    handle_app_change,
+    hotkey::{format_key_name, function_key, function_key_number, Hotkey, HotkeyAction},
+    input::{
+        get_app_input_mode, rebuild_keyboard_layout_map, EngineFeatures, GlobeKeyBehavior,
+        MacroConflictBehavior, TypingMethod, UnknownAppMode, INPUT_STATE,
+    },
+    migration::{detect_installed_imes, parse_macro_file, DetectedIme, ImportedSettings},
     platform::{
-        is_launch_on_login, update_launch_on_login, KeyModifier, SystemTray, SystemTrayMenuItemKey,
-        SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT, SYMBOL_SUPER,
+        get_app_display_name, get_app_icon, get_running_apps, get_text_replacements,
+        is_accessibility_trusted, is_function_key_standard, is_launch_on_login,
+        open_accessibility_settings, open_in_text_editor, open_url, remove_app_change_callbacks,
+        reveal_in_file_manager, stop_event_listener, update_launch_on_login, KeyModifier,
+        SystemTray, SystemTrayMenuItemKey, Tray, SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT,
+        SYMBOL_SUPER,
+    },
+    safe_mode,
+    settings_server::get_settings_page_url,
+    template::Locale,
+    text_replacement::{
+        build_text_replacements_plist, find_conflicts, TextReplacementConflict, EXPORT_FILE_NAME,
     },
+    tutorial,
+    validation::get_user_dictionary_path,
     UI_EVENT_SINK,
 };
 use druid::{
-    commands::QUIT_APP,
+    commands::{OPEN_FILE, QUIT_APP, SAVE_FILE_AS, SHOW_OPEN_PANEL, SHOW_SAVE_PANEL},
+    lens::Constant,
+    text::{RichText, RichTextBuilder},
     theme::{BACKGROUND_DARK, BORDER_DARK, PLACEHOLDER_COLOR},
     widget::{
-        Button, Checkbox, Container, Controller, FillStrat, Flex, Image, Label, LineBreaking, List,
-        RadioGroup, Scroll, Switch, TextBox,
+        Button, Checkbox, Container, Controller, Either, FillStrat, Flex, Image, Label,
+        LineBreaking, List, RadioGroup, RawLabel, Scroll, SizedBox, Switch, TextBox, ViewSwitcher,
     },
-    Application, Color, Data, Env, Event, EventCtx, ImageBuf, Lens, Screen, Selector, Target,
-    Widget, WidgetExt, WindowDesc,
+    Application, Color, Data, Env, Event, EventCtx, FileDialogOptions, FontWeight, ImageBuf, Lens,
+    LensExt, Screen, Selector, Target, TimerToken, Widget, WidgetExt, WindowDesc, WindowState,
 };
-use log::error;
+use log::{debug, error};
 
 pub const UPDATE_UI: Selector = Selector::new("gox-ui.update-ui");
+// The frontmost app changed, handled once no other change came for the interval
+pub const APP_CHANGED: Selector<Duration> = Selector::new("gox-ui.app-changed");
 pub const SHOW_UI: Selector = Selector::new("gox-ui.show-ui");
-const DELETE_MACRO: Selector<String> = Selector::new("gox-ui.delete-macro");
+// Carries the time the app started, to measure how long the tray took
+pub const SHOW_TRAY: Selector<Instant> = Selector::new("gox-ui.show-tray");
+pub const SHOW_WHATS_NEW: Selector<String> = Selector::new("gox-ui.show-whats-new");
+pub const SHOW_MIGRATION: Selector = Selector::new("gox-ui.show-migration");
+pub const SHOW_TUTORIAL: Selector = Selector::new("gox-ui.show-tutorial");
+pub const SHOW_LATENCY_WARNING: Selector<String> = Selector::new("gox-ui.show-latency-warning");
+pub const SHOW_INJECTION_ERROR: Selector<String> = Selector::new("gox-ui.show-injection-error");
+pub const UPDATE_DEBUG_INDICATOR: Selector<(bool, usize)> =
+    Selector::new("gox-ui.update-debug-indicator");
+const ENABLE_FAST_MODE: Selector<String> = Selector::new("gox-ui.enable-fast-mode");
+const DISABLE_FAST_MODE: Selector<String> = Selector::new("gox-ui.disable-fast-mode");
+pub const DELETE_MACRO: Selector<String> = Selector::new("gox-ui.delete-macro");
+pub const SET_MACRO: Selector<(String, String)> = Selector::new("gox-ui.set-macro");
+pub const SET_APP_POLICY: Selector<(String, AppPolicy)> = Selector::new("gox-ui.set-app-policy");
 const ADD_MACRO: Selector = Selector::new("gox-ui.add-macro");
+const SET_TEMPLATE_MACRO: Selector<(String, bool)> = Selector::new("gox-ui.set-template-macro");
+const SET_ALT_PASSTHROUGH_APP: Selector<(String, bool)> =
+    Selector::new("gox-ui.set-alt-passthrough-app");
+const REMOVE_APP: Selector<String> = Selector::new("gox-ui.remove-app");
+const SHUTDOWN: Selector = Selector::new("gox-ui.shutdown");
+const SET_HOTKEY_ERROR: Selector<String> = Selector::new("gox-ui.set-hotkey-error");
+const SAVE_ACTION_HOTKEYS: Selector = Selector::new("gox-ui.save-action-hotkeys");
+const SHOW_CHEAT_SHEET: Selector = Selector::new("gox-ui.show-cheat-sheet");
 pub const WINDOW_WIDTH: f64 = 335.0;
-pub const WINDOW_HEIGHT: f64 = 375.0;
+pub const WINDOW_HEIGHT: f64 = 483.0;
 
 pub fn format_letter_key(c: Option<char>) -> String {
     if let Some(c) = c {
         return if c.is_ascii_whitespace() {
             String::from("Space")
+        } else if let Some(number) = function_key_number(c) {
+            format!("F{}", number)
         } else {
             c.to_ascii_uppercase().to_string()
         };
@@ -42,7 +102,9 @@ pub fn letter_key_to_char(input: &str) -> Option<char> {
     match input {
         "Space" => Some(' '),
         s => {
-            if input.len() > 1 {
+            if let Some(number) = s.strip_prefix(['F', 'f']).filter(|n| !n.is_empty()) {
+                number.parse().ok().and_then(function_key)
+            } else if input.len() > 1 {
                 None
             } else {
                 s.chars().last()
@@ -52,13 +114,13 @@ pub fn letter_key_to_char(input: &str) -> Option<char> {
 }
 
 struct LetterKeyController;
-impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for LetterKeyController {
+impl<W: Widget<HotkeySettings>> Controller<HotkeySettings, W> for LetterKeyController {
     fn event(
         &mut self,
         child: &mut W,
         ctx: &mut EventCtx,
         event: &Event,
-        data: &mut UIDataAdapter,
+        data: &mut HotkeySettings,
         env: &Env,
     ) {
         if let &Event::MouseDown(_) = event {
@@ -80,164 +142,599 @@ impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for LetterKeyControl
 struct MacroEntry {
     from: String,
     to: String,
+    is_template: bool,
+}
+
+#[derive(Clone, Data, PartialEq, Eq)]
+struct AppEntry {
+    path: String,
+    name: String,
+    mode: String,
+}
+
+#[derive(Clone, Data, PartialEq, Eq)]
+struct RunningAppEntry {
+    path: String,
+    name: String,
+    mode: String,
+    reason: String,
+    is_fast_mode: bool,
+    is_alt_passthrough: bool,
 }
 
 #[derive(Clone, Data, Lens, PartialEq, Eq)]
-pub struct UIDataAdapter {
+pub struct GeneralSettings {
     is_enabled: bool,
     typing_method: TypingMethod,
-    hotkey_display: String,
+    globe_key_behavior: GlobeKeyBehavior,
     launch_on_login: bool,
     is_auto_toggle_enabled: bool,
-    // Macro config
-    is_macro_enabled: bool,
-    macro_table: Arc<Vec<MacroEntry>>,
-    new_macro_from: String,
-    new_macro_to: String,
-    // Hotkey config
+    unknown_app_mode: UnknownAppMode,
+    is_auto_learn_enabled: bool,
+    is_low_power_enabled: bool,
+}
+
+impl GeneralSettings {
+    fn new() -> Self {
+        Self {
+            is_enabled: true,
+            typing_method: TypingMethod::Telex,
+            globe_key_behavior: GlobeKeyBehavior::Toggle,
+            launch_on_login: false,
+            is_auto_toggle_enabled: false,
+            unknown_app_mode: UnknownAppMode::KeepCurrent,
+            is_auto_learn_enabled: true,
+            is_low_power_enabled: false,
+        }
+    }
+
+    fn update(&mut self) {
+        unsafe {
+            self.is_enabled = INPUT_STATE.is_enabled();
+            self.typing_method = INPUT_STATE.get_method();
+            self.globe_key_behavior = INPUT_STATE.get_globe_key_behavior();
+            self.is_auto_toggle_enabled = INPUT_STATE.is_auto_toggle_enabled();
+            self.unknown_app_mode = INPUT_STATE.get_unknown_app_mode();
+            self.is_auto_learn_enabled = INPUT_STATE.is_auto_learn_enabled();
+            self.is_low_power_enabled = INPUT_STATE.is_low_power_enabled();
+        }
+        self.launch_on_login = is_launch_on_login();
+    }
+}
+
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+pub struct HotkeySettings {
+    display: String,
+    error: String,
     super_key: bool,
     ctrl_key: bool,
     alt_key: bool,
     shift_key: bool,
     capslock_key: bool,
     letter_key: String,
-    // system tray
-    systray: SystemTray,
 }
 
-impl UIDataAdapter {
-    pub fn new() -> Self {
-        let mut ret = Self {
-            is_enabled: true,
-            typing_method: TypingMethod::Telex,
-            hotkey_display: String::new(),
-            launch_on_login: false,
-            is_auto_toggle_enabled: false,
-            is_macro_enabled: false,
-            macro_table: Arc::new(Vec::new()),
-            new_macro_from: String::new(),
-            new_macro_to: String::new(),
+impl HotkeySettings {
+    fn new() -> Self {
+        Self {
+            display: String::new(),
+            error: String::new(),
             super_key: true,
             ctrl_key: true,
             alt_key: false,
             shift_key: false,
             capslock_key: false,
             letter_key: String::from("Space"),
-            systray: SystemTray::new(),
+        }
+    }
+
+    fn update(&mut self) {
+        let hotkey = unsafe { INPUT_STATE.get_hotkey() };
+        self.display = format_hotkey(hotkey);
+        self.error = String::new();
+        let (modifiers, keycode) = hotkey.inner();
+        self.super_key = modifiers.is_super();
+        self.ctrl_key = modifiers.is_control();
+        self.alt_key = modifiers.is_alt();
+        self.shift_key = modifiers.is_shift();
+        self.letter_key = format_letter_key(keycode);
+    }
+}
+
+// The hotkeys of the actions in hotkey.rs, written as in the config file
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+pub struct ActionHotkeySettings {
+    expand_macro: String,
+    transform_word: String,
+    transform_selection: String,
+    error: String,
+}
+
+impl ActionHotkeySettings {
+    fn new() -> Self {
+        Self {
+            expand_macro: String::new(),
+            transform_word: String::new(),
+            transform_selection: String::new(),
+            error: String::new(),
+        }
+    }
+
+    fn update(&mut self) {
+        let config = CONFIG_MANAGER.lock().unwrap();
+        self.expand_macro = config.get_macro_expand_hotkey().to_string();
+        self.transform_word = config.get_transform_word_hotkey().to_string();
+        self.transform_selection = config.get_transform_selection_hotkey().to_string();
+    }
+
+    // Saves every hotkey that can be saved, the errors are kept for the others
+    fn save(&mut self) {
+        let errors = [
+            (HotkeyAction::ExpandMacro, &self.expand_macro, "Gõ tắt"),
+            (
+                HotkeyAction::TransformWord,
+                &self.transform_word,
+                "Chuyển từ",
+            ),
+            (
+                HotkeyAction::TransformSelection,
+                &self.transform_selection,
+                "Chuyển vùng chọn",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(action, key_sequence, label)| {
+            unsafe { INPUT_STATE.set_action_hotkey(action, key_sequence.trim()) }
+                .err()
+                .map(|err| format!("{}: {}", label, err))
+        })
+        .collect::<Vec<_>>();
+        self.error = errors.join(", ");
+    }
+}
+
+// Progress in the typing exercise, see tutorial.rs
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+pub struct TutorialState {
+    step: usize,
+    input: String,
+    is_checked: bool,
+}
+
+impl TutorialState {
+    fn new() -> Self {
+        Self {
+            step: 0,
+            input: String::new(),
+            is_checked: false,
+        }
+    }
+
+    fn get_word(&self) -> &'static str {
+        tutorial::EXERCISES[self.step]
+    }
+
+    fn is_correct(&self) -> bool {
+        tutorial::is_correct(self.get_word(), &self.input)
+    }
+
+    fn is_last_step(&self) -> bool {
+        self.step + 1 == tutorial::EXERCISES.len()
+    }
+
+    fn next_step(&mut self) {
+        *self = Self {
+            step: self.step + 1,
+            ..Self::new()
         };
-        ret.setup_system_tray_actions();
-        ret.update();
-        ret
     }
+}
 
-    pub fn update(&mut self) {
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+pub struct MacroSettings {
+    is_enabled: bool,
+    conflict_behavior: MacroConflictBehavior,
+    locale: Locale,
+    // Sorted by trigger, like the macro table itself
+    table: Arc<Vec<MacroEntry>>,
+    new_from: String,
+    new_to: String,
+}
+
+impl MacroSettings {
+    fn new() -> Self {
+        Self {
+            is_enabled: false,
+            conflict_behavior: MacroConflictBehavior::Expand,
+            locale: Locale::Vietnamese,
+            table: Arc::new(Vec::new()),
+            new_from: String::new(),
+            new_to: String::new(),
+        }
+    }
+
+    fn update(&mut self) {
         unsafe {
-            self.is_enabled = INPUT_STATE.is_enabled();
-            self.typing_method = INPUT_STATE.get_method();
-            self.hotkey_display = INPUT_STATE.get_hotkey().to_string();
-            self.is_macro_enabled = INPUT_STATE.is_macro_enabled();
-            self.is_auto_toggle_enabled = INPUT_STATE.is_auto_toggle_enabled();
-            self.launch_on_login = is_launch_on_login();
-            self.macro_table = Arc::new(
-                INPUT_STATE
-                    .get_macro_table()
+            self.is_enabled = INPUT_STATE.is_macro_enabled();
+            self.conflict_behavior = INPUT_STATE.get_macro_conflict_behavior();
+            self.locale = INPUT_STATE.get_locale();
+            self.sync_table(
+                INPUT_STATE.get_macro_table(),
+                INPUT_STATE.get_template_macros(),
+            );
+        }
+    }
+
+    // Keeps the same list when nothing changed, so the macro editor isn't
+    // rebuilt on every update
+    fn sync_table(&mut self, macros: &BTreeMap<String, String>, template_macros: &[String]) {
+        let is_same = self.table.len() == macros.len()
+            && self.table.iter().zip(macros).all(|(entry, (from, to))| {
+                entry.from == *from
+                    && entry.to == *to
+                    && entry.is_template == template_macros.contains(from)
+            });
+        if !is_same {
+            self.table = Arc::new(
+                macros
                     .iter()
-                    .map(|(source, target)| MacroEntry {
-                        from: source.to_string(),
-                        to: target.to_string(),
+                    .map(|(from, to)| MacroEntry {
+                        from: from.to_string(),
+                        to: to.to_string(),
+                        is_template: template_macros.contains(from),
                     })
-                    .collect::<Vec<MacroEntry>>(),
+                    .collect(),
             );
+        }
+    }
 
-            let (modifiers, keycode) = INPUT_STATE.get_hotkey().inner();
-            self.super_key = modifiers.is_super();
-            self.ctrl_key = modifiers.is_control();
-            self.alt_key = modifiers.is_alt();
-            self.shift_key = modifiers.is_shift();
-            self.letter_key = format_letter_key(keycode);
-
-            match self.is_enabled {
-                true => {
-                    let title = if INPUT_STATE.is_gox_mode_enabled() {
-                        "gõ"
-                    } else {
-                        "VN"
-                    };
-                    self.systray.set_title(title);
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::Enable, "Tắt gõ tiếng Việt");
-                }
-                false => {
-                    let title = if INPUT_STATE.is_gox_mode_enabled() {
-                        match self.typing_method {
-                            TypingMethod::Telex => "gox",
-                            TypingMethod::VNI => "go4",
-                        }
-                    } else {
-                        "EN"
-                    };
-                    self.systray.set_title(title);
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::Enable, "Bật gõ tiếng Việt");
-                }
-            }
-            match self.typing_method {
-                TypingMethod::VNI => {
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex, "Telex");
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI, "VNI ✓");
-                }
-                TypingMethod::Telex => {
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex, "Telex ✓");
-                    self.systray
-                        .set_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI, "VNI");
-                }
+    fn insert_entry(&mut self, from: String, to: String) {
+        let table = Arc::make_mut(&mut self.table);
+        match table.binary_search_by(|entry| entry.from.cmp(&from)) {
+            Ok(index) => table[index].to = to,
+            Err(index) => table.insert(
+                index,
+                MacroEntry {
+                    from,
+                    to,
+                    is_template: false,
+                },
+            ),
+        }
+    }
+
+    fn set_template_entry(&mut self, from: &str, is_template: bool) {
+        if let Ok(index) = self
+            .table
+            .binary_search_by(|entry| entry.from.as_str().cmp(from))
+        {
+            Arc::make_mut(&mut self.table)[index].is_template = is_template;
+        }
+    }
+
+    fn remove_entry(&mut self, from: &str) {
+        if let Ok(index) = self
+            .table
+            .binary_search_by(|entry| entry.from.as_str().cmp(from))
+        {
+            Arc::make_mut(&mut self.table).remove(index);
+        }
+    }
+}
+
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+struct FeatureSettings {
+    is_auto_restore_enabled: bool,
+    is_selection_compensation_enabled: bool,
+    is_firefox_workaround_enabled: bool,
+    is_auto_capitalize_enabled: bool,
+    is_old_tone_style: bool,
+    is_foreign_script_passthrough_enabled: bool,
+    is_english_dictionary_enabled: bool,
+    is_spelling_normalization_enabled: bool,
+    is_strict_vietnamese_enabled: bool,
+}
+
+impl FeatureSettings {
+    fn new() -> Self {
+        Self {
+            is_auto_restore_enabled: true,
+            is_selection_compensation_enabled: true,
+            is_firefox_workaround_enabled: true,
+            is_auto_capitalize_enabled: false,
+            is_old_tone_style: false,
+            is_foreign_script_passthrough_enabled: false,
+            is_english_dictionary_enabled: false,
+            is_spelling_normalization_enabled: false,
+            is_strict_vietnamese_enabled: false,
+        }
+    }
+
+    fn update(&mut self) {
+        let features = unsafe { INPUT_STATE.get_features() };
+        self.is_auto_restore_enabled = features.contains(EngineFeatures::AUTO_RESTORE);
+        self.is_selection_compensation_enabled =
+            features.contains(EngineFeatures::SELECTION_COMPENSATION);
+        self.is_firefox_workaround_enabled = features.contains(EngineFeatures::FIREFOX_WORKAROUND);
+        self.is_auto_capitalize_enabled = features.contains(EngineFeatures::AUTO_CAPITALIZE);
+        self.is_old_tone_style = features.contains(EngineFeatures::OLD_TONE_STYLE);
+        self.is_foreign_script_passthrough_enabled =
+            features.contains(EngineFeatures::FOREIGN_SCRIPT_PASSTHROUGH);
+        self.is_english_dictionary_enabled = features.contains(EngineFeatures::ENGLISH_DICTIONARY);
+        self.is_spelling_normalization_enabled =
+            features.contains(EngineFeatures::SPELLING_NORMALIZATION);
+        self.is_strict_vietnamese_enabled = features.contains(EngineFeatures::STRICT_VIETNAMESE);
+    }
+
+    // Each toggle with the engine feature it stands for
+    fn entries(&self) -> [(EngineFeatures, bool); 9] {
+        [
+            (EngineFeatures::AUTO_RESTORE, self.is_auto_restore_enabled),
+            (
+                EngineFeatures::SELECTION_COMPENSATION,
+                self.is_selection_compensation_enabled,
+            ),
+            (
+                EngineFeatures::FIREFOX_WORKAROUND,
+                self.is_firefox_workaround_enabled,
+            ),
+            (
+                EngineFeatures::AUTO_CAPITALIZE,
+                self.is_auto_capitalize_enabled,
+            ),
+            (EngineFeatures::OLD_TONE_STYLE, self.is_old_tone_style),
+            (
+                EngineFeatures::FOREIGN_SCRIPT_PASSTHROUGH,
+                self.is_foreign_script_passthrough_enabled,
+            ),
+            (
+                EngineFeatures::ENGLISH_DICTIONARY,
+                self.is_english_dictionary_enabled,
+            ),
+            (
+                EngineFeatures::SPELLING_NORMALIZATION,
+                self.is_spelling_normalization_enabled,
+            ),
+            (
+                EngineFeatures::STRICT_VIETNAMESE,
+                self.is_strict_vietnamese_enabled,
+            ),
+        ]
+    }
+}
+
+#[derive(Clone, Data, Lens, PartialEq, Eq)]
+pub struct UIDataAdapter {
+    general: GeneralSettings,
+    hotkey: HotkeySettings,
+    action_hotkeys: ActionHotkeySettings,
+    macros: MacroSettings,
+    tutorial: TutorialState,
+    // Per-app config
+    app_list: Arc<Vec<AppEntry>>,
+    // Only listed while the Apps window is open
+    running_apps: Arc<Vec<RunningAppEntry>>,
+    is_app_list_open: bool,
+    features: FeatureSettings,
+    // Debugging
+    is_debug_indicator_enabled: bool,
+    // Empty when the settings server is off
+    settings_page_url: String,
+    // Settings managed by an administrator
+    locked_settings: Arc<Vec<String>>,
+    // system tray
+    systray: Option<Tray>,
+}
+
+impl UIDataAdapter {
+    pub fn new() -> Self {
+        let mut ret = Self {
+            general: GeneralSettings::new(),
+            hotkey: HotkeySettings::new(),
+            action_hotkeys: ActionHotkeySettings::new(),
+            macros: MacroSettings::new(),
+            tutorial: TutorialState::new(),
+            app_list: Arc::new(Vec::new()),
+            running_apps: Arc::new(Vec::new()),
+            is_app_list_open: false,
+            features: FeatureSettings::new(),
+            is_debug_indicator_enabled: false,
+            settings_page_url: String::new(),
+            locked_settings: Arc::new(Vec::new()),
+            systray: None,
+        };
+        ret.update();
+        ret
+    }
+
+    // The tray comes after the event tap has started, see SHOW_TRAY
+    fn set_systray(&mut self, systray: Tray) {
+        self.systray = Some(systray);
+        self.setup_system_tray_actions();
+        self.update_system_tray();
+    }
+
+    pub fn update(&mut self) {
+        self.general.update();
+        self.hotkey.update();
+        self.action_hotkeys.update();
+        self.macros.update();
+        self.features.update();
+        self.is_debug_indicator_enabled = unsafe { INPUT_STATE.is_debug_indicator_enabled() };
+        // Keeps the same lists when nothing changed, so the Apps window and
+        // the locked controls aren't rebuilt on every update
+        let app_list = get_app_list();
+        if *self.app_list != app_list {
+            self.app_list = Arc::new(app_list);
+        }
+        self.refresh_running_apps();
+        let locked_settings = get_locked_settings();
+        if *self.locked_settings != locked_settings {
+            self.locked_settings = Arc::new(locked_settings);
+        }
+        self.settings_page_url = get_settings_page_url().unwrap_or_default();
+        self.update_system_tray();
+    }
+
+    fn refresh_running_apps(&mut self) {
+        if self.is_app_list_open {
+            self.running_apps = Arc::new(get_running_app_list());
+        }
+    }
+
+    fn is_locked(&self, key: &str) -> bool {
+        self.locked_settings
+            .iter()
+            .any(|locked_key| locked_key == key)
+    }
+
+    fn update_system_tray(&self) {
+        let Some(systray) = &self.systray else {
+            return;
+        };
+        let is_gox_mode_enabled = unsafe { INPUT_STATE.is_gox_mode_enabled() };
+        let debug_state = self
+            .is_debug_indicator_enabled
+            .then(|| unsafe { INPUT_STATE.get_debug_state() });
+        update_tray_status(
+            systray,
+            self.general.is_enabled,
+            is_gox_mode_enabled,
+            self.general.typing_method,
+            debug_state,
+        );
+        self.update_current_app_menu(systray);
+    }
+
+    fn update_debug_indicator(&self, is_tracking: bool, buffer_length: usize) {
+        let Some(systray) = &self.systray else {
+            return;
+        };
+        let is_gox_mode_enabled = unsafe { INPUT_STATE.is_gox_mode_enabled() };
+        let title = tray_title(
+            self.general.is_enabled,
+            is_gox_mode_enabled,
+            self.general.typing_method,
+        );
+        systray.set_title(&format_debug_indicator(title, is_tracking, buffer_length));
+    }
+
+    fn update_current_app_menu(&self, systray: &Tray) {
+        let (active_app, policy) = unsafe {
+            (
+                INPUT_STATE.get_active_app().to_string(),
+                INPUT_STATE.get_active_app_policy(),
+            )
+        };
+        let title = if active_app.is_empty() {
+            String::from("Ứng dụng hiện tại")
+        } else {
+            format!("Ứng dụng hiện tại: {}", get_app_display_name(&active_app))
+        };
+        systray.set_menu_item_title(SystemTrayMenuItemKey::CurrentApp, &title);
+        for (key, target_policy, label) in [
+            (
+                SystemTrayMenuItemKey::AppPolicyVietnamese,
+                AppPolicy::Vietnamese,
+                "Luôn tiếng Việt",
+            ),
+            (
+                SystemTrayMenuItemKey::AppPolicyEnglish,
+                AppPolicy::English,
+                "Luôn tiếng Anh",
+            ),
+            (
+                SystemTrayMenuItemKey::AppPolicyDefault,
+                AppPolicy::Default,
+                "Theo mặc định",
+            ),
+            (
+                SystemTrayMenuItemKey::AppPolicyIgnored,
+                AppPolicy::Ignored,
+                "Bỏ qua hoàn toàn",
+            ),
+        ] {
+            if policy == target_policy {
+                systray.set_menu_item_title(key, &format!("{} ✓", label));
+            } else {
+                systray.set_menu_item_title(key, label);
             }
         }
     }
 
-    fn setup_system_tray_actions(&mut self) {
-        self.systray
-            .set_menu_item_callback(SystemTrayMenuItemKey::ShowUI, || {
-                UI_EVENT_SINK
-                    .get()
-                    .map(|event| Some(event.submit_command(SHOW_UI, (), Target::Auto)));
-            });
-        self.systray
-            .set_menu_item_callback(SystemTrayMenuItemKey::Enable, || {
-                unsafe {
-                    INPUT_STATE.toggle_vietnamese();
-                }
-                UI_EVENT_SINK
-                    .get()
-                    .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
-            });
-        self.systray
-            .set_menu_item_callback(SystemTrayMenuItemKey::TypingMethodTelex, || {
-                unsafe {
-                    INPUT_STATE.set_method(TypingMethod::Telex);
-                }
-                UI_EVENT_SINK
-                    .get()
-                    .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
-            });
-        self.systray
-            .set_menu_item_callback(SystemTrayMenuItemKey::TypingMethodVNI, || {
+    fn setup_system_tray_actions(&self) {
+        let Some(systray) = &self.systray else {
+            return;
+        };
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::ShowUI, || {
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(SHOW_UI, (), Target::Auto)));
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::CheatSheet, || {
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(SHOW_CHEAT_SHEET, (), Target::Auto)));
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::Enable, || {
+            unsafe {
+                INPUT_STATE.toggle_vietnamese();
+            }
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::TypingMethodTelex, || {
+            unsafe {
+                INPUT_STATE.set_method(TypingMethod::Telex);
+            }
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::TypingMethodVNI, || {
+            unsafe {
+                INPUT_STATE.set_method(TypingMethod::VNI);
+            }
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
+        });
+        for (key, policy) in [
+            (
+                SystemTrayMenuItemKey::AppPolicyVietnamese,
+                AppPolicy::Vietnamese,
+            ),
+            (SystemTrayMenuItemKey::AppPolicyEnglish, AppPolicy::English),
+            (SystemTrayMenuItemKey::AppPolicyDefault, AppPolicy::Default),
+            (SystemTrayMenuItemKey::AppPolicyIgnored, AppPolicy::Ignored),
+        ] {
+            systray.set_menu_item_callback(key, move || {
                 unsafe {
-                    INPUT_STATE.set_method(TypingMethod::VNI);
+                    INPUT_STATE.set_active_app_policy(policy);
                 }
                 UI_EVENT_SINK
                     .get()
                     .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
             });
-        self.systray
-            .set_menu_item_callback(SystemTrayMenuItemKey::Exit, || {
-                UI_EVENT_SINK
-                    .get()
-                    .map(|event| Some(event.submit_command(QUIT_APP, (), Target::Auto)));
-            });
+        }
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::OpenConfigFile, || {
+            open_in_text_editor(&get_config_file());
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::RevealConfigFile, || {
+            reveal_in_file_manager(&get_config_file());
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::RestartEngine, || {
+            crate::restart_engine();
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(UPDATE_UI, (), Target::Auto)));
+        });
+        systray.set_menu_item_callback(SystemTrayMenuItemKey::Exit, || {
+            UI_EVENT_SINK
+                .get()
+                .map(|event| Some(event.submit_command(SHUTDOWN, (), Target::Auto)));
+        });
     }
 
     pub fn toggle_vietnamese(&mut self) {
@@ -246,9 +743,211 @@ impl UIDataAdapter {
         }
         self.update();
     }
+
+    // Tear everything down in order before quitting, so we don't leave
+    // a ghost item in the menu bar. The config is saved by each setter, so
+    // the file isn't written here over edits made by hand.
+    fn shutdown(&mut self) {
+        safe_mode::record_clean_exit();
+        stop_event_listener();
+        remove_app_change_callbacks();
+        if let Some(systray) = &self.systray {
+            systray.remove();
+        }
+    }
+}
+
+// Tray title and the labels of the enable and typing method items
+fn tray_title(
+    is_enabled: bool,
+    is_gox_mode_enabled: bool,
+    typing_method: TypingMethod,
+) -> &'static str {
+    match (is_enabled, is_gox_mode_enabled) {
+        (true, true) => "gõ",
+        (true, false) => "VN",
+        (false, true) => match typing_method {
+            TypingMethod::Telex => "gox",
+            TypingMethod::VNI => "go4",
+        },
+        (false, false) => "EN",
+    }
+}
+
+// The tray title with the number of buffered keys, and a cross when the
+// engine stopped tracking the current word
+fn format_debug_indicator(title: &str, is_tracking: bool, buffer_length: usize) -> String {
+    if is_tracking {
+        format!("{} [{}]", title, buffer_length)
+    } else {
+        format!("{} [{} ✕]", title, buffer_length)
+    }
+}
+
+// The debug state goes in the same title, so the tray is not set twice
+fn update_tray_status(
+    systray: &Tray,
+    is_enabled: bool,
+    is_gox_mode_enabled: bool,
+    typing_method: TypingMethod,
+    debug_state: Option<(bool, usize)>,
+) {
+    let title = tray_title(is_enabled, is_gox_mode_enabled, typing_method);
+    match debug_state {
+        Some((is_tracking, buffer_length)) => {
+            systray.set_title(&format_debug_indicator(title, is_tracking, buffer_length))
+        }
+        None => systray.set_title(title),
+    }
+    if is_enabled {
+        systray.set_menu_item_title(SystemTrayMenuItemKey::Enable, "Tắt gõ tiếng Việt");
+    } else {
+        systray.set_menu_item_title(SystemTrayMenuItemKey::Enable, "Bật gõ tiếng Việt");
+    }
+    match typing_method {
+        TypingMethod::VNI => {
+            systray.set_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex, "Telex");
+            systray.set_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI, "VNI ✓");
+        }
+        TypingMethod::Telex => {
+            systray.set_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex, "Telex ✓");
+            systray.set_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI, "VNI");
+        }
+    }
+}
+
+// When the F-keys control the media, fn has to be held to press them
+fn format_hotkey(hotkey: &Hotkey) -> String {
+    if hotkey.is_function_key() && !is_function_key_standard() {
+        format!("fn {}", hotkey)
+    } else {
+        hotkey.to_string()
+    }
+}
+
+// Written out first when it doesn't exist yet, so there's something to open
+fn get_config_file() -> PathBuf {
+    let path = ConfigStore::get_config_path();
+    if !path.exists() {
+        CONFIG_MANAGER.lock().unwrap().flush();
+    }
+    path
+}
+
+fn open_user_dictionary() {
+    let path = match get_user_dictionary_path() {
+        Some(path) => path,
+        None => return,
+    };
+    // Created empty the first time, so the editor has something to open
+    if !path.exists() {
+        if let Err(err) = fs::write(&path, "") {
+            error!("Failed to create {}: {}", path.display(), err);
+            return;
+        }
+    }
+    open_in_text_editor(&path);
+}
+
+fn get_locked_settings() -> Vec<String> {
+    CONFIG_MANAGER.lock().unwrap().get_locked_keys().to_vec()
+}
+
+fn get_app_list() -> Vec<AppEntry> {
+    let config = CONFIG_MANAGER.lock().unwrap();
+    let vn_apps = config.get_vietnamese_apps().iter().map(|path| (path, "VN"));
+    let en_apps = config.get_english_apps().iter().map(|path| (path, "EN"));
+    let ignored_apps = config
+        .get_ignored_apps()
+        .iter()
+        .map(|path| (path, "Bỏ qua"));
+    let mut apps = vn_apps
+        .chain(en_apps)
+        .chain(ignored_apps)
+        .map(|(path, mode)| AppEntry {
+            path: path.to_string(),
+            name: get_app_display_name(path),
+            mode: mode.to_string(),
+        })
+        .collect::<Vec<AppEntry>>();
+    apps.sort_by_key(|app| app.name.to_lowercase());
+    apps
+}
+
+/// The input mode an app gets and why, as shown in the running apps list.
+fn describe_app_mode(
+    policy: AppPolicy,
+    is_learned: bool,
+    has_workarounds: bool,
+    is_auto_toggle_enabled: bool,
+    unknown_app_mode: UnknownAppMode,
+) -> (&'static str, String) {
+    let (mode, reason) = match policy {
+        AppPolicy::Ignored => ("Bỏ qua", "thủ công"),
+        _ if !is_auto_toggle_enabled => ("Hiện tại", "không tự chuyển"),
+        _ => (
+            match get_app_input_mode(policy, unknown_app_mode) {
+                Some(true) => "VN",
+                Some(false) => "EN",
+                None => "Hiện tại",
+            },
+            match policy {
+                AppPolicy::Default => "mặc định",
+                _ if is_learned => "tự học",
+                _ => "thủ công",
+            },
+        ),
+    };
+    if has_workarounds {
+        (mode, format!("{}, tương thích", reason))
+    } else {
+        (mode, reason.to_string())
+    }
+}
+
+fn get_running_app_list() -> Vec<RunningAppEntry> {
+    let (is_auto_toggle_enabled, unknown_app_mode) = unsafe {
+        (
+            INPUT_STATE.is_auto_toggle_enabled(),
+            INPUT_STATE.get_unknown_app_mode(),
+        )
+    };
+    let config = CONFIG_MANAGER.lock().unwrap();
+    let mut apps = get_running_apps()
+        .into_iter()
+        .map(|path| {
+            let (mode, reason) = describe_app_mode(
+                config.get_app_policy(&path),
+                config.is_learned_app(&path),
+                compat::has_workarounds(&path) || config.is_fast_mode_app(&path),
+                is_auto_toggle_enabled,
+                unknown_app_mode,
+            );
+            RunningAppEntry {
+                name: get_app_display_name(&path),
+                mode: mode.to_string(),
+                reason,
+                is_fast_mode: config.is_fast_mode_app(&path),
+                is_alt_passthrough: config.is_alt_passthrough_app(&path),
+                path,
+            }
+        })
+        .collect::<Vec<RunningAppEntry>>();
+    apps.sort_by_key(|app| app.name.to_lowercase());
+    apps
 }
 
-pub struct UIController;
+pub struct UIController {
+    app_change_timer: TimerToken,
+}
+
+impl UIController {
+    fn new() -> Self {
+        Self {
+            app_change_timer: TimerToken::INVALID,
+        }
+    }
+}
 
 impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for UIController {
     fn event(
@@ -260,35 +959,157 @@ impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for UIController {
         env: &Env,
     ) {
         match event {
+            Event::Timer(token) if *token == self.app_change_timer => {
+                ctx.set_handled();
+                self.app_change_timer = TimerToken::INVALID;
+                unsafe { handle_app_change() };
+                // Apps that were opened or quit
+                data.refresh_running_apps();
+            }
             Event::Command(cmd) => {
                 if cmd.get(UPDATE_UI).is_some() {
                     data.update();
                     rebuild_keyboard_layout_map();
                 }
+                if let Some(interval) = cmd.get(APP_CHANGED) {
+                    ctx.set_handled();
+                    // Replaces the timer of the app before
+                    self.app_change_timer = ctx.request_timer(*interval);
+                }
+                if cmd.get(SHUTDOWN).is_some() {
+                    ctx.set_handled();
+                    data.shutdown();
+                    ctx.submit_command(QUIT_APP);
+                }
                 if cmd.get(SHOW_UI).is_some() {
                     ctx.set_handled();
+                    ctx.window().set_window_state(WindowState::Restored);
                     ctx.window().bring_to_front_and_focus();
                 }
+                if cmd.get(SHOW_CHEAT_SHEET).is_some() {
+                    ctx.set_handled();
+                    let new_window = WindowDesc::new(cheat_sheet_ui_builder())
+                        .title("Hướng dẫn gõ")
+                        .window_size((320.0, 420.0))
+                        .with_min_size((320.0, 320.0))
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
+                if cmd.get(SHOW_TUTORIAL).is_some() {
+                    ctx.set_handled();
+                    data.tutorial = TutorialState::new();
+                    let new_window = WindowDesc::new(tutorial_ui_builder())
+                        .title("Tập gõ")
+                        .window_size((320.0, 360.0))
+                        .with_min_size((320.0, 320.0))
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
+                if cmd.get(SHOW_MIGRATION).is_some() {
+                    ctx.set_handled();
+                    let new_window = WindowDesc::new(migration_ui_builder(detect_installed_imes()))
+                        .title("Chuyển từ bộ gõ khác")
+                        .window_size((320.0, 320.0))
+                        .with_min_size((320.0, 320.0))
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
+                if let Some(notes) = cmd.get(SHOW_WHATS_NEW) {
+                    ctx.set_handled();
+                    let new_window = WindowDesc::new(whats_new_ui_builder(notes))
+                        .title("Có gì mới")
+                        .window_size((360.0, 400.0))
+                        .with_min_size((320.0, 320.0))
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
                 if let Some(source) = cmd.get(DELETE_MACRO) {
                     unsafe { INPUT_STATE.delete_macro(source) };
+                    data.macros.remove_entry(source);
+                }
+                if let Some((from, is_template)) = cmd.get(SET_TEMPLATE_MACRO) {
+                    unsafe { INPUT_STATE.set_template_macro(from, *is_template) };
+                    data.macros.set_template_entry(from, *is_template);
+                }
+                if let Some((from, to)) = cmd.get(SET_MACRO) {
+                    unsafe { INPUT_STATE.add_macro(from.clone(), to.clone()) };
+                    data.macros.insert_entry(from.clone(), to.clone());
+                }
+                if let Some(app_path) = cmd.get(SHOW_LATENCY_WARNING) {
+                    ctx.set_handled();
+                    let new_window = WindowDesc::new(latency_warning_ui_builder(app_path))
+                        .title("Gõ chậm")
+                        .window_size((320.0, 160.0))
+                        .resizable(false)
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
+                if let Some(message) = cmd.get(SHOW_INJECTION_ERROR) {
+                    ctx.set_handled();
+                    let new_window = WindowDesc::new(injection_error_ui_builder(message))
+                        .title("Đã chặn")
+                        .window_size((320.0, 140.0))
+                        .resizable(false)
+                        .set_always_on_top(true)
+                        .set_position(center_window_position());
+                    ctx.new_window(new_window);
+                }
+                if let Some(startup_time) = cmd.get(SHOW_TRAY) {
+                    data.set_systray(Tray::new(SystemTray::new()));
+                    debug!("Tray ready after {:?}", startup_time.elapsed());
+                }
+                if let Some(app_path) = cmd.get(ENABLE_FAST_MODE) {
+                    unsafe { INPUT_STATE.enable_fast_mode(app_path) };
+                    data.update();
+                }
+                if let Some(app_path) = cmd.get(DISABLE_FAST_MODE) {
+                    unsafe { INPUT_STATE.disable_fast_mode(app_path) };
+                    data.update();
+                }
+                if let Some((is_tracking, buffer_length)) = cmd.get(UPDATE_DEBUG_INDICATOR) {
+                    data.update_debug_indicator(*is_tracking, *buffer_length);
+                }
+                if cmd.get(SAVE_ACTION_HOTKEYS).is_some() {
+                    data.action_hotkeys.save();
+                }
+                if let Some(hotkey_error) = cmd.get(SET_HOTKEY_ERROR) {
+                    data.hotkey.error = hotkey_error.clone();
+                }
+                if let Some((app_path, policy)) = cmd.get(SET_APP_POLICY) {
+                    unsafe { INPUT_STATE.set_app_policy(app_path, *policy) };
+                    data.update();
+                }
+                if let Some((app_path, is_passthrough)) = cmd.get(SET_ALT_PASSTHROUGH_APP) {
+                    unsafe { INPUT_STATE.set_alt_passthrough_app(app_path, *is_passthrough) };
+                    data.update();
+                }
+                if let Some(app_path) = cmd.get(REMOVE_APP) {
+                    CONFIG_MANAGER.lock().unwrap().remove_app(app_path);
                     data.update();
                 }
                 if cmd.get(ADD_MACRO).is_some()
-                    && !data.new_macro_from.is_empty()
-                    && !data.new_macro_to.is_empty()
+                    && !data.macros.new_from.is_empty()
+                    && !data.macros.new_to.is_empty()
                 {
-                    unsafe {
-                        INPUT_STATE
-                            .add_macro(data.new_macro_from.clone(), data.new_macro_to.clone())
-                    };
-                    data.new_macro_from = String::new();
-                    data.new_macro_to = String::new();
-                    data.update();
+                    let from = std::mem::take(&mut data.macros.new_from);
+                    let to = std::mem::take(&mut data.macros.new_to);
+                    unsafe { INPUT_STATE.add_macro(from.clone(), to.clone()) };
+                    data.macros.insert_entry(from, to);
                 }
             }
             Event::WindowCloseRequested => {
                 ctx.set_handled();
-                ctx.window().hide();
+                // Without a tray there would be no way to bring a hidden window back
+                if data.systray.is_some() {
+                    ctx.window().hide();
+                } else {
+                    ctx.window().set_window_state(WindowState::Minimized);
+                }
             }
             _ => {}
         }
@@ -304,46 +1125,99 @@ impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for UIController {
         env: &Env,
     ) {
         unsafe {
-            if old_data.typing_method != data.typing_method {
-                INPUT_STATE.set_method(data.typing_method);
-            }
+            if !old_data.general.same(&data.general) {
+                let (old_general, general) = (&old_data.general, &data.general);
+                if old_general.typing_method != general.typing_method {
+                    INPUT_STATE.set_method(general.typing_method);
+                }
+
+                if old_general.globe_key_behavior != general.globe_key_behavior {
+                    INPUT_STATE.set_globe_key_behavior(general.globe_key_behavior);
+                }
+
+                if old_general.launch_on_login != general.launch_on_login {
+                    if let Err(err) = update_launch_on_login(general.launch_on_login) {
+                        error!("{}", err);
+                    }
+                }
+
+                if old_general.is_auto_toggle_enabled != general.is_auto_toggle_enabled {
+                    INPUT_STATE.toggle_auto_toggle();
+                }
 
-            if old_data.launch_on_login != data.launch_on_login {
-                if let Err(err) = update_launch_on_login(data.launch_on_login) {
-                    error!("{}", err);
+                if old_general.unknown_app_mode != general.unknown_app_mode {
+                    INPUT_STATE.set_unknown_app_mode(general.unknown_app_mode);
+                }
+
+                if old_general.is_auto_learn_enabled != general.is_auto_learn_enabled {
+                    INPUT_STATE.toggle_auto_learn();
+                }
+
+                if old_general.is_low_power_enabled != general.is_low_power_enabled {
+                    INPUT_STATE.toggle_low_power_enabled();
                 }
             }
 
             // Update hotkey
-            {
+            if !old_data.hotkey.same(&data.hotkey) {
+                let hotkey = &data.hotkey;
                 let mut new_mod = KeyModifier::new();
                 new_mod.apply(
-                    data.super_key,
-                    data.ctrl_key,
-                    data.alt_key,
-                    data.shift_key,
-                    data.capslock_key,
+                    hotkey.super_key,
+                    hotkey.ctrl_key,
+                    hotkey.alt_key,
+                    hotkey.shift_key,
+                    hotkey.capslock_key,
                 );
-                let key_code = letter_key_to_char(&data.letter_key);
-                if !INPUT_STATE.get_hotkey().is_match(new_mod, key_code) {
-                    INPUT_STATE.set_hotkey(&format!(
+                let key_code = letter_key_to_char(&hotkey.letter_key);
+                // Invalid hotkeys are not saved, the error is shown next to the hotkey
+                let hotkey_error = if INPUT_STATE.get_hotkey().is_match(new_mod, key_code) {
+                    String::new()
+                } else {
+                    let key_sequence = format!(
                         "{}{}",
                         new_mod,
-                        match key_code {
-                            Some(' ') => String::from("space"),
-                            Some(c) => c.to_string(),
-                            _ => String::new(),
-                        }
-                    ));
+                        key_code.map(format_key_name).unwrap_or_default()
+                    );
+                    match INPUT_STATE.set_hotkey(&key_sequence) {
+                        Ok(_) => String::new(),
+                        Err(err) => err.to_string(),
+                    }
+                };
+                if hotkey_error != hotkey.error {
+                    ctx.submit_command(SET_HOTKEY_ERROR.with(hotkey_error));
+                }
+            }
+
+            if !old_data.macros.same(&data.macros) {
+                let (old_macros, macros) = (&old_data.macros, &data.macros);
+                if old_macros.conflict_behavior != macros.conflict_behavior {
+                    INPUT_STATE.set_macro_conflict_behavior(macros.conflict_behavior);
+                }
+
+                if old_macros.locale != macros.locale {
+                    INPUT_STATE.set_locale(macros.locale);
+                }
+
+                if old_macros.is_enabled != macros.is_enabled {
+                    INPUT_STATE.toggle_macro_enabled();
                 }
             }
 
-            if old_data.is_macro_enabled != data.is_macro_enabled {
-                INPUT_STATE.toggle_macro_enabled();
+            for ((feature, old_value), (_, value)) in old_data
+                .features
+                .entries()
+                .into_iter()
+                .zip(data.features.entries())
+            {
+                if old_value != value {
+                    INPUT_STATE.set_feature_enabled(feature, value);
+                }
             }
 
-            if old_data.is_auto_toggle_enabled != data.is_auto_toggle_enabled {
-                INPUT_STATE.toggle_auto_toggle();
+            if old_data.is_debug_indicator_enabled != data.is_debug_indicator_enabled {
+                INPUT_STATE.toggle_debug_indicator();
+                data.update_system_tray();
             }
         }
         child.update(ctx, old_data, data, env);
@@ -362,11 +1236,13 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                     .with_child(
                         Flex::row()
                             .with_child(Label::new("Chế độ gõ tiếng Việt"))
-                            .with_child(Switch::new().lens(UIDataAdapter::is_enabled).on_click(
-                                |_, data, _| {
-                                    data.toggle_vietnamese();
-                                },
-                            ))
+                            .with_child(
+                                Switch::new()
+                                    .lens(UIDataAdapter::general.then(GeneralSettings::is_enabled))
+                                    .on_click(|_, data, _| {
+                                        data.toggle_vietnamese();
+                                    }),
+                            )
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
                             .must_fill_main_axis(true)
@@ -381,7 +1257,31 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                                     ("Telex", TypingMethod::Telex),
                                     ("VNI", TypingMethod::VNI),
                                 ])
-                                .lens(UIDataAdapter::typing_method),
+                                .lens(UIDataAdapter::general.then(GeneralSettings::typing_method))
+                                .disabled_if(|data, _| data.is_locked(TYPING_METHOD_CONFIG_KEY)),
+                            )
+                            .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                            .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+                            .must_fill_main_axis(true)
+                            .expand_width()
+                            .padding(8.0),
+                    )
+                    .with_child(
+                        Flex::row()
+                            .with_child(Label::new("Phím Globe"))
+                            .with_child(
+                                RadioGroup::column(vec![
+                                    ("Bật tắt tiếng Việt", GlobeKeyBehavior::Toggle),
+                                    ("Giữ để mở cài đặt", GlobeKeyBehavior::ToggleOrSettings),
+                                    ("Bỏ qua", GlobeKeyBehavior::PassThrough),
+                                ])
+                                .lens(
+                                    UIDataAdapter::general
+                                        .then(GeneralSettings::globe_key_behavior),
+                                )
+                                .disabled_if(|data, _| {
+                                    data.is_locked(GLOBE_KEY_BEHAVIOR_CONFIG_KEY)
+                                }),
                             )
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
@@ -392,7 +1292,9 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                     .with_child(
                         Flex::row()
                             .with_child(Label::new("Khởi động cùng OS"))
-                            .with_child(Checkbox::new("").lens(UIDataAdapter::launch_on_login))
+                            .with_child(Checkbox::new("").lens(
+                                UIDataAdapter::general.then(GeneralSettings::launch_on_login),
+                            ))
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
                             .must_fill_main_axis(true)
@@ -403,7 +1305,33 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                         Flex::row()
                             .with_child(Label::new("Bật tắt theo ứng dụng"))
                             .with_child(
-                                Checkbox::new("").lens(UIDataAdapter::is_auto_toggle_enabled),
+                                Checkbox::new("")
+                                    .lens(
+                                        UIDataAdapter::general
+                                            .then(GeneralSettings::is_auto_toggle_enabled),
+                                    )
+                                    .disabled_if(|data, _| {
+                                        data.is_locked(AUTOS_TOGGLE_ENABLED_CONFIG_KEY)
+                                    }),
+                            )
+                            .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                            .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+                            .must_fill_main_axis(true)
+                            .expand_width()
+                            .padding(8.0),
+                    )
+                    .with_child(
+                        Flex::row()
+                            .with_child(Label::new("Tiết kiệm pin"))
+                            .with_child(
+                                Checkbox::new("")
+                                    .lens(
+                                        UIDataAdapter::general
+                                            .then(GeneralSettings::is_low_power_enabled),
+                                    )
+                                    .disabled_if(|data, _| {
+                                        data.is_locked(LOW_POWER_ENABLED_CONFIG_KEY)
+                                    }),
                             )
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
@@ -414,7 +1342,13 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                     .with_child(
                         Flex::row()
                             .with_child(Label::new("Gõ tắt"))
-                            .with_child(Checkbox::new("").lens(UIDataAdapter::is_macro_enabled))
+                            .with_child(
+                                Checkbox::new("")
+                                    .lens(UIDataAdapter::macros.then(MacroSettings::is_enabled))
+                                    .disabled_if(|data, _| {
+                                        data.is_locked(MACRO_ENABLED_CONFIG_KEY)
+                                    }),
+                            )
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
                             .must_fill_main_axis(true)
@@ -423,6 +1357,28 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                     )
                     .with_child(
                         Flex::row()
+                            .with_child(Button::new("Ứng dụng").on_click(|ctx, _, _| {
+                                let new_win_position = ctx.window().get_position() - (50.0, 50.0); // offset a bit
+                                let new_window = WindowDesc::new(app_list_ui_builder())
+                                    .title("Ứng dụng")
+                                    .window_size((320.0, 560.0))
+                                    .with_min_size((320.0, 560.0))
+                                    .set_always_on_top(true)
+                                    .set_position(new_win_position);
+                                ctx.new_window(new_window);
+                            }))
+                            .with_spacer(8.0)
+                            .with_child(Button::new("Nâng cao").on_click(|ctx, _, _| {
+                                let new_win_position = ctx.window().get_position() - (50.0, 50.0); // offset a bit
+                                let new_window = WindowDesc::new(advanced_ui_builder())
+                                    .title("Nâng cao")
+                                    .window_size((320.0, 800.0))
+                                    .with_min_size((320.0, 800.0))
+                                    .set_always_on_top(true)
+                                    .set_position(new_win_position);
+                                ctx.new_window(new_window);
+                            }))
+                            .with_spacer(8.0)
                             .with_child(Button::new("Bảng gõ tắt").on_click(|ctx, _, _| {
                                 let new_win_position = ctx.window().get_position() - (50.0, 50.0); // offset a bit
                                 let new_window = WindowDesc::new(macro_editor_ui_builder())
@@ -442,35 +1398,39 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                     .with_child(
                         Flex::row()
                             .with_child(Label::new("Bật tắt gõ tiếng Việt"))
-                            .with_child(
-                                Label::dynamic(|data: &UIDataAdapter, _| {
-                                    data.hotkey_display.to_owned()
-                                })
-                                .border(PLACEHOLDER_COLOR, 1.0)
-                                .rounded(4.0),
-                            )
+                            .with_child(Either::new(
+                                |data: &HotkeySettings, _| data.error.is_empty(),
+                                Label::dynamic(|data: &HotkeySettings, _| data.display.to_owned())
+                                    .border(PLACEHOLDER_COLOR, 1.0)
+                                    .rounded(4.0),
+                                Label::dynamic(|data: &HotkeySettings, _| data.error.to_owned())
+                                    .with_text_color(Color::RED),
+                            ))
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
                             .must_fill_main_axis(true)
                             .expand_width()
-                            .padding(8.0),
+                            .padding(8.0)
+                            .lens(UIDataAdapter::hotkey),
                     )
                     .with_child(
                         Flex::row()
-                            .with_child(Checkbox::new(SYMBOL_SUPER).lens(UIDataAdapter::super_key))
-                            .with_child(Checkbox::new(SYMBOL_CTRL).lens(UIDataAdapter::ctrl_key))
-                            .with_child(Checkbox::new(SYMBOL_ALT).lens(UIDataAdapter::alt_key))
-                            .with_child(Checkbox::new(SYMBOL_SHIFT).lens(UIDataAdapter::shift_key))
+                            .with_child(Checkbox::new(SYMBOL_SUPER).lens(HotkeySettings::super_key))
+                            .with_child(Checkbox::new(SYMBOL_CTRL).lens(HotkeySettings::ctrl_key))
+                            .with_child(Checkbox::new(SYMBOL_ALT).lens(HotkeySettings::alt_key))
+                            .with_child(Checkbox::new(SYMBOL_SHIFT).lens(HotkeySettings::shift_key))
                             .with_child(
                                 TextBox::new()
-                                    .lens(UIDataAdapter::letter_key)
+                                    .lens(HotkeySettings::letter_key)
                                     .controller(LetterKeyController),
                             )
                             .cross_axis_alignment(druid::widget::CrossAxisAlignment::End)
                             .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
                             .must_fill_main_axis(true)
                             .expand_width()
-                            .padding(8.0),
+                            .padding(8.0)
+                            .lens(UIDataAdapter::hotkey)
+                            .disabled_if(|data, _| data.is_locked(HOTKEY_CONFIG_KEY)),
                     ),
             )
             .border(BORDER_DARK, 1.0)
@@ -496,10 +1456,11 @@ pub fn main_ui_builder() -> impl Widget<UIDataAdapter> {
                 .expand_width(),
         )
         .padding(8.0)
-        .controller(UIController)
+        .controller(UIController::new())
 }
 
-pub fn permission_request_ui_builder() -> impl Widget<()> {
+// The data is whether the last check found the permission still missing
+pub fn permission_request_ui_builder() -> impl Widget<bool> {
     let image_data = ImageBuf::from_data(include_bytes!("../assets/accessibility.png")).unwrap();
     Flex::column()
         .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
@@ -515,14 +1476,38 @@ pub fn permission_request_ui_builder() -> impl Widget<()> {
                 .padding(6.0)
         )
         .with_child(
-            Label::new("Bạn vui lòng thoát khỏi ứng dụng và mở lại sau khi đã cấp quyền.")
+            Label::new("Sau khi cấp quyền, bấm Kiểm tra lại để bắt đầu sử dụng.")
                 .with_line_break_mode(LineBreaking::WordWrap)
                 .padding(6.0)
         )
+        .with_child(Either::new(
+            |is_missing: &bool, _| *is_missing,
+            Label::new("GõKey vẫn chưa có quyền Accessibility.")
+                .with_text_color(Color::RED)
+                .padding(6.0),
+            SizedBox::empty(),
+        ))
         .with_child(
             Flex::row()
                 .cross_axis_alignment(druid::widget::CrossAxisAlignment::End)
                 .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .with_child(
+                    Button::new("Mở System Settings → Accessibility")
+                        .fix_height(28.0)
+                        .on_click(|_, _, _| open_accessibility_settings())
+                        .padding(6.0)
+                )
+                .with_child(
+                    Button::new("Kiểm tra lại")
+                        .fix_height(28.0)
+                        .on_click(|_, is_missing: &mut bool, _| {
+                            *is_missing = !is_accessibility_trusted();
+                            if !*is_missing {
+                                crate::relaunch_app();
+                            }
+                        })
+                        .padding(6.0)
+                )
                 .with_child(
                     Button::new("Thoát")
                         .fix_width(100.0)
@@ -549,11 +1534,27 @@ pub fn macro_editor_ui_builder() -> impl Widget<UIDataAdapter> {
                 .expand_width(),
         )
         .with_spacer(10.0)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Định dạng ngày, số"))
+                .with_child(
+                    RadioGroup::row(vec![
+                        ("Việt Nam", Locale::Vietnamese),
+                        ("Mỹ", Locale::English),
+                    ])
+                    .lens(UIDataAdapter::macros.then(MacroSettings::locale))
+                    .disabled_if(|data, _| data.is_locked(LOCALE_CONFIG_KEY)),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+                .must_fill_main_axis(true)
+                .expand_width(),
+        )
+        .with_spacer(10.0)
         .with_flex_child(
             {
                 let mut scroll = Scroll::new(
                     List::new(macro_row_item)
-                        .lens(UIDataAdapter::macro_table)
+                        .lens(UIDataAdapter::macros.then(MacroSettings::table))
                         .expand_width(),
                 );
                 scroll.set_enabled_scrollbars(druid::scroll_component::ScrollbarsEnabled::Vertical);
@@ -571,7 +1572,7 @@ pub fn macro_editor_ui_builder() -> impl Widget<UIDataAdapter> {
                         .with_placeholder("Gõ tắt mới")
                         .with_text_alignment(druid::text::TextAlignment::Start)
                         .expand_width()
-                        .lens(UIDataAdapter::new_macro_from),
+                        .lens(UIDataAdapter::macros.then(MacroSettings::new_from)),
                     2.0,
                 )
                 .with_flex_child(
@@ -579,7 +1580,7 @@ pub fn macro_editor_ui_builder() -> impl Widget<UIDataAdapter> {
                         .with_placeholder("thay thế")
                         .with_text_alignment(druid::text::TextAlignment::Start)
                         .expand_width()
-                        .lens(UIDataAdapter::new_macro_to),
+                        .lens(UIDataAdapter::macros.then(MacroSettings::new_to)),
                     2.0,
                 )
                 .with_flex_child(
@@ -594,19 +1595,115 @@ pub fn macro_editor_ui_builder() -> impl Widget<UIDataAdapter> {
         )
         .with_child(
             Flex::row()
+                .with_child(
+                    Button::new("Xuất sang macOS...")
+                        .on_click(|ctx, _, _| {
+                            ctx.submit_command(
+                                SHOW_SAVE_PANEL
+                                    .with(FileDialogOptions::new().default_name(EXPORT_FILE_NAME)),
+                            )
+                        })
+                        .fix_height(28.0),
+                )
+                .with_flex_spacer(1.0)
                 .with_child(
                     Button::new("Đóng")
                         .on_click(|ctx, _, _| ctx.window().close())
                         .fix_width(100.0)
                         .fix_height(28.0),
                 )
-                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
                 .expand_width()
                 .padding(6.0),
         )
         .must_fill_main_axis(true)
         .expand_width()
         .padding(8.0)
+        .controller(MacroExportController)
+}
+
+// The save panel answers to the window that showed it
+struct MacroExportController;
+impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for MacroExportController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut UIDataAdapter,
+        env: &Env,
+    ) {
+        if let Event::Command(cmd) = event {
+            if let Some(file_info) = cmd.get(SAVE_FILE_AS) {
+                ctx.set_handled();
+                let macros = unsafe { INPUT_STATE.get_macro_table() };
+                let template_macros = unsafe { INPUT_STATE.get_template_macros() };
+                match fs::write(
+                    file_info.path(),
+                    build_text_replacements_plist(macros, template_macros),
+                ) {
+                    Ok(()) => {
+                        let conflicts = find_conflicts(macros, &get_text_replacements());
+                        let new_win_position = ctx.window().get_position() - (50.0, 50.0); // offset a bit
+                        let new_window =
+                            WindowDesc::new(text_replacement_export_ui_builder(conflicts))
+                                .title("Xuất sang macOS")
+                                .window_size((320.0, 320.0))
+                                .with_min_size((320.0, 240.0))
+                                .set_always_on_top(true)
+                                .set_position(new_win_position);
+                        ctx.new_window(new_window);
+                    }
+                    Err(err) => error!("Failed to export macros: {}", err),
+                }
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+fn text_replacement_export_ui_builder(
+    conflicts: Vec<TextReplacementConflict>,
+) -> impl Widget<UIDataAdapter> {
+    let mut rows = Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start);
+    if !conflicts.is_empty() {
+        rows.add_child(Label::new("Các gõ tắt sẽ thay thế cụm từ đang có:").padding(4.0));
+    }
+    for conflict in conflicts {
+        rows.add_child(
+            Label::new(format!(
+                "{}\n{} → {}",
+                conflict.shortcut, conflict.existing_phrase, conflict.macro_target
+            ))
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .align_left()
+            .expand_width()
+            .border(Color::GRAY, 0.5)
+            .padding(4.0),
+        );
+    }
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(
+            Label::new(
+                "Kéo tệp vừa lưu vào Cài đặt hệ thống > Bàn phím > Thay thế văn bản. Gõ tắt có ngày giờ hoặc phép tính không được xuất.",
+            )
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .padding(4.0),
+        )
+        .with_flex_child(Scroll::new(rows).vertical().expand(), 1.0)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .padding(8.0)
 }
 
 fn macro_row_item() -> impl Widget<MacroEntry> {
@@ -623,6 +1720,23 @@ fn macro_row_item() -> impl Widget<MacroEntry> {
                 .align_left(),
             2.0,
         )
+        .with_child(
+            // Placeholders like {date} and {=1+1} are only expanded in templates
+            Button::dynamic(|e: &MacroEntry, _| {
+                match e.is_template {
+                    true => "Mẫu ✓",
+                    false => "Mẫu",
+                }
+                .to_string()
+            })
+            .on_click(|ctx, data: &mut MacroEntry, _| {
+                ctx.submit_command(
+                    SET_TEMPLATE_MACRO
+                        .with((data.from.clone(), !data.is_template))
+                        .to(Target::Global),
+                )
+            }),
+        )
         .with_flex_child(
             Button::new("×").on_click(|ctx, data: &mut MacroEntry, _| {
                 ctx.submit_command(DELETE_MACRO.with(data.from.clone()).to(Target::Global))
@@ -635,11 +1749,1083 @@ fn macro_row_item() -> impl Widget<MacroEntry> {
         .border(Color::GRAY, 0.5)
 }
 
-pub fn center_window_position() -> (f64, f64) {
-    let screen_rect = Screen::get_display_rect();
+pub fn app_list_ui_builder() -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Ứng dụng"))
+                .main_axis_alignment(druid::widget::MainAxisAlignment::Center)
+                .expand_width(),
+        )
+        .with_spacer(10.0)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Ứng dụng khác"))
+                .with_child(
+                    RadioGroup::column(vec![
+                        ("Giữ nguyên", UnknownAppMode::KeepCurrent),
+                        ("Tiếng Việt", UnknownAppMode::Vietnamese),
+                        ("Tiếng Anh", UnknownAppMode::English),
+                    ])
+                    .lens(UIDataAdapter::general.then(GeneralSettings::unknown_app_mode))
+                    .disabled_if(|data, _| {
+                        !data.general.is_auto_toggle_enabled
+                            || data.is_locked(UNKNOWN_APP_MODE_CONFIG_KEY)
+                    }),
+                )
+                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+                .must_fill_main_axis(true)
+                .expand_width(),
+        )
+        .with_spacer(6.0)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Ghi nhớ khi bật tắt"))
+                .with_child(
+                    Checkbox::new("")
+                        .lens(UIDataAdapter::general.then(GeneralSettings::is_auto_learn_enabled))
+                        .disabled_if(|data, _| data.is_locked(AUTO_LEARN_APPS_CONFIG_KEY)),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+                .must_fill_main_axis(true)
+                .expand_width(),
+        )
+        .with_spacer(10.0)
+        .with_flex_child(
+            {
+                let mut scroll = Scroll::new(
+                    List::new(app_row_item)
+                        .lens(UIDataAdapter::app_list)
+                        .expand_width(),
+                );
+                scroll.set_enabled_scrollbars(druid::scroll_component::ScrollbarsEnabled::Vertical);
+                scroll.set_horizontal_scroll_enabled(false);
+                scroll
+            }
+            .expand(),
+            1.0,
+        )
+        .with_spacer(10.0)
+        .with_child(Label::new("Đang chạy"))
+        .with_child(
+            Label::new("Nhanh ✓: bỏ qua các bước kiểm tra văn bản đang chọn")
+                .with_text_size(10.0)
+                .with_text_color(PLACEHOLDER_COLOR),
+        )
+        .with_child(
+            Label::new("⌥ ✓: chữ gõ kèm phím Option không ngắt từ đang gõ")
+                .with_text_size(10.0)
+                .with_text_color(PLACEHOLDER_COLOR),
+        )
+        .with_spacer(6.0)
+        .with_flex_child(
+            {
+                let mut scroll = Scroll::new(
+                    List::new(running_app_row_item)
+                        .lens(UIDataAdapter::running_apps)
+                        .expand_width(),
+                );
+                scroll.set_enabled_scrollbars(druid::scroll_component::ScrollbarsEnabled::Vertical);
+                scroll.set_horizontal_scroll_enabled(false);
+                scroll
+            }
+            .expand(),
+            1.0,
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width()
+                .padding(6.0),
+        )
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(8.0)
+        .controller(AppListController)
+}
 
-    let x = (screen_rect.width() - WINDOW_WIDTH) / 2.0;
-    let y = (screen_rect.height() - WINDOW_HEIGHT) / 2.0;
+// Running apps are listed from when the window opens until it closes
+struct AppListController;
+impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for AppListController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut UIDataAdapter,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                data.is_app_list_open = true;
+                data.refresh_running_apps();
+            }
+            Event::WindowDisconnected => {
+                data.is_app_list_open = false;
+                data.running_apps = Arc::new(Vec::new());
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
 
-    (x, y)
+fn running_app_row_item() -> impl Widget<RunningAppEntry> {
+    Flex::row()
+        .with_child(ViewSwitcher::new(
+            |e: &RunningAppEntry, _| e.path.clone(),
+            |path, _, _| match get_app_icon(path) {
+                Some(icon) => Box::new(Image::new(icon).fix_size(20.0, 20.0)),
+                None => Box::new(SizedBox::empty().fix_size(20.0, 20.0)),
+            },
+        ))
+        .with_spacer(6.0)
+        .with_flex_child(
+            Label::dynamic(|e: &RunningAppEntry, _| e.name.clone())
+                .with_line_break_mode(LineBreaking::WordWrap)
+                .align_left(),
+            3.0,
+        )
+        .with_child(
+            Button::dynamic(|e: &RunningAppEntry, _| {
+                match e.is_fast_mode {
+                    true => "Nhanh ✓",
+                    false => "Nhanh",
+                }
+                .to_string()
+            })
+            .on_click(|ctx, data: &mut RunningAppEntry, _| {
+                let command = match data.is_fast_mode {
+                    true => DISABLE_FAST_MODE,
+                    false => ENABLE_FAST_MODE,
+                };
+                ctx.submit_command(command.with(data.path.clone()).to(Target::Global))
+            }),
+        )
+        .with_child(
+            // For the accents of other languages typed with Option dead keys
+            Button::dynamic(|e: &RunningAppEntry, _| {
+                match e.is_alt_passthrough {
+                    true => "⌥ ✓",
+                    false => "⌥",
+                }
+                .to_string()
+            })
+            .on_click(|ctx, data: &mut RunningAppEntry, _| {
+                ctx.submit_command(
+                    SET_ALT_PASSTHROUGH_APP
+                        .with((data.path.clone(), !data.is_alt_passthrough))
+                        .to(Target::Global),
+                )
+            }),
+        )
+        .with_flex_child(
+            Flex::column()
+                .with_child(Label::dynamic(|e: &RunningAppEntry, _| e.mode.clone()))
+                .with_child(
+                    Label::dynamic(|e: &RunningAppEntry, _| e.reason.clone())
+                        .with_text_size(10.0)
+                        .with_text_color(PLACEHOLDER_COLOR),
+                )
+                .cross_axis_alignment(druid::widget::CrossAxisAlignment::End),
+            2.0,
+        )
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Center)
+        .expand_width()
+        .border(Color::GRAY, 0.5)
+}
+
+fn app_row_item() -> impl Widget<AppEntry> {
+    Flex::row()
+        .with_child(ViewSwitcher::new(
+            |e: &AppEntry, _| e.path.clone(),
+            |path, _, _| match get_app_icon(path) {
+                Some(icon) => Box::new(Image::new(icon).fix_size(20.0, 20.0)),
+                None => Box::new(SizedBox::empty().fix_size(20.0, 20.0)),
+            },
+        ))
+        .with_spacer(6.0)
+        .with_flex_child(
+            Label::dynamic(|e: &AppEntry, _| e.name.clone())
+                .with_line_break_mode(LineBreaking::WordWrap)
+                .align_left(),
+            3.0,
+        )
+        .with_flex_child(Label::dynamic(|e: &AppEntry, _| e.mode.clone()), 1.0)
+        .with_flex_child(
+            Button::new("×").on_click(|ctx, data: &mut AppEntry, _| {
+                ctx.submit_command(REMOVE_APP.with(data.path.clone()).to(Target::Global))
+            }),
+            1.0,
+        )
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Center)
+        .expand_width()
+        .border(Color::GRAY, 0.5)
+}
+
+pub fn latency_warning_ui_builder(app_path: &str) -> impl Widget<UIDataAdapter> {
+    let app_path = app_path.to_string();
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .with_child(
+            Label::new(format!(
+                "Gõ tiếng Việt trong {} đang bị chậm. Bật chế độ nhanh để bỏ qua các bước kiểm tra văn bản đang chọn cho ứng dụng này?",
+                get_app_display_name(&app_path)
+            ))
+            .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Để sau")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_height(28.0),
+                )
+                .with_spacer(8.0)
+                .with_child(
+                    Button::new("Bật chế độ nhanh")
+                        .on_click(move |ctx, _, _| {
+                            ctx.submit_command(
+                                ENABLE_FAST_MODE.with(app_path.clone()).to(Target::Global),
+                            );
+                            ctx.window().close();
+                        })
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(8.0)
+}
+
+pub fn injection_error_ui_builder(message: &str) -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .with_child(Label::new(message).with_line_break_mode(LineBreaking::WordWrap))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(8.0)
+}
+
+pub fn cheat_sheet_ui_builder() -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .with_child(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &UIDataAdapter, _| {
+                    match data.general.typing_method {
+                        TypingMethod::Telex => "Kiểu gõ Telex".to_string(),
+                        TypingMethod::VNI => "Kiểu gõ VNI".to_string(),
+                    }
+                }))
+                .main_axis_alignment(druid::widget::MainAxisAlignment::Center)
+                .expand_width(),
+        )
+        .with_spacer(10.0)
+        .with_flex_child(
+            {
+                // Rebuilt from the engine whenever the typing method changes
+                let mut scroll = Scroll::new(ViewSwitcher::new(
+                    |data: &UIDataAdapter, _| data.general.typing_method,
+                    |method, _, _| {
+                        let mut rows = Flex::column();
+                        for rule in describe_method(*method) {
+                            rows.add_child(cheat_sheet_row_item(rule));
+                        }
+                        Box::new(rows.expand_width())
+                    },
+                ));
+                scroll.set_enabled_scrollbars(druid::scroll_component::ScrollbarsEnabled::Vertical);
+                scroll.set_horizontal_scroll_enabled(false);
+                scroll
+            }
+            .expand(),
+            1.0,
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Tập gõ")
+                        .on_click(|ctx, _, _| ctx.submit_command(SHOW_TUTORIAL.to(Target::Global)))
+                        .fix_height(28.0),
+                )
+                .with_spacer(8.0)
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width()
+                .padding(6.0),
+        )
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(8.0)
+}
+
+// The words are typed with gõkey itself, then checked, and the rules for
+// the letters they need are shown after a wrong answer
+pub fn tutorial_ui_builder() -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::Start)
+        .with_child(Label::dynamic(|data: &UIDataAdapter, _| {
+            format!(
+                "Bài {}/{}: gõ thử từ \"{}\"",
+                data.tutorial.step + 1,
+                tutorial::EXERCISES.len(),
+                data.tutorial.get_word()
+            )
+        }))
+        .with_spacer(8.0)
+        .with_child(
+            TextBox::new()
+                .with_placeholder("Gõ vào đây")
+                .expand_width()
+                .lens(UIDataAdapter::tutorial.then(TutorialState::input)),
+        )
+        .with_spacer(8.0)
+        .with_child(
+            Label::dynamic(|data: &UIDataAdapter, _| {
+                if data.tutorial.is_correct() {
+                    "Chính xác! ✓".to_string()
+                } else if data.tutorial.is_checked {
+                    "Chưa đúng, hãy xoá đi và gõ lại theo gợi ý:".to_string()
+                } else {
+                    String::new()
+                }
+            })
+            .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .with_spacer(8.0)
+        .with_flex_child(
+            ViewSwitcher::new(
+                |data: &UIDataAdapter, _| {
+                    let is_hint_shown = data.tutorial.is_checked && !data.tutorial.is_correct();
+                    (
+                        data.tutorial.step,
+                        is_hint_shown,
+                        data.general.typing_method,
+                    )
+                },
+                |(step, is_hint_shown, method), _, _| {
+                    let mut rows = Flex::column();
+                    if *is_hint_shown {
+                        let rules = describe_method(*method);
+                        for rule in tutorial::get_hints(tutorial::EXERCISES[*step], &rules) {
+                            rows.add_child(cheat_sheet_row_item(rule));
+                        }
+                    }
+                    Box::new(rows.expand_width())
+                },
+            ),
+            1.0,
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Kiểm tra")
+                        .on_click(|_, data: &mut UIDataAdapter, _| data.tutorial.is_checked = true)
+                        .fix_height(28.0),
+                )
+                .with_spacer(8.0)
+                .with_child(
+                    Button::new("Bài tiếp")
+                        .on_click(|ctx, data: &mut UIDataAdapter, _| {
+                            if data.tutorial.is_last_step() {
+                                ctx.window().close();
+                            } else {
+                                data.tutorial.next_step();
+                            }
+                        })
+                        .fix_height(28.0)
+                        .disabled_if(|data: &UIDataAdapter, _| !data.tutorial.is_correct()),
+                )
+                .with_spacer(8.0)
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(8.0)
+}
+
+fn cheat_sheet_row_item(rule: RuleDescription) -> impl Widget<UIDataAdapter> {
+    Flex::row()
+        .with_flex_child(Label::new(rule.keys).align_left(), 1.0)
+        .with_flex_child(
+            Label::new(rule.description)
+                .with_line_break_mode(LineBreaking::WordWrap)
+                .align_left(),
+            3.0,
+        )
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Baseline)
+        .expand_width()
+        .border(Color::GRAY, 0.5)
+}
+
+// The engine features are locked all together by an administrator
+fn feature_checkbox(
+    lens: impl Lens<FeatureSettings, bool> + 'static,
+) -> impl Widget<UIDataAdapter> {
+    Checkbox::new("")
+        .lens(UIDataAdapter::features.then(lens))
+        .disabled_if(|data, _| data.is_locked(ENGINE_FEATURES_CONFIG_KEY))
+}
+
+// Toggles for the parts of the engine, to find out which one misbehaves
+pub fn advanced_ui_builder() -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::Start)
+        .with_child(advanced_row_item(
+            "Khôi phục từ không hợp lệ",
+            feature_checkbox(FeatureSettings::is_auto_restore_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Xoá thêm khi có văn bản đang chọn",
+            feature_checkbox(FeatureSettings::is_selection_compensation_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Sửa lỗi thanh địa chỉ Firefox",
+            feature_checkbox(FeatureSettings::is_firefox_workaround_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Khôi phục từ tiếng Anh",
+            feature_checkbox(FeatureSettings::is_english_dictionary_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Từ giữ nguyên (mỗi dòng một từ)",
+            Button::new("Mở").on_click(|_, _, _| open_user_dictionary()),
+        ))
+        .with_child(advanced_row_item(
+            "Sửa lỗi chính tả vùng miền",
+            feature_checkbox(FeatureSettings::is_spelling_normalization_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Chặn từ sai (cho người học)",
+            feature_checkbox(FeatureSettings::is_strict_vietnamese_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Hiện trạng thái bộ gõ (gỡ lỗi)",
+            Checkbox::new("")
+                .lens(UIDataAdapter::is_debug_indicator_enabled)
+                .disabled_if(|data, _| data.is_locked(DEBUG_INDICATOR_CONFIG_KEY)),
+        ))
+        .with_child(Either::new(
+            |data: &UIDataAdapter, _| data.settings_page_url.is_empty(),
+            SizedBox::empty(),
+            advanced_row_item(
+                "Trang cài đặt web",
+                Button::new("Mở")
+                    .on_click(|_, data: &mut UIDataAdapter, _| open_url(&data.settings_page_url)),
+            ),
+        ))
+        .with_child(advanced_row_item(
+            "Tự viết hoa đầu câu",
+            feature_checkbox(FeatureSettings::is_auto_capitalize_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Bỏ qua cạnh chữ Hán, Hàn, Ả Rập",
+            feature_checkbox(FeatureSettings::is_foreign_script_passthrough_enabled),
+        ))
+        .with_child(advanced_row_item(
+            "Gõ tắt",
+            Checkbox::new("")
+                .lens(UIDataAdapter::macros.then(MacroSettings::is_enabled))
+                .disabled_if(|data, _| data.is_locked(MACRO_ENABLED_CONFIG_KEY)),
+        ))
+        .with_child(advanced_row_item(
+            "Gõ tắt trùng với từ",
+            RadioGroup::column(vec![
+                ("Thay thế (Esc để giữ)", MacroConflictBehavior::Expand),
+                ("Giữ nguyên (Tab để thay)", MacroConflictBehavior::Literal),
+            ])
+            .lens(UIDataAdapter::macros.then(MacroSettings::conflict_behavior))
+            .disabled_if(|data, _| data.is_locked(MACRO_CONFLICT_BEHAVIOR_CONFIG_KEY)),
+        ))
+        .with_child(advanced_row_item(
+            "Đặt dấu",
+            RadioGroup::column(vec![("Kiểu mới (hoà)", false), ("Kiểu cũ (hòa)", true)])
+                .lens(UIDataAdapter::features.then(FeatureSettings::is_old_tone_style))
+                .disabled_if(|data, _| data.is_locked(ENGINE_FEATURES_CONFIG_KEY)),
+        ))
+        .with_child(action_hotkey_row_item(
+            "Phím gõ tắt",
+            ActionHotkeySettings::expand_macro,
+        ))
+        .with_child(action_hotkey_row_item(
+            "Phím chuyển từ ở con trỏ",
+            ActionHotkeySettings::transform_word,
+        ))
+        .with_child(action_hotkey_row_item(
+            "Phím chuyển vùng chọn",
+            ActionHotkeySettings::transform_selection,
+        ))
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    Label::dynamic(|data: &ActionHotkeySettings, _| data.error.to_owned())
+                        .with_text_color(Color::rgb(1.0, 0.0, 0.0))
+                        .with_line_break_mode(LineBreaking::WordWrap)
+                        .lens(UIDataAdapter::action_hotkeys),
+                    1.0,
+                )
+                .with_child(Button::new("Lưu phím tắt").on_click(|ctx, _, _| {
+                    ctx.submit_command(SAVE_ACTION_HOTKEYS.to(Target::Global))
+                }))
+                .must_fill_main_axis(true)
+                .expand_width()
+                .padding(4.0),
+        )
+        .with_flex_spacer(1.0)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Nhập từ bộ gõ khác")
+                        .on_click(|ctx, _, _| ctx.submit_command(SHOW_MIGRATION.to(Target::Global)))
+                        .fix_height(28.0),
+                )
+                .with_spacer(8.0)
+                .with_child(
+                    Button::new("Chẩn đoán")
+                        .on_click(|ctx, _, _| {
+                            let new_win_position = ctx.window().get_position() - (50.0, 50.0); // offset a bit
+                            let new_window = WindowDesc::new(diagnostics_ui_builder())
+                                .title("Chẩn đoán")
+                                .window_size((360.0, 320.0))
+                                .with_min_size((320.0, 240.0))
+                                .set_always_on_top(true)
+                                .set_position(new_win_position);
+                            ctx.new_window(new_window);
+                        })
+                        .fix_height(28.0),
+                )
+                .with_flex_spacer(1.0)
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .padding(8.0)
+}
+
+// Written as in the config file, like "ctrl+shift+v", empty to turn it off
+fn action_hotkey_row_item(
+    label: &str,
+    lens: impl Lens<ActionHotkeySettings, String> + 'static,
+) -> impl Widget<UIDataAdapter> {
+    advanced_row_item(
+        label,
+        TextBox::new()
+            .with_placeholder("ctrl+shift+v")
+            .fix_width(120.0)
+            .lens(UIDataAdapter::action_hotkeys.then(lens)),
+    )
+}
+
+fn advanced_row_item(
+    label: &str,
+    control: impl Widget<UIDataAdapter> + 'static,
+) -> impl Widget<UIDataAdapter> {
+    Flex::row()
+        .with_child(Label::new(label))
+        .with_child(control)
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .must_fill_main_axis(true)
+        .expand_width()
+        .padding(4.0)
+}
+
+// A snapshot of how the text selection check was answered in each app,
+// apps that mostly need the heuristic are likely to double characters
+pub fn diagnostics_ui_builder() -> impl Widget<UIDataAdapter> {
+    let mut rows = Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start);
+    let metrics = unsafe { INPUT_STATE.get_selection_metrics() };
+    if metrics.get_counts().is_empty() {
+        rows.add_child(Label::new("Chưa có dữ liệu").padding(4.0));
+    }
+    for (app, [text, range, heuristic]) in metrics.get_counts() {
+        rows.add_child(
+            Label::new(format!(
+                "{}\nVăn bản chọn: {}, vùng chọn: {}, đoán: {}",
+                get_app_display_name(app),
+                text,
+                range,
+                heuristic
+            ))
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .align_left()
+            .expand_width()
+            .border(Color::GRAY, 0.5)
+            .padding(4.0),
+        );
+    }
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(Label::new("Cách kiểm tra văn bản đang chọn:").padding(4.0))
+        .with_flex_child(Scroll::new(rows).vertical().expand(), 1.0)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .padding(8.0)
+}
+
+fn import_settings(ctx: &mut EventCtx, settings: &ImportedSettings) {
+    unsafe { INPUT_STATE.import_settings(settings) };
+    ctx.submit_command(UPDATE_UI.to(Target::Global));
+}
+
+fn describe_imported_settings(settings: &ImportedSettings) -> String {
+    let mut lines = Vec::new();
+    if let Some(method) = settings.method {
+        lines.push(format!("Kiểu gõ: {}", method));
+    }
+    if let Some(hotkey) = &settings.hotkey {
+        lines.push(format!("Phím tắt: {}", hotkey));
+    }
+    if !settings.macros.is_empty() {
+        lines.push(format!("Gõ tắt: {} mục", settings.macros.len()));
+    }
+    lines.join("\n")
+}
+
+// The open panel answers to the window that showed it
+struct MigrationController;
+impl<W: Widget<UIDataAdapter>> Controller<UIDataAdapter, W> for MigrationController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut UIDataAdapter,
+        env: &Env,
+    ) {
+        if let Event::Command(cmd) = event {
+            if let Some(file_info) = cmd.get(OPEN_FILE) {
+                ctx.set_handled();
+                match fs::read_to_string(file_info.path()) {
+                    Ok(content) => {
+                        let settings = ImportedSettings {
+                            macros: parse_macro_file(&content),
+                            ..Default::default()
+                        };
+                        import_settings(ctx, &settings);
+                    }
+                    Err(err) => error!("Failed to read macro file: {}", err),
+                }
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+// Settings found in other input methods, offered on the first launch
+pub fn migration_ui_builder(detected: Vec<DetectedIme>) -> impl Widget<UIDataAdapter> {
+    let mut rows = Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start);
+    if detected.is_empty() {
+        rows.add_child(Label::new("Không tìm thấy bộ gõ nào khác").padding(4.0));
+    }
+    for ime in detected {
+        rows.add_child(migration_row_item(ime));
+    }
+    Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(Label::new("Nhập cài đặt từ bộ gõ bạn đang dùng:").padding(4.0))
+        .with_child(rows)
+        .with_flex_spacer(1.0)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Nhập bảng gõ tắt từ tệp...")
+                        .on_click(|ctx, _, _| {
+                            ctx.submit_command(SHOW_OPEN_PANEL.with(FileDialogOptions::new()))
+                        })
+                        .fix_height(28.0),
+                )
+                .with_flex_spacer(1.0)
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .expand_width(),
+        )
+        .must_fill_main_axis(true)
+        .padding(8.0)
+        .controller(MigrationController)
+}
+
+fn migration_row_item(ime: DetectedIme) -> impl Widget<UIDataAdapter> {
+    let description = format!(
+        "{}\n{}",
+        ime.name,
+        describe_imported_settings(&ime.settings)
+    );
+    let settings = ime.settings;
+    Flex::row()
+        .with_flex_child(
+            Label::new(description)
+                .with_line_break_mode(LineBreaking::WordWrap)
+                .align_left(),
+            1.0,
+        )
+        .with_child(Button::new("Nhập").on_click(move |ctx, _, _| import_settings(ctx, &settings)))
+        .main_axis_alignment(druid::widget::MainAxisAlignment::SpaceBetween)
+        .expand_width()
+        .border(Color::GRAY, 0.5)
+        .padding(4.0)
+}
+
+// Only what the changelog uses: headings, bullets and **bold**
+fn markdown_to_rich_text(markdown: &str) -> RichText {
+    let mut builder = RichTextBuilder::new();
+    for line in markdown.lines() {
+        if let Some(heading) = line.strip_prefix("## ") {
+            builder
+                .push(&format!("Phiên bản {}\n", heading))
+                .weight(FontWeight::BOLD)
+                .size(15.0);
+            continue;
+        }
+        let line = match line.strip_prefix("- ") {
+            Some(item) => {
+                builder.push("• ");
+                item
+            }
+            None => line,
+        };
+        for (i, part) in line.replace('`', "").split("**").enumerate() {
+            if i % 2 == 1 {
+                builder.push(part).weight(FontWeight::BOLD);
+            } else {
+                builder.push(part);
+            }
+        }
+        builder.push("\n");
+    }
+    builder.build()
+}
+
+pub fn whats_new_ui_builder(notes: &str) -> impl Widget<UIDataAdapter> {
+    Flex::column()
+        .with_flex_child(
+            {
+                let mut scroll = Scroll::new(
+                    RawLabel::new()
+                        .with_line_break_mode(LineBreaking::WordWrap)
+                        .lens(Constant(markdown_to_rich_text(notes)))
+                        .expand_width(),
+                );
+                scroll.set_enabled_scrollbars(druid::scroll_component::ScrollbarsEnabled::Vertical);
+                scroll.set_horizontal_scroll_enabled(false);
+                scroll
+            }
+            .expand(),
+            1.0,
+        )
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Đóng")
+                        .on_click(|ctx, _, _| ctx.window().close())
+                        .fix_width(100.0)
+                        .fix_height(28.0),
+                )
+                .main_axis_alignment(druid::widget::MainAxisAlignment::End)
+                .expand_width()
+                .padding(6.0),
+        )
+        .must_fill_main_axis(true)
+        .padding(8.0)
+}
+
+pub fn center_window_position() -> (f64, f64) {
+    let screen_rect = Screen::get_display_rect();
+
+    let x = (screen_rect.width() - WINDOW_WIDTH) / 2.0;
+    let y = (screen_rect.height() - WINDOW_HEIGHT) / 2.0;
+
+    (x, y)
+}
+
+#[cfg(test)]
+fn tray_snapshot(
+    is_enabled: bool,
+    is_gox_mode_enabled: bool,
+    typing_method: TypingMethod,
+) -> [String; 4] {
+    let mock = Arc::new(crate::platform::MockTray::default());
+    let tray = Tray::from(mock.clone());
+    update_tray_status(&tray, is_enabled, is_gox_mode_enabled, typing_method, None);
+    [
+        mock.get_title(),
+        mock.get_menu_item_title(SystemTrayMenuItemKey::Enable)
+            .unwrap_or_default(),
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex)
+            .unwrap_or_default(),
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI)
+            .unwrap_or_default(),
+    ]
+}
+
+#[test]
+fn test_tray_status_snapshots() {
+    let cases = [
+        (
+            true,
+            false,
+            TypingMethod::Telex,
+            ["VN", "Tắt gõ tiếng Việt", "Telex ✓", "VNI"],
+        ),
+        (
+            true,
+            false,
+            TypingMethod::VNI,
+            ["VN", "Tắt gõ tiếng Việt", "Telex", "VNI ✓"],
+        ),
+        (
+            true,
+            true,
+            TypingMethod::Telex,
+            ["gõ", "Tắt gõ tiếng Việt", "Telex ✓", "VNI"],
+        ),
+        (
+            true,
+            true,
+            TypingMethod::VNI,
+            ["gõ", "Tắt gõ tiếng Việt", "Telex", "VNI ✓"],
+        ),
+        (
+            false,
+            false,
+            TypingMethod::Telex,
+            ["EN", "Bật gõ tiếng Việt", "Telex ✓", "VNI"],
+        ),
+        (
+            false,
+            false,
+            TypingMethod::VNI,
+            ["EN", "Bật gõ tiếng Việt", "Telex", "VNI ✓"],
+        ),
+        (
+            false,
+            true,
+            TypingMethod::Telex,
+            ["gox", "Bật gõ tiếng Việt", "Telex ✓", "VNI"],
+        ),
+        (
+            false,
+            true,
+            TypingMethod::VNI,
+            ["go4", "Bật gõ tiếng Việt", "Telex", "VNI ✓"],
+        ),
+    ];
+    for (is_enabled, is_gox_mode_enabled, typing_method, expected) in cases {
+        assert_eq!(
+            tray_snapshot(is_enabled, is_gox_mode_enabled, typing_method),
+            expected.map(String::from)
+        );
+    }
+}
+
+#[test]
+fn test_tray_status_transitions() {
+    let mock = Arc::new(crate::platform::MockTray::default());
+    let tray = Tray::from(mock.clone());
+    update_tray_status(&tray, true, false, TypingMethod::VNI, None);
+    update_tray_status(&tray, false, true, TypingMethod::Telex, None);
+    assert_eq!(mock.get_title(), "gox");
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI),
+        Some("VNI".to_string())
+    );
+    update_tray_status(&tray, true, true, TypingMethod::Telex, None);
+    assert_eq!(mock.get_title(), "gõ");
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::Enable),
+        Some("Tắt gõ tiếng Việt".to_string())
+    );
+}
+
+#[test]
+fn test_tray_status_delta_updates() {
+    let mock = Arc::new(crate::platform::MockTray::default());
+    let tray = Tray::from(mock.clone());
+    let update_count = || mock.update_count.load(std::sync::atomic::Ordering::SeqCst);
+    update_tray_status(&tray, true, false, TypingMethod::Telex, None);
+    assert_eq!(update_count(), 4);
+    update_tray_status(&tray, true, false, TypingMethod::Telex, None);
+    assert_eq!(update_count(), 4);
+    // Only the two typing method items change
+    update_tray_status(&tray, true, false, TypingMethod::VNI, None);
+    assert_eq!(update_count(), 6);
+    update_tray_status(&tray, true, false, TypingMethod::VNI, Some((true, 3)));
+    assert_eq!(update_count(), 7);
+    assert_eq!(mock.get_title(), "VN [3]");
+}
+
+#[test]
+fn test_tray_follows_ui_updates() {
+    let _lock = crate::replay::reset_engine_state();
+    let mock = Arc::new(crate::platform::MockTray::default());
+    let mut data = UIDataAdapter::new();
+    data.set_systray(Tray::from(mock.clone()));
+    assert_eq!(mock.get_title(), "VN");
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex),
+        Some("Telex ✓".to_string())
+    );
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::CurrentApp),
+        Some("Ứng dụng hiện tại".to_string())
+    );
+
+    unsafe {
+        INPUT_STATE.toggle_vietnamese();
+        INPUT_STATE.set_method(TypingMethod::VNI);
+    }
+    data.update();
+    assert_eq!(mock.get_title(), "EN");
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::Enable),
+        Some("Bật gõ tiếng Việt".to_string())
+    );
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodTelex),
+        Some("Telex".to_string())
+    );
+    assert_eq!(
+        mock.get_menu_item_title(SystemTrayMenuItemKey::TypingMethodVNI),
+        Some("VNI ✓".to_string())
+    );
+}
+
+#[test]
+fn test_describe_app_mode() {
+    use UnknownAppMode::*;
+    let describe = |policy, is_learned, has_workarounds, unknown_app_mode| {
+        describe_app_mode(policy, is_learned, has_workarounds, true, unknown_app_mode)
+    };
+    assert_eq!(
+        describe(AppPolicy::Vietnamese, false, false, KeepCurrent),
+        ("VN", "thủ công".to_string())
+    );
+    assert_eq!(
+        describe(AppPolicy::English, true, false, Vietnamese),
+        ("EN", "tự học".to_string())
+    );
+    assert_eq!(
+        describe(AppPolicy::Default, false, true, English),
+        ("EN", "mặc định, tương thích".to_string())
+    );
+    assert_eq!(
+        describe(AppPolicy::Default, false, false, KeepCurrent),
+        ("Hiện tại", "mặc định".to_string())
+    );
+    // Ignored apps are left alone even with auto-toggle off
+    assert_eq!(
+        describe_app_mode(AppPolicy::Ignored, false, false, false, KeepCurrent),
+        ("Bỏ qua", "thủ công".to_string())
+    );
+    assert_eq!(
+        describe_app_mode(AppPolicy::Vietnamese, false, false, false, KeepCurrent),
+        ("Hiện tại", "không tự chuyển".to_string())
+    );
+}
+
+#[test]
+fn test_macro_settings_table_updates() {
+    let mut settings = MacroSettings::new();
+    let mut macros = BTreeMap::from([
+        ("hn".to_string(), "Hà Nội".to_string()),
+        ("tp".to_string(), "thành phố".to_string()),
+    ]);
+    settings.sync_table(&macros, &[]);
+    let table = settings.table.clone();
+    // An unchanged table keeps the same list
+    settings.sync_table(&macros, &[]);
+    assert!(Arc::ptr_eq(&table, &settings.table));
+
+    settings.insert_entry("dn".to_string(), "Đà Nẵng".to_string());
+    settings.insert_entry("hn".to_string(), "Hà Nội, Việt Nam".to_string());
+    settings.remove_entry("tp");
+    settings.remove_entry("unknown");
+    let entries = settings
+        .table
+        .iter()
+        .map(|entry| (entry.from.as_str(), entry.to.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(entries, vec![("dn", "Đà Nẵng"), ("hn", "Hà Nội, Việt Nam")]);
+
+    macros.insert("dn".to_string(), "Đà Nẵng".to_string());
+    macros.insert("hn".to_string(), "Hà Nội, Việt Nam".to_string());
+    macros.remove("tp");
+    let table = settings.table.clone();
+    settings.sync_table(&macros, &[]);
+    assert!(Arc::ptr_eq(&table, &settings.table));
+
+    settings.set_template_entry("dn", true);
+    let table = settings.table.clone();
+    settings.sync_table(&macros, &["dn".to_string()]);
+    assert!(Arc::ptr_eq(&table, &settings.table));
+    settings.sync_table(&macros, &[]);
+    assert!(!settings.table[0].is_template);
+}
+
+#[test]
+fn test_format_debug_indicator() {
+    assert_eq!(format_debug_indicator("VN", true, 0), "VN [0]");
+    assert_eq!(format_debug_indicator("gõ", true, 5), "gõ [5]");
+    assert_eq!(format_debug_indicator("VN", false, 12), "VN [12 ✕]");
 }
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::platform::get_home_dir;
+
+// One word per line, words the user wants kept as typed. Opened from the
+// "Nâng cao" window and read again when switching apps.
+const USER_DICTIONARY_FILE: &str = ".goxkey.words";
+const ENGLISH_DICTIONARY_PATH: &str = "/usr/share/dict/words";
+// Shorter English words are too often Vietnamese syllables typed in Telex,
+// like "as" for "á" or "of" for "ò"
+const MIN_ENGLISH_WORD_LENGTH: usize = 4;
+
+static ENGLISH_WORDS: Lazy<WordList> = Lazy::new(|| {
+    WordList::from_file(Path::new(ENGLISH_DICTIONARY_PATH))
+        .filter(|word| word.chars().count() >= MIN_ENGLISH_WORD_LENGTH)
+});
+
+/// Reads the English dictionary ahead of time, so the first word checked
+/// against it doesn't hold up the event tap.
+pub fn preload_english_words() {
+    thread::spawn(|| Lazy::force(&ENGLISH_WORDS));
+}
+
+pub fn get_user_dictionary_path() -> Option<PathBuf> {
+    get_home_dir().map(|home| home.join(USER_DICTIONARY_FILE))
+}
+
+/// Decides whether a finished word stays as it is on screen, or gets
+/// restored to the keys that were typed.
+pub trait WordValidator {
+    /// Some(true) keeps the word, Some(false) restores it, and None leaves
+    /// the decision to the next validator.
+    fn validate(&self, typed: &str, word: &str) -> Option<bool>;
+}
+
+/// Asks each validator in order, the first one with an answer wins. A word
+/// nobody vouches for is not valid.
+pub fn is_valid_word(validators: &[&dyn WordValidator], typed: &str, word: &str) -> bool {
+    validators
+        .iter()
+        .find_map(|validator| validator.validate(typed, word))
+        .unwrap_or(false)
+}
+
+#[derive(Default)]
+pub struct WordList {
+    words: HashSet<String>,
+}
+
+impl WordList {
+    pub fn new<I: IntoIterator<Item = String>>(words: I) -> Self {
+        Self {
+            words: words.into_iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .map(|content| {
+                Self::new(
+                    content
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty())
+                        .map(String::from),
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn load_user_dictionary() -> Self {
+        get_user_dictionary_path()
+            .map(|path| Self::from_file(&path))
+            .unwrap_or_default()
+    }
+
+    fn filter(mut self, predicate: impl Fn(&String) -> bool) -> Self {
+        self.words.retain(predicate);
+        self
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(&word.to_lowercase())
+    }
+}
+
+// The allowed words from the config and the user dictionary keep the
+// word on screen, whatever the other validators think
+impl WordValidator for WordList {
+    fn validate(&self, _typed: &str, word: &str) -> Option<bool> {
+        self.contains(word).then_some(true)
+    }
+}
+
+/// Restores words whose keys spell an English word, like "text" that
+/// would otherwise stay as "tẽt". Off by default, see MIN_ENGLISH_WORD_LENGTH.
+pub struct EnglishDictionary<'a>(pub &'a WordList);
+
+impl EnglishDictionary<'static> {
+    pub fn system() -> Self {
+        Self(&ENGLISH_WORDS)
+    }
+}
+
+impl WordValidator for EnglishDictionary<'_> {
+    fn validate(&self, typed: &str, word: &str) -> Option<bool> {
+        (typed != word && self.0.contains(typed)).then_some(false)
+    }
+}
+
+/// Whether the keys as typed make a word on their own, an English word from
+/// the dictionary or a Vietnamese one. Restoring those is not a typing slip.
+pub fn is_word_as_typed(english_dictionary: Option<&EnglishDictionary>, typed: &str) -> bool {
+    english_dictionary.is_some_and(|dictionary| dictionary.0.contains(typed))
+        || vi::validation::is_valid_word(typed)
+}
+
+pub struct VietnameseValidator;
+
+impl WordValidator for VietnameseValidator {
+    fn validate(&self, _typed: &str, word: &str) -> Option<bool> {
+        Some(vi::validation::is_valid_word(word))
+    }
+}
+
+#[test]
+fn test_validator_precedence() {
+    let allowed_words = WordList::new(["đc".to_string()]);
+    let english_words = WordList::new(["text".to_string()]);
+    let english_dictionary = EnglishDictionary(&english_words);
+    let validators: [&dyn WordValidator; 3] =
+        [&allowed_words, &english_dictionary, &VietnameseValidator];
+    // Allowed words win over the Vietnamese rules
+    assert!(is_valid_word(&validators, "ddc", "đc"));
+    // English words are restored even when they look Vietnamese
+    assert!(!is_valid_word(&validators, "text", "tẽt"));
+    assert!(is_valid_word(&validators, "vieetj", "việt"));
+    assert!(!is_valid_word(&validators, "ffff", "ffff"));
+    assert!(!is_valid_word(&[], "a", "a"));
+}
+
+#[test]
+fn test_word_as_typed() {
+    let english_words = WordList::new(["text".to_string()]);
+    let english_dictionary = EnglishDictionary(&english_words);
+    assert!(is_word_as_typed(Some(&english_dictionary), "text"));
+    assert!(!is_word_as_typed(None, "text"));
+    assert!(is_word_as_typed(None, "ban"));
+    assert!(!is_word_as_typed(Some(&english_dictionary), "ffff"));
+}
+
+#[test]
+fn test_word_list_ignores_case() {
+    let words = WordList::new(["GõKey".to_string()]);
+    assert!(words.contains("gõkey"));
+    assert!(words.contains("GÕKEY"));
+    assert!(!words.contains("gokey"));
+}